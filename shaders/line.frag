@@ -0,0 +1,7 @@
+#version 140
+in vec4 vColor;
+out vec4 f_color;
+
+void main() {
+    f_color = vColor;
+}
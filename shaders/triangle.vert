@@ -0,0 +1,12 @@
+#version 140
+in vec2 position;
+in vec4 color;
+uniform mat3 transform;
+uniform vec3 tint;
+uniform float alpha;
+out vec4 vColor;
+
+void main() {
+    gl_Position = vec4((transform * vec3(position, 1.0)).xy, 0.0, 1.0);
+    vColor = vec4(color.rgb * tint, color.a * alpha);
+}
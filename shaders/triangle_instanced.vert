@@ -0,0 +1,19 @@
+#version 140
+in vec2 position;
+in vec4 color;
+in vec2 instance_position;
+in float instance_angle;
+in float instance_scale;
+in vec3 instance_tint;
+uniform mat3 transform;
+uniform vec3 tint;
+uniform float alpha;
+out vec4 vColor;
+
+void main() {
+    float s = sin(instance_angle);
+    float c = cos(instance_angle);
+    vec2 placed = mat2(c, s, -s, c) * (position * instance_scale) + instance_position;
+    gl_Position = vec4((transform * vec3(placed, 1.0)).xy, 0.0, 1.0);
+    vColor = vec4(color.rgb * tint * instance_tint, color.a * alpha);
+}
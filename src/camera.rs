@@ -0,0 +1,215 @@
+use crate::gfx;
+use crate::gfx::transform::GfxTransform;
+
+// Owns the scene scale, view rotation and origin commands at the top
+// of the command list.  Everything is steered through targets that
+// the camera eases towards a little every tick.
+pub struct Camera {
+    position:        (f32, f32),
+    target:          (f32, f32),
+    scale:           f32,
+    target_scale:    f32,
+    min_scale:       f32,
+    max_scale:       f32,
+    angle:           f32,
+    target_angle:    f32,
+    // how much of the way to the target is covered each tick, 1.0
+    // to snap straight there
+    smoothing:       f32,
+    // in clip space, so it shakes as hard at any zoom
+    shake:           f32,
+    shake_offset:    (f32, f32),
+
+    gfx_scale:       gfx::CommandHandle,
+    gfx_rotation:    gfx::CommandHandle,
+    gfx_origin:      gfx::CommandHandle
+}
+
+const SHAKE_DECAY: f32 = 0.85;
+
+// the short way round from one angle to another
+fn angle_between(from: f32, to: f32) -> f32 {
+    let two_pi = std::f32::consts::PI * 2.0;
+    let mut difference = (to - from) % two_pi;
+    if difference > std::f32::consts::PI {
+        difference -= two_pi;
+    } else if difference < -std::f32::consts::PI {
+        difference += two_pi;
+    }
+    return difference;
+}
+
+impl Camera {
+    pub fn new(gfx: &mut gfx::Gfx, position: (f32, f32), scale: f32) -> Camera {
+        Camera { position:     position,
+                 target:       position,
+                 scale:        scale,
+                 target_scale: scale,
+                 min_scale:    0.0001,
+                 max_scale:    10.0,
+                 angle:        0.0,
+                 target_angle: 0.0,
+                 smoothing:    1.0,
+                 shake:        0.0,
+                 shake_offset: (0.0, 0.0),
+                 gfx_scale:    gfx.scene_scale(scale),
+                 gfx_rotation: gfx.view_rotate(0.0),
+                 gfx_origin:   gfx.origin(position.0, position.1) }
+    }
+
+    pub fn position(&self) -> (f32, f32) {
+        return self.position;
+    }
+
+    pub fn scale(&self) -> f32 {
+        return self.scale;
+    }
+
+    pub fn angle(&self) -> f32 {
+        return self.angle;
+    }
+
+    pub fn set_smoothing(&mut self, smoothing: f32) {
+        self.smoothing = smoothing.clamp(0.0, 1.0);
+    }
+
+    pub fn set_zoom_limits(&mut self, min_scale: f32, max_scale: f32) {
+        self.min_scale    = min_scale;
+        self.max_scale    = max_scale;
+        self.target_scale = self.target_scale.max(min_scale).min(max_scale);
+    }
+
+    pub fn follow(&mut self, target: (f32, f32)) {
+        self.target = target;
+    }
+
+    pub fn zoom_to(&mut self, scale: f32) {
+        self.target_scale = scale.max(self.min_scale).min(self.max_scale);
+    }
+
+    // turn so that down on screen points at center
+    pub fn face_away_from(&mut self, center: (f32, f32)) {
+        self.target_angle = gfx::get_angle(self.target, center);
+    }
+
+    // amount is how far the view jumps, as a fraction of half the
+    // screen height, dying away over the next second or so
+    pub fn shake(&mut self, amount: f32) {
+        self.shake = self.shake.max(amount);
+    }
+
+    pub fn tick(&mut self, gfx: &mut gfx::Gfx) -> Result<(), gfx::GfxError> {
+        let smoothing = self.smoothing;
+        self.position.0 += (self.target.0 - self.position.0) * smoothing;
+        self.position.1 += (self.target.1 - self.position.1) * smoothing;
+        // zoom in ratios rather than steps, so it eases the same at
+        // every scale
+        self.scale *= (self.target_scale / self.scale).powf(smoothing);
+        self.angle += angle_between(self.angle, self.target_angle) * smoothing;
+
+        if self.shake > 0.001 {
+            self.shake_offset = ((rand::random::<f32>()*2.0 - 1.0) * self.shake / self.scale,
+                                 (rand::random::<f32>()*2.0 - 1.0) * self.shake / self.scale);
+            self.shake *= SHAKE_DECAY;
+        } else {
+            self.shake        = 0.0;
+            self.shake_offset = (0.0, 0.0);
+        }
+
+        let origin = gfx::add_points(self.position, self.shake_offset);
+        gfx.change_scene_scale(self.gfx_scale, self.scale)?;
+        gfx.change_view_rotation(self.gfx_rotation, self.angle)?;
+        gfx.change_origin(self.gfx_origin, origin.0, origin.1)?;
+        return Ok(());
+    }
+
+    // world to clip space as of the last tick, for a frame of
+    // width x height pixels
+    pub fn view(&self, width: u32, height: u32) -> GfxTransform {
+        let origin = gfx::add_points(self.position, self.shake_offset);
+        return gfx::view_transform(origin, self.scale, self.angle, height as f32 / width as f32);
+    }
+
+    // pixels from the top left
+    pub fn world_to_screen(&self, point: (f32, f32), width: u32, height: u32) -> (f32, f32) {
+        let clip = self.view(width, height).apply(point);
+        return ((clip.0 + 1.0) * 0.5 * width as f32,
+                (1.0 - clip.1) * 0.5 * height as f32);
+    }
+
+    pub fn screen_to_world(&self, pixel: (f32, f32), width: u32, height: u32) -> (f32, f32) {
+        let clip = (pixel.0 / width as f32 * 2.0 - 1.0,
+                    1.0 - pixel.1 / height as f32 * 2.0);
+        match self.view(width, height).inverse() {
+            Some(inverse) => return inverse.apply(clip),
+            None          => return self.position
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: (f32, f32), b: (f32, f32)) -> bool {
+        return (a.0 - b.0).abs() < 1e-3 && (a.1 - b.1).abs() < 1e-3;
+    }
+
+    #[test]
+    fn view_transform_centres_the_origin() {
+        let view = gfx::view_transform((10.0, 20.0), 0.5, 0.0, 0.75);
+        assert!(close(view.apply((10.0, 20.0)), (0.0, 0.0)));
+        // a unit up is half the screen height at scale 0.5, a unit
+        // across is squeezed by the aspect ratio
+        assert!(close(view.apply((10.0, 21.0)), (0.0, 0.5)));
+        assert!(close(view.apply((11.0, 20.0)), (0.375, 0.0)));
+    }
+
+    #[test]
+    fn view_transform_turns_the_world() {
+        let view = gfx::view_transform((0.0, 0.0), 1.0, std::f32::consts::FRAC_PI_2, 1.0);
+        assert!(close(view.apply((1.0, 0.0)), (0.0, 1.0)));
+    }
+
+    #[test]
+    fn screen_middle_is_the_position() {
+        let mut gfx = gfx::Gfx::new();
+        let camera = Camera::new(&mut gfx, (5.0, -3.0), 0.25);
+        assert!(close(camera.screen_to_world((400.0, 300.0), 800, 600), (5.0, -3.0)));
+        // the top of the screen is 1/scale up
+        assert!(close(camera.screen_to_world((400.0, 0.0), 800, 600), (5.0, 1.0)));
+    }
+
+    #[test]
+    fn screen_to_world_undoes_world_to_screen() {
+        let mut gfx = gfx::Gfx::new();
+        let mut camera = Camera::new(&mut gfx, (0.0, 1000.0), 0.1);
+        camera.follow((30.0, 990.0));
+        camera.face_away_from((0.0, 0.0));
+        camera.tick(&mut gfx).unwrap();
+        for point in [(30.0, 990.0), (35.0, 985.0), (20.0, 1001.0)].iter() {
+            let pixel = camera.world_to_screen(*point, 1024, 768);
+            assert!(close(camera.screen_to_world(pixel, 1024, 768), *point));
+        }
+    }
+
+    #[test]
+    fn tick_eases_towards_the_target() {
+        let mut gfx = gfx::Gfx::new();
+        let mut camera = Camera::new(&mut gfx, (0.0, 0.0), 1.0);
+        camera.set_smoothing(0.5);
+        camera.follow((10.0, 0.0));
+        camera.zoom_to(4.0);
+        camera.tick(&mut gfx).unwrap();
+        assert!(close(camera.position(), (5.0, 0.0)));
+        assert!((camera.scale() - 2.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn angles_go_the_short_way_round() {
+        let pi = std::f32::consts::PI;
+        assert!((angle_between(0.1, -0.1) + 0.2).abs() < 1e-5);
+        assert!((angle_between(pi - 0.1, -pi + 0.1) - 0.2).abs() < 1e-5);
+        assert!((angle_between(-pi + 0.1, pi - 0.1) + 0.2).abs() < 1e-5);
+    }
+}
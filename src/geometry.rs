@@ -0,0 +1,150 @@
+use glium::index::PrimitiveType;
+
+use crate::gfx;
+
+type Color = (f32, f32, f32, f32);
+
+// Shapes on a planet added a piece at a time, every point as (angle,
+// radius) from the middle of it, and handed back at the end as one
+// index buffer for the triangles and one for the lines.  The vertices
+// go into gfx as they're added, for whoever's claiming them.
+pub struct GeometryBuilder<'a> {
+    gfx:       &'a mut gfx::Gfx,
+    triangles: Vec< u32 >,
+    lines:     Vec< u32 >
+}
+
+impl<'a> GeometryBuilder<'a> {
+    pub fn new(gfx: &'a mut gfx::Gfx) -> GeometryBuilder<'a> {
+        GeometryBuilder { gfx:       gfx,
+                          triangles: Vec::new(),
+                          lines:     Vec::new() }
+    }
+
+    fn triangle_vertex(&mut self, point: (f32, f32), color: Color) {
+        self.gfx.add_triangle_vertex(gfx::place(point.0, point.1), color);
+    }
+
+    // corners in strip order, both ends of one side then both of the
+    // other, so 0,1,2 and 1,2,3 are the two triangles
+    pub fn quad(&mut self, corners: [(f32, f32); 4], colors: [Color; 4]) {
+        let first = self.gfx.triangle_len() as u32;
+        for (corner, color) in corners.iter().zip(colors.iter()) {
+            self.triangle_vertex(*corner, *color);
+        }
+        self.triangles.extend_from_slice(&[first, first+1, first+2, first+1, first+2, first+3]);
+    }
+
+    // from angles.0 to angles.1 and radii.0 to radii.1, straight across
+    // top and bottom, colors in the same order as quad()
+    pub fn curved_rect(&mut self, angles: (f32, f32), radii: (f32, f32), colors: [Color; 4]) {
+        self.quad([(angles.0, radii.0),
+                   (angles.0, radii.1),
+                   (angles.1, radii.0),
+                   (angles.1, radii.1)], colors);
+    }
+
+    // arc_length round from start_angle in steps pieces, so it follows
+    // the curve of the ground, faded from colors.0 at radii.0 to
+    // colors.1 at radii.1
+    pub fn arc_band(&mut self,
+                    start_angle: f32,
+                    arc_length: f32,
+                    radii: (f32, f32),
+                    steps: u32,
+                    colors: (Color, Color)) {
+        let step_width = arc_length / (steps as f32);
+        let first = self.gfx.triangle_len() as u32;
+        self.triangle_vertex((start_angle, radii.0), colors.0);
+        self.triangle_vertex((start_angle, radii.1), colors.1);
+        for i in 1..(steps+1) {
+            self.triangle_vertex((start_angle+(step_width*(i as f32)), radii.0), colors.0);
+            self.triangle_vertex((start_angle+(step_width*(i as f32)), radii.1), colors.1);
+            let corner = first + (i-1)*2;
+            self.triangles.extend_from_slice(&[corner, corner+1, corner+2, corner+1, corner+2, corner+3]);
+        }
+    }
+
+    // a convex shape, fanned out from the first point
+    pub fn polygon(&mut self, points: &[(f32, f32)], color: Color) {
+        if points.len() < 3 {
+            return;
+        }
+        let first = self.gfx.triangle_len() as u32;
+        for point in points.iter() {
+            self.triangle_vertex(*point, color);
+        }
+        for i in 1..(points.len() as u32 - 1) {
+            self.triangles.extend_from_slice(&[first, first+i, first+i+1]);
+        }
+    }
+
+    // lines all the way round through points and back to the first
+    pub fn outline(&mut self, points: &[(f32, f32)]) {
+        let first = self.gfx.line_len() as u32;
+        for point in points.iter() {
+            self.gfx.add_line_vertex(gfx::place(point.0, point.1));
+        }
+        let n = points.len() as u32;
+        for i in 0..n {
+            self.lines.push(first + i);
+            self.lines.push(first + (i+1) % n);
+        }
+    }
+
+    // the triangles as a TrianglesList and the lines as a LinesList.
+    // They can't be one buffer, the two index different vertex arrays
+    // and go to different programs, so each gets its own draw.
+    pub fn finish(self) -> (gfx::IndexHandle, gfx::IndexHandle) {
+        return ( self.gfx.add_indices(&self.triangles, PrimitiveType::TrianglesList),
+                 self.gfx.add_indices(&self.lines, PrimitiveType::LinesList) );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Color = (1.0, 0.0, 0.0, 1.0);
+    const BLUE: Color = (0.0, 0.0, 1.0, 1.0);
+
+    #[test]
+    fn arc_band_shares_edges() {
+        let mut gfx = gfx::Gfx::new();
+        gfx.add_triangle_vertex((0.0, 0.0), RED);
+        let mut geometry = GeometryBuilder::new(&mut gfx);
+        geometry.arc_band(0.0, 0.1, (10.0, 12.0), 3, (RED, BLUE));
+        assert_eq!(geometry.triangles, vec![1, 2, 3, 2, 3, 4,
+                                            3, 4, 5, 4, 5, 6,
+                                            5, 6, 7, 6, 7, 8]);
+        assert!(geometry.lines.is_empty());
+        drop(geometry);
+        assert_eq!(gfx.triangle_len(), 9);
+    }
+
+    #[test]
+    fn polygon_fans_from_the_first_point() {
+        let mut gfx = gfx::Gfx::new();
+        let mut geometry = GeometryBuilder::new(&mut gfx);
+        geometry.polygon(&[(0.0, 10.0), (0.1, 10.0), (0.1, 11.0), (0.0, 11.0), (0.05, 12.0)], RED);
+        assert_eq!(geometry.triangles, vec![0, 1, 2, 0, 2, 3, 0, 3, 4]);
+
+        // too few points to have an inside
+        geometry.polygon(&[(0.0, 10.0), (0.1, 10.0)], RED);
+        assert_eq!(geometry.triangles.len(), 9);
+        drop(geometry);
+        assert_eq!(gfx.triangle_len(), 5);
+    }
+
+    #[test]
+    fn outline_closes_the_loop() {
+        let mut gfx = gfx::Gfx::new();
+        gfx.add_line_vertex((0.0, 0.0));
+        let mut geometry = GeometryBuilder::new(&mut gfx);
+        geometry.outline(&[(0.0, 10.0), (0.1, 10.0), (0.1, 11.0)]);
+        assert_eq!(geometry.lines, vec![1, 2, 2, 3, 3, 1]);
+        assert!(geometry.triangles.is_empty());
+        drop(geometry);
+        assert_eq!(gfx.line_len(), 4);
+    }
+}
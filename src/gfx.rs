@@ -1,11 +1,34 @@
-#[allow(unused_imports)]
-use glium::{glutin, Surface};
 use glium::index::PrimitiveType;
 
-use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+
+use self::image::Image;
+use self::instances::GfxInstance;
+use self::shaders::GfxProgramError;
+use self::state::{GfxBlend, GfxDrawState, GfxLineStyle, GfxRect, GfxStencil};
+use self::stats::GfxFrameStats;
+use self::transform::GfxTransform;
+use self::viewport::GfxViewport;
+
+pub mod dump;
+pub mod glium_backend;
+pub mod image;
+pub mod instances;
+pub mod lines;
+pub mod recording;
+pub mod shaders;
+pub mod software;
+pub mod spans;
+pub mod state;
+pub mod stats;
+pub mod transform;
+pub mod viewport;
 
 // GFX constants
 const GFX_SKIP: u32 = 1;
+// freed, waiting for compact() to take it out of the list
+const GFX_FREE: u32 = 2;
 
 pub fn place (angle: f32, 
               distance: f32) -> (f32, f32) {
@@ -38,14 +61,124 @@ pub fn get_angle(c1: (f32, f32),
                       c1.1 - c2.1);
 }
 
+// world to clip space for what Origin, SceneScale and ViewRotate set,
+// aspect_ratio being height/width
+pub fn view_transform(origin: (f32, f32),
+                      scene_scale: f32,
+                      rotation: f32,
+                      aspect_ratio: f32) -> GfxTransform {
+    return GfxTransform::scale(scene_scale*aspect_ratio, scene_scale)
+               .multiply(&GfxTransform::rotation(rotation))
+               .multiply(&GfxTransform::translation(-origin.0, -origin.1));
+}
+
+// handles into the different Gfx tables, so a command id can't be
+// passed where an index buffer is wanted
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CommandHandle(usize);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ProgramHandle(usize);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct IndexHandle(usize);
+
+// a range of line or triangle vertices, which moves when compact()
+// squeezes out the freed ones
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct VertexHandle(usize);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct InstanceHandle(usize);
+
+impl CommandHandle {
+    pub fn id(&self) -> usize {
+        return self.0;
+    }
+}
+
+impl ProgramHandle {
+    pub fn id(&self) -> usize {
+        return self.0;
+    }
+}
+
+impl IndexHandle {
+    pub fn id(&self) -> usize {
+        return self.0;
+    }
+}
+
+impl VertexHandle {
+    pub fn id(&self) -> usize {
+        return self.0;
+    }
+}
+
+impl InstanceHandle {
+    pub fn id(&self) -> usize {
+        return self.0;
+    }
+}
+
+#[derive(Debug)]
+pub enum GfxError {
+    NoSuchCommand(CommandHandle),
+    NoSuchProgram(ProgramHandle),
+    NoSuchIndices(IndexHandle),
+    NoSuchVertex(usize),
+    NoSuchVertices(VertexHandle),
+    NoSuchInstances(InstanceHandle),
+    WrongCommand {
+        handle:   CommandHandle,
+        expected: &'static str
+    },
+    UnboundDraw(CommandHandle),
+    UnbalancedTransform(CommandHandle),
+    InstancedLines(CommandHandle),
+    OffscreenSize(u32, u32),
+    NoSuchViewport(String)
+}
+
+impl fmt::Display for GfxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GfxError::NoSuchCommand(handle) => write!(f, "no command {0}", handle.0),
+            GfxError::NoSuchProgram(handle) => write!(f, "no program {0}", handle.0),
+            GfxError::NoSuchIndices(handle) => write!(f, "no index buffer {0}", handle.0),
+            GfxError::NoSuchVertex(index)   => write!(f, "no vertex {0}", index),
+            GfxError::NoSuchVertices(handle) => write!(f, "no vertex range {0}", handle.0),
+            GfxError::NoSuchInstances(handle) => write!(f, "no instance buffer {0}", handle.0),
+            GfxError::WrongCommand { handle, expected } => {
+                write!(f, "command {0} is not a {1} command", handle.0, expected)
+            },
+            GfxError::UnboundDraw(handle) => {
+                write!(f, "command {0} draws without a program and indices set", handle.0)
+            },
+            GfxError::UnbalancedTransform(handle) => {
+                write!(f, "command {0} pops a transform that was never pushed", handle.0)
+            },
+            GfxError::InstancedLines(handle) => {
+                write!(f, "command {0} draws instances of lines, only triangles are instanced", handle.0)
+            },
+            GfxError::OffscreenSize(width, height) => {
+                write!(f, "can't draw offscreen at {0}x{1}, it goes up to {2}x{2}", width, height, MAX_OFFSCREEN_SIZE)
+            },
+            GfxError::NoSuchViewport(name) => write!(f, "no viewport {0}", name)
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 enum GfxCommandTypes {
     LineDraw,
     TriangleDraw,
+    InstancedDraw(InstanceHandle),
     NoOp,
-    Program(usize),
-    Indices(usize),
+    Program(ProgramHandle),
+    Indices(IndexHandle),
     Rotate(f32),
+    ViewRotate(f32),
     SceneScale(f32),
     ObjectScale(f32),
     Translate {
@@ -55,276 +188,1205 @@ enum GfxCommandTypes {
     Origin {
         x: f32,
         y: f32
+    },
+    Scale {
+        x: f32,
+        y: f32
+    },
+    Shear {
+        x: f32,
+        y: f32
+    },
+    Matrix(GfxTransform),
+    PushTransform,
+    PopTransform,
+    Blend(GfxBlend),
+    LineWidth(f32),
+    LineStyle(Option<GfxLineStyle>),
+    Scissor(Option<GfxRect>),
+    Stencil(GfxStencil),
+    Layer(i32),
+    Tint([f32; 3]),
+    Alpha(f32)
+}
+
+// what Translate/Rotate/Shear/ObjectScale/Scale/Matrix set up, applied 
+// scale first and the Matrix last.  PushTransform makes the combined
+// transform the parent of everything up to the matching PopTransform.
+#[derive(Copy, Clone)]
+struct GfxLocalTransform {
+    translation: [f32; 2],
+    angle:       f32,
+    scale:       [f32; 2],
+    shear:       [f32; 2],
+    matrix:      GfxTransform
+}
+
+impl GfxLocalTransform {
+    fn identity() -> GfxLocalTransform {
+        GfxLocalTransform { translation: [0.0, 0.0],
+                            angle:       0.0,
+                            scale:       [1.0, 1.0],
+                            shear:       [0.0, 0.0],
+                            matrix:      GfxTransform::identity() }
     }
+
+    fn to_transform(&self) -> GfxTransform {
+        return self.matrix.multiply(&GfxTransform::translation(self.translation[0], self.translation[1]))
+                          .multiply(&GfxTransform::rotation(self.angle))
+                          .multiply(&GfxTransform::shear(self.shear[0], self.shear[1]))
+                          .multiply(&GfxTransform::scale(self.scale[0], self.scale[1]));
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct GfxLineVertex {
+    pub position: [f32; 2]
 }
 
 #[derive(Copy, Clone)]
-struct GfxLineVertex {
-    position: [f32; 2]
+pub struct GfxTriangleVertex {
+    pub position: [f32; 2],
+    pub color:    [f32; 4]
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GfxDrawType {
+    Lines,
+    Triangles
+}
+
+// everything the line/triangle programs need for one draw.  The
+// transform goes all the way from vertex positions to clip space,
+// vertex colours are multiplied by tint and their alpha by alpha.
+#[derive(Copy, Clone, Debug)]
+pub struct GfxUniforms {
+    pub transform: GfxTransform,
+    pub tint:      [f32; 3],
+    pub alpha:     f32
+}
+
+pub struct GfxProgram {
+    pub vert_shader: String,
+    pub frag_shader: String
+}
+
+// a circle around everything an index buffer draws, in vertex space
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GfxBounds {
+    pub center: [f32; 2],
+    pub radius: f32
+}
+
+impl GfxBounds {
+    // around the box of the points, not the smallest circle but close
+    pub fn around<I: Iterator<Item = [f32; 2]>>(points: I) -> Option<GfxBounds> {
+        let mut min = [f32::MAX, f32::MAX];
+        let mut max = [f32::MIN, f32::MIN];
+        let mut any = false;
+        for point in points {
+            min = [min[0].min(point[0]), min[1].min(point[1])];
+            max = [max[0].max(point[0]), max[1].max(point[1])];
+            any = true;
+        }
+        if !any {
+            return None;
+        }
+        let center = [(min[0] + max[0]) * 0.5, (min[1] + max[1]) * 0.5];
+        let radius = ((max[0] - center[0]).powi(2) + (max[1] - center[1]).powi(2)).sqrt();
+        return Some(GfxBounds { center: center, radius: radius });
+    }
+
+    // whether any of the circle lands in clip space, give or take
+    // margin.  The circle comes out as an ellipse, its extents along x
+    // and y are the radius times the length of each row of the matrix.
+    pub fn visible(&self, transform: &GfxTransform, margin: (f32, f32)) -> bool {
+        let m = &transform.m;
+        let (x, y)   = transform.apply((self.center[0], self.center[1]));
+        let extent_x = self.radius * (m[0]*m[0] + m[2]*m[2]).sqrt() + margin.0;
+        let extent_y = self.radius * (m[1]*m[1] + m[3]*m[3]).sqrt() + margin.1;
+        return x - extent_x <= 1.0 && x + extent_x >= -1.0 &&
+               y - extent_y <= 1.0 && y + extent_y >= -1.0;
+    }
+}
+
+#[derive(Clone)]
+pub struct GfxIndices {
+    pub data:           Vec< u32 >,
+    pub primitive_type: PrimitiveType,
+    // worked out from the vertices when first needed, unless fixed
+    // with set_bounds.  span is the lowest vertex to one past the
+    // highest, to tell which buffers a changed vertex affects.
+    bounds:             Option<GfxBounds>,
+    fixed_bounds:       bool,
+    span:               Range<u32>
+}
+
+impl GfxIndices {
+    pub fn new(data: Vec< u32 >, primitive_type: PrimitiveType) -> GfxIndices {
+        let mut indices = GfxIndices { data:           data,
+                                       primitive_type: primitive_type,
+                                       bounds:         None,
+                                       fixed_bounds:   false,
+                                       span:           0..0 };
+        indices.update_span();
+        return indices;
+    }
+
+    fn update_span(&mut self) {
+        self.span = match (self.data.iter().min(), self.data.iter().max()) {
+            // u32::MAX can't be a vertex anyone has, it just mustn't overflow
+            (Some(min), Some(max)) => *min..max.saturating_add(1),
+            _                      => 0..0
+        };
+    }
+
+    // which backing array the indices point into
+    pub fn draw_type(&self) -> GfxDrawType {
+        match self.primitive_type {
+            PrimitiveType::LinesList | 
+            PrimitiveType::LineStrip | 
+            PrimitiveType::LineLoop     => GfxDrawType::Lines,
+            _                           => GfxDrawType::Triangles
+        }
+    }
+}
+
+// widest and highest set_offscreen goes, which 8k fits and most gpus
+// can do
+pub const MAX_OFFSCREEN_SIZE: u32 = 8192;
+
+pub fn check_offscreen_size(width: u32, height: u32) -> Result<(), GfxError> {
+    if width == 0 || height == 0 || width > MAX_OFFSCREEN_SIZE || height > MAX_OFFSCREEN_SIZE {
+        return Err(GfxError::OffscreenSize(width, height));
+    }
+    return Ok(());
+}
+
+#[derive(Clone)]
+struct GfxVertexRange {
+    draw_type: GfxDrawType,
+    range:     Range<usize>
 }
 
+// Gfx keeps every resource on the cpu side and hands it to a backend
+// the first time it's needed.  Resource ids are the Gfx ids, so a
+// backend can just keep them in a Vec.  An index or instance buffer id
+// can be loaded again after it's freed, or after compaction renumbers the
+// vertices it points at, and a program after it's replaced.  A
+// program that doesn't build leaves the old one in place, if any,
+// and draws with no program are dropped.
+pub trait RenderBackend {
+    fn load_program(&mut self, id: usize, program: &GfxProgram) -> Result<(), GfxProgramError>;
+    fn load_indices(&mut self, id: usize, indices: &GfxIndices);
+    fn free_indices(&mut self, id: usize);
+    fn load_instances(&mut self, id: usize, instances: &[GfxInstance]);
+    fn free_instances(&mut self, id: usize);
+    // vertices is the whole backing array, only the dirty range has
+    // changed since the last call
+    fn load_line_vertices(&mut self, 
+                          vertices: &[GfxLineVertex], 
+                          dirty: Range<usize>);
+    fn load_triangle_vertices(&mut self, 
+                              vertices: &[GfxTriangleVertex], 
+                              dirty: Range<usize>);
+
+    // frames from here on go into an image of this size rather than
+    // the window, until it's set back to None.  OffscreenSize if it's
+    // too big, or nothing across, in which case nothing changes.
+    fn set_offscreen(&mut self, size: Option<(u32, u32)>) -> Result<(), GfxError>;
+    fn begin_frame(&mut self) -> (u32, u32);
+    fn draw(&mut self, 
+            draw_type: GfxDrawType, 
+            program: usize, 
+            indices: usize, 
+            uniforms: &GfxUniforms,
+            state: &GfxDrawState);
+    // once per instance, each one's transform applied before
+    // uniforms.transform and its tint multiplied into uniforms.tint
+    fn draw_instanced(&mut self, 
+                      draw_type: GfxDrawType, 
+                      program: usize, 
+                      indices: usize, 
+                      instances: usize,
+                      uniforms: &GfxUniforms,
+                      state: &GfxDrawState);
+    // a line draw of indices with a line style, already made into a
+    // triangle list in the same space as the line vertices, for the
+    // line program to draw the way it would the lines
+    fn draw_thick_lines(&mut self,
+                        program: usize,
+                        indices: usize,
+                        triangles: &[GfxLineVertex],
+                        uniforms: &GfxUniforms,
+                        state: &GfxDrawState);
+    fn end_frame(&mut self);
+    // the last frame that was ended, None if there's nothing to read
+    fn read_frame(&mut self) -> Option<Image>;
+}
+
+// a draw that made it past culling, waiting for the rest of its
+// viewport so it can go out in layer order
+struct GfxQueuedDraw {
+    draw_type: GfxDrawType,
+    program:   usize,
+    indices:   usize,
+    instances: Option<usize>,
+    // what a line draw with a line style came out as, in thick_lines
+    thick:     Option<usize>,
+    uniforms:  GfxUniforms,
+    state:     GfxDrawState
+}
+
+// where a command is: in commands, or in inserting until it's put in
+// place in one go
 #[derive(Copy, Clone)]
-struct GfxTriangleVertex {
-    position: [f32; 2],
-    color:    [f32; 4]
+enum GfxSlot {
+    Listed(usize),
+    Inserted(usize)
 }
 
 #[derive(Copy, Clone)]
 struct GfxCommand {
     flags: u32,
+    slot:  usize,
     command: GfxCommandTypes
 }
 
+// CommandHandles and VertexHandles go through a slot table so the
+// commands and vertices can move underneath them.  Freed slots and
+// index buffer ids get handed out again, so a handle is only good
+// until it's freed.
 pub struct Gfx {
     commands:          Vec< GfxCommand >,
-    programs:          Vec< glium::Program >,
-    indices:           Vec< glium::IndexBuffer<u32> >,
+    command_slots:     Vec< Option<GfxSlot> >,
+    free_command_slots: Vec< usize >,
+    insert_at:         Option<CommandHandle>,
+    // pushed since insert_before(), all to go in front of insert_at
+    inserting:         Vec< GfxCommand >,
+    programs:          Vec< GfxProgram >,
+    indices:           Vec< Option<GfxIndices> >,
+    free_index_ids:    Vec< usize >,
+    indices_pending:   Vec< usize >,
+    instances:         Vec< Option<Vec< GfxInstance >> >,
+    free_instance_ids: Vec< usize >,
+    instances_pending: Vec< usize >,
     line_backing:      Vec< GfxLineVertex >,
     triangle_backing:  Vec< GfxTriangleVertex >,
-    line_vertices:     Option<glium::VertexBuffer<GfxLineVertex>>,
-    triangle_vertices: Option<glium::VertexBuffer<GfxTriangleVertex>>,
-    backing_changed:   bool
+    vertex_ranges:     Vec< Option<GfxVertexRange> >,
+    free_vertex_slots: Vec< usize >,
+    line_free:         Vec< Range<usize> >,
+    triangle_free:     Vec< Range<usize> >,
+    programs_pending:  Vec< usize >,
+    program_errors:    Vec< GfxProgramError >,
+    line_dirty:        Vec< Range<usize> >,
+    triangle_dirty:    Vec< Range<usize> >,
+    // line draws with a line style as they were last expanded
+    thick_lines:       Vec< lines::ThickLines >,
+    // which index buffers use which vertices, worked out again the
+    // first time it's needed after the buffers change
+    line_spans:        Option<spans::GfxSpans>,
+    triangle_spans:    Option<spans::GfxSpans>,
+    stats:             GfxFrameStats,
+    viewports:         Vec< GfxViewport >
 }
 
         
 impl GfxCommand {
     fn noop() -> GfxCommand {
         GfxCommand { flags: 0,
+                     slot:  0,
                      command: GfxCommandTypes::NoOp } 
     }
 
-    fn print(&mut self) {
+    fn describe(&self) -> String {
         match self.command {
             GfxCommandTypes::LineDraw => {
-                println!("line draw");
+                return "line draw".to_string();
             },
             GfxCommandTypes::TriangleDraw => {
-                println!("triangle draw");
+                return "triangle draw".to_string();
+            },
+            GfxCommandTypes::InstancedDraw(index) => {
+                return format!("instanced draw {0}", index.0);
             },
             GfxCommandTypes::NoOp               => { 
-                println!("no op");
+                return "no op".to_string();
             },
             GfxCommandTypes::Indices(index)     =>  { 
-                println!("indices {0}", index);
+                return format!("indices {0}", index.0);
             },
             GfxCommandTypes::Program(index)     =>  { 
-                println!("program {0}", index);
+                return format!("program {0}", index.0);
             },
             GfxCommandTypes::Rotate(angle)      => { 
-                println!("rotate {0}", angle);
+                return format!("rotate {0}", angle);
+            },
+            GfxCommandTypes::ViewRotate(angle)  => { 
+                return format!("view rotate {0}", angle);
             },
             GfxCommandTypes::SceneScale(scale)       => { 
-                println!("scene scale {0}", scale);
+                return format!("scene scale {0}", scale);
             },
             GfxCommandTypes::ObjectScale(scale)       => { 
-                println!("object scale {0}", scale);
+                return format!("object scale {0}", scale);
             },
             GfxCommandTypes::Translate { x, y } =>  { 
-                println!("translate {0} {1}", x, y);
+                return format!("translate {0} {1}", x, y);
             },
             GfxCommandTypes::Origin { x, y }    =>  { 
-                println!("no op {0} {1}", x, y);
+                return format!("origin {0} {1}", x, y);
+            },
+            GfxCommandTypes::Scale { x, y }     =>  { 
+                return format!("scale {0} {1}", x, y);
+            },
+            GfxCommandTypes::Shear { x, y }     =>  { 
+                return format!("shear {0} {1}", x, y);
+            },
+            GfxCommandTypes::Matrix(matrix)     =>  { 
+                return format!("matrix {0:?}", matrix.m);
+            },
+            GfxCommandTypes::PushTransform      =>  { 
+                return "push transform".to_string();
+            },
+            GfxCommandTypes::PopTransform       =>  { 
+                return "pop transform".to_string();
+            },
+            GfxCommandTypes::Blend(blend)       =>  { 
+                return format!("blend {0:?}", blend);
+            },
+            GfxCommandTypes::LineWidth(width)   =>  { 
+                return format!("line width {0}", width);
+            },
+            GfxCommandTypes::LineStyle(None)    =>  { 
+                return "line style off".to_string();
+            },
+            GfxCommandTypes::LineStyle(Some(style)) => { 
+                return format!("line style {0} {1:?} {2:?} {3:?}", style.width, style.units, style.join, style.cap);
+            },
+            GfxCommandTypes::Scissor(None)      =>  { 
+                return "scissor off".to_string();
+            },
+            GfxCommandTypes::Scissor(Some(rect)) => { 
+                return format!("scissor {0} {1} {2} {3}", rect.left, rect.bottom, rect.width, rect.height);
+            },
+            GfxCommandTypes::Stencil(stencil)   =>  { 
+                return format!("stencil {0:?}", stencil);
+            },
+            GfxCommandTypes::Layer(layer)       =>  { 
+                return format!("layer {0}", layer);
+            },
+            GfxCommandTypes::Tint(tint)         =>  { 
+                return format!("tint {0} {1} {2}", tint[0], tint[1], tint[2]);
+            },
+            GfxCommandTypes::Alpha(alpha)       =>  { 
+                return format!("alpha {0}", alpha);
             }
         }
     }
-
-
-
 }
 
 impl Gfx {
     pub fn new() -> Gfx {
-        let line_vertices = None;
-        let triangle_vertices = None;
         let programs = Vec::new();
         let indices  = Vec::new();
         let line_backing = Vec::new();
         let triangle_backing = Vec::new();
         let commands = Vec::new();
 
-        Gfx { line_vertices:     line_vertices,
-              triangle_vertices: triangle_vertices,
-              programs:          programs,
+        Gfx { programs:          programs,
               indices:           indices,
+              free_index_ids:    Vec::new(),
+              indices_pending:   Vec::new(),
+              instances:         Vec::new(),
+              free_instance_ids: Vec::new(),
+              instances_pending: Vec::new(),
               line_backing:      line_backing,
               triangle_backing:  triangle_backing,
-              backing_changed:   false,
+              vertex_ranges:     Vec::new(),
+              free_vertex_slots: Vec::new(),
+              line_free:         Vec::new(),
+              triangle_free:     Vec::new(),
+              programs_pending:  Vec::new(),
+              program_errors:    Vec::new(),
+              line_dirty:        Vec::new(),
+              triangle_dirty:    Vec::new(),
+              thick_lines:       Vec::new(),
+              line_spans:        None,
+              triangle_spans:    None,
+              stats:             GfxFrameStats::new(),
+              viewports:         vec![GfxViewport::new("main", GfxRect::full())],
+              command_slots:     Vec::new(),
+              free_command_slots: Vec::new(),
+              insert_at:         None,
+              inserting:         Vec::new(),
               commands:          commands }
 
     }
 
-    pub fn program(&mut self, program: usize) -> usize {
-        self.commands.push(GfxCommand { flags:0, command:GfxCommandTypes::Program ( program )});
-        return self.commands.len() - 1;
+    fn push(&mut self, command: GfxCommandTypes) -> CommandHandle {
+        let slot = match self.free_command_slots.pop() {
+            Some(slot) => slot,
+            None       => {
+                self.command_slots.push(None);
+                self.command_slots.len() - 1
+            }
+        };
+        let command = GfxCommand { flags:0, slot:slot, command:command };
+        // inserted ones are kept aside and put in place together, so
+        // what's after them only moves once however many there are
+        if self.gap().is_some() {
+            self.command_slots[slot] = Some(GfxSlot::Inserted(self.inserting.len()));
+            self.inserting.push(command);
+        } else {
+            self.command_slots[slot] = Some(GfxSlot::Listed(self.commands.len()));
+            self.commands.push(command);
+        }
+        return CommandHandle(slot);
+    }
+
+    // where in commands the ones being inserted will go
+    fn gap(&self) -> Option<usize> {
+        match self.insert_at.and_then(|handle| self.command_slots.get(handle.0)) {
+            Some(Some(GfxSlot::Listed(position))) => return Some(*position),
+            _                                     => return None
+        }
+    }
+
+    // puts the inserted commands in place
+    fn settle(&mut self) {
+        if self.inserting.is_empty() {
+            return;
+        }
+        let gap = self.gap().unwrap_or(self.commands.len());
+        let inserting = std::mem::take(&mut self.inserting);
+        self.commands.splice(gap..gap, inserting);
+        for (i, command) in self.commands.iter().enumerate().skip(gap) {
+            if command.flags & GFX_FREE == 0 {
+                self.command_slots[command.slot] = Some(GfxSlot::Listed(i));
+            }
+        }
+    }
+
+    // where it is in list order, counting the ones being inserted as
+    // if they'd been put in place
+    fn position(&self, handle: CommandHandle) -> Result<usize, GfxError> {
+        let slot = match self.command_slots.get(handle.0) {
+            Some(Some(slot)) => *slot,
+            _                => return Err(GfxError::NoSuchCommand(handle))
+        };
+        match (slot, self.gap()) {
+            (GfxSlot::Listed(position), Some(gap)) if position >= gap => return Ok(position + self.inserting.len()),
+            (GfxSlot::Listed(position), _)                           => return Ok(position),
+            (GfxSlot::Inserted(i), gap)                              => return Ok(gap.unwrap_or(self.commands.len()) + i)
+        }
+    }
+
+    // every command in list order, the same order position() counts in
+    fn ordered(&self) -> impl Iterator<Item = &GfxCommand> {
+        let gap = self.gap().unwrap_or(self.commands.len());
+        return self.commands[..gap].iter()
+                   .chain(self.inserting.iter())
+                   .chain(self.commands[gap..].iter());
+    }
+
+    fn command_mut(&mut self, handle: CommandHandle) -> Result<&mut GfxCommand, GfxError> {
+        match self.command_slots.get(handle.0) {
+            Some(Some(GfxSlot::Listed(position))) => return Ok(&mut self.commands[*position]),
+            Some(Some(GfxSlot::Inserted(i)))      => return Ok(&mut self.inserting[*i]),
+            _                                     => return Err(GfxError::NoSuchCommand(handle))
+        }
+    }
+
+    // new commands go in front of this one instead of on the end,
+    // until append() is called.  If it gets freed in the meantime
+    // they go on the end again.
+    pub fn insert_before(&mut self, handle: CommandHandle) -> Result<(), GfxError> {
+        self.position(handle)?;
+        self.settle();
+        self.insert_at = Some(handle);
+        return Ok(());
+    }
+
+    pub fn append(&mut self) {
+        self.settle();
+        self.insert_at = None;
+    }
+
+    // frees first, last and everything between them.  They stay in
+    // the list, not drawn, until compact().
+    pub fn free_commands(&mut self, first: CommandHandle, last: CommandHandle) -> Result<(), GfxError> {
+        self.position(first)?;
+        self.position(last)?;
+        self.settle();
+        let first = self.position(first)?;
+        let last  = self.position(last)?;
+        for position in first.min(last)..(first.max(last) + 1) {
+            let command = &mut self.commands[position];
+            if command.flags & GFX_FREE == 0 {
+                command.flags |= GFX_FREE;
+                self.command_slots[command.slot] = None;
+                self.free_command_slots.push(command.slot);
+                if self.insert_at == Some(CommandHandle(command.slot)) {
+                    self.insert_at = None;
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    // change_* only ever changes the values of a command, never what
+    // kind of command it is
+    fn change(&mut self, 
+              handle: CommandHandle, 
+              command: GfxCommandTypes) -> Result<(), GfxError> {
+        let existing = self.command_mut(handle)?;
+        if std::mem::discriminant(&existing.command) != std::mem::discriminant(&command) {
+            let expected = match command {
+                GfxCommandTypes::Rotate(_)         => "rotate",
+                GfxCommandTypes::ViewRotate(_)     => "view rotate",
+                GfxCommandTypes::SceneScale(_)     => "scene scale",
+                GfxCommandTypes::ObjectScale(_)    => "object scale",
+                GfxCommandTypes::Translate { .. }  => "translate",
+                GfxCommandTypes::Origin { .. }     => "origin",
+                GfxCommandTypes::Scale { .. }      => "scale",
+                GfxCommandTypes::Shear { .. }      => "shear",
+                GfxCommandTypes::Matrix(_)         => "matrix",
+                GfxCommandTypes::Blend(_)          => "blend",
+                GfxCommandTypes::LineWidth(_)      => "line width",
+                GfxCommandTypes::LineStyle(_)      => "line style",
+                GfxCommandTypes::Scissor(_)        => "scissor",
+                GfxCommandTypes::Stencil(_)        => "stencil",
+                GfxCommandTypes::Layer(_)          => "layer",
+                GfxCommandTypes::Tint(_)           => "tint",
+                GfxCommandTypes::Alpha(_)          => "alpha",
+                _                                  => "matching"
+            };
+            return Err(GfxError::WrongCommand { handle: handle, expected: expected });
+        }
+        existing.command = command;
+        return Ok(());
+    }
+
+    pub fn program(&mut self, program: ProgramHandle) -> Result<CommandHandle, GfxError> {
+        if program.0 >= self.programs.len() {
+            return Err(GfxError::NoSuchProgram(program));
+        }
+        return Ok(self.push(GfxCommandTypes::Program ( program )));
     }
 
-    pub fn skip(&mut self, id: usize) {
-        self.commands[id].flags |= GFX_SKIP;
+    pub fn skip(&mut self, handle: CommandHandle) -> Result<(), GfxError> {
+        self.command_mut(handle)?.flags |= GFX_SKIP;
+        return Ok(());
     }
     
-    pub fn unskip(&mut self, id: usize) {
-        self.commands[id].flags &= !GFX_SKIP;
+    pub fn unskip(&mut self, handle: CommandHandle) -> Result<(), GfxError> {
+        self.command_mut(handle)?.flags &= !GFX_SKIP;
+        return Ok(());
+    }
+
+    pub fn rotate(&mut self, angle: f32) -> CommandHandle {
+        return self.push(GfxCommandTypes::Rotate ( angle ));
     }
 
-    pub fn rotate(&mut self, angle: f32) -> usize {
-        self.commands.push(GfxCommand { flags:0, command:GfxCommandTypes::Rotate ( angle )});
-        return self.commands.len() - 1;
+    pub fn change_rotation(&mut self, handle: CommandHandle, angle: f32) -> Result<(), GfxError> {
+        return self.change(handle, GfxCommandTypes::Rotate ( angle ));
     }
 
-    pub fn change_rotation(&mut self, id: usize, angle: f32) {
-        self.commands[id].command = GfxCommandTypes::Rotate ( angle );
+    // turns the whole world anticlockwise on screen, so whatever is
+    // at angle (the way place() and get_angle() measure it) from the
+    // origin ends up straight above it
+    pub fn view_rotate(&mut self, angle: f32) -> CommandHandle {
+        return self.push(GfxCommandTypes::ViewRotate ( angle ));
     }
 
-    pub fn scene_scale(&mut self, scale: f32) -> usize {
-        self.commands.push(GfxCommand { flags:0, command:GfxCommandTypes::SceneScale ( scale )});
-        return self.commands.len() - 1;
+    pub fn change_view_rotation(&mut self, handle: CommandHandle, angle: f32) -> Result<(), GfxError> {
+        return self.change(handle, GfxCommandTypes::ViewRotate ( angle ));
+    }
+
+    pub fn scene_scale(&mut self, scale: f32) -> CommandHandle {
+        return self.push(GfxCommandTypes::SceneScale ( scale ));
     }
     
-    pub fn object_scale(&mut self, scale: f32) -> usize {
-        self.commands.push(GfxCommand { flags:0, command:GfxCommandTypes::ObjectScale ( scale )});
-        return self.commands.len() - 1;
+    pub fn object_scale(&mut self, scale: f32) -> CommandHandle {
+        return self.push(GfxCommandTypes::ObjectScale ( scale ));
     }
     
-    pub fn change_scene_scale(&mut self, id: usize, angle: f32) {
-        self.commands[id].command = GfxCommandTypes::SceneScale ( angle );
+    pub fn change_scene_scale(&mut self, handle: CommandHandle, scale: f32) -> Result<(), GfxError> {
+        return self.change(handle, GfxCommandTypes::SceneScale ( scale ));
     }
     
-    pub fn change_object_scale(&mut self, id: usize, angle: f32) {
-        self.commands[id].command = GfxCommandTypes::ObjectScale ( angle );
+    pub fn change_object_scale(&mut self, handle: CommandHandle, scale: f32) -> Result<(), GfxError> {
+        return self.change(handle, GfxCommandTypes::ObjectScale ( scale ));
     }
-    pub fn translate(&mut self, x: f32, y: f32) -> usize {
-        self.commands.push(GfxCommand { flags:0, command:GfxCommandTypes::Translate { x:x, y:y }});
-        return self.commands.len() - 1;
+    pub fn translate(&mut self, x: f32, y: f32) -> CommandHandle {
+        return self.push(GfxCommandTypes::Translate { x:x, y:y });
     }
     
-    pub fn change_translation(&mut self, id: usize, trans: (f32, f32)) {
-        self.commands[id].command = GfxCommandTypes::Translate { x:trans.0, y:trans.1 };
+    pub fn change_translation(&mut self, handle: CommandHandle, trans: (f32, f32)) -> Result<(), GfxError> {
+        return self.change(handle, GfxCommandTypes::Translate { x:trans.0, y:trans.1 });
     }
     
-    pub fn origin(&mut self, x: f32, y: f32) -> usize {
-        self.commands.push(GfxCommand { flags:0, command:GfxCommandTypes::Origin { x:x, y:y }});
-        return self.commands.len() - 1;
+    pub fn origin(&mut self, x: f32, y: f32) -> CommandHandle {
+        return self.push(GfxCommandTypes::Origin { x:x, y:y });
     }
-    pub fn change_origin(&mut self, id: usize, x: f32, y: f32) {
-        self.commands[id].command = GfxCommandTypes::Origin { x:x, y:y };
+    pub fn change_origin(&mut self, handle: CommandHandle, x: f32, y: f32) -> Result<(), GfxError> {
+        return self.change(handle, GfxCommandTypes::Origin { x:x, y:y });
     }
     
-    pub fn line_draw(&mut self) -> usize {
-        self.commands.push(GfxCommand { flags:0, command:GfxCommandTypes::LineDraw });
-        return self.commands.len() - 1;
+    // non-uniform version of object_scale, negative to mirror
+    pub fn scale(&mut self, x: f32, y: f32) -> CommandHandle {
+        return self.push(GfxCommandTypes::Scale { x:x, y:y });
+    }
+
+    pub fn change_scale(&mut self, handle: CommandHandle, x: f32, y: f32) -> Result<(), GfxError> {
+        return self.change(handle, GfxCommandTypes::Scale { x:x, y:y });
+    }
+
+    pub fn shear(&mut self, x: f32, y: f32) -> CommandHandle {
+        return self.push(GfxCommandTypes::Shear { x:x, y:y });
+    }
+
+    pub fn change_shear(&mut self, handle: CommandHandle, x: f32, y: f32) -> Result<(), GfxError> {
+        return self.change(handle, GfxCommandTypes::Shear { x:x, y:y });
+    }
+
+    // multiplied in after translate/rotate/shear/scale
+    pub fn matrix(&mut self, matrix: GfxTransform) -> CommandHandle {
+        return self.push(GfxCommandTypes::Matrix(matrix));
+    }
+
+    pub fn change_matrix(&mut self, handle: CommandHandle, matrix: GfxTransform) -> Result<(), GfxError> {
+        return self.change(handle, GfxCommandTypes::Matrix(matrix));
+    }
+
+    // the transform commands after this are relative to the
+    // transform in effect here, until the matching pop_transform
+    pub fn push_transform(&mut self) -> CommandHandle {
+        return self.push(GfxCommandTypes::PushTransform);
+    }
+
+    pub fn pop_transform(&mut self) -> CommandHandle {
+        return self.push(GfxCommandTypes::PopTransform);
+    }
+
+    // draw state, it holds until the next command of the same kind
+    // whatever the transform stack does
+    pub fn blend(&mut self, blend: GfxBlend) -> CommandHandle {
+        return self.push(GfxCommandTypes::Blend(blend));
+    }
+
+    pub fn change_blend(&mut self, handle: CommandHandle, blend: GfxBlend) -> Result<(), GfxError> {
+        return self.change(handle, GfxCommandTypes::Blend(blend));
+    }
+
+    pub fn line_width(&mut self, width: f32) -> CommandHandle {
+        return self.push(GfxCommandTypes::LineWidth(width));
+    }
+
+    pub fn change_line_width(&mut self, handle: CommandHandle, width: f32) -> Result<(), GfxError> {
+        return self.change(handle, GfxCommandTypes::LineWidth(width));
+    }
+
+    // line draws after it come out as triangles the same on every
+    // driver, None goes back to line_width
+    pub fn line_style(&mut self, style: Option<GfxLineStyle>) -> CommandHandle {
+        return self.push(GfxCommandTypes::LineStyle(style));
+    }
+
+    pub fn change_line_style(&mut self, handle: CommandHandle, style: Option<GfxLineStyle>) -> Result<(), GfxError> {
+        return self.change(handle, GfxCommandTypes::LineStyle(style));
+    }
+
+    // None draws to the whole frame again
+    pub fn scissor(&mut self, rect: Option<GfxRect>) -> CommandHandle {
+        return self.push(GfxCommandTypes::Scissor(rect));
+    }
+
+    pub fn change_scissor(&mut self, handle: CommandHandle, rect: Option<GfxRect>) -> Result<(), GfxError> {
+        return self.change(handle, GfxCommandTypes::Scissor(rect));
+    }
+
+    pub fn stencil(&mut self, stencil: GfxStencil) -> CommandHandle {
+        return self.push(GfxCommandTypes::Stencil(stencil));
+    }
+
+    pub fn change_stencil(&mut self, handle: CommandHandle, stencil: GfxStencil) -> Result<(), GfxError> {
+        return self.change(handle, GfxCommandTypes::Stencil(stencil));
+    }
+
+    // higher layers are drawn over lower ones whatever order they're
+    // in the list, everything starts out in layer 0
+    pub fn layer(&mut self, layer: i32) -> CommandHandle {
+        return self.push(GfxCommandTypes::Layer(layer));
+    }
+
+    pub fn change_layer(&mut self, handle: CommandHandle, layer: i32) -> Result<(), GfxError> {
+        return self.change(handle, GfxCommandTypes::Layer(layer));
+    }
+
+    // multiplies the colour of everything drawn after it, like the
+    // draw state it isn't affected by push/pop_transform
+    pub fn tint(&mut self, r: f32, g: f32, b: f32) -> CommandHandle {
+        return self.push(GfxCommandTypes::Tint([r, g, b]));
+    }
+
+    pub fn change_tint(&mut self, handle: CommandHandle, r: f32, g: f32, b: f32) -> Result<(), GfxError> {
+        return self.change(handle, GfxCommandTypes::Tint([r, g, b]));
+    }
+
+    pub fn alpha(&mut self, alpha: f32) -> CommandHandle {
+        return self.push(GfxCommandTypes::Alpha(alpha));
+    }
+
+    pub fn change_alpha(&mut self, handle: CommandHandle, alpha: f32) -> Result<(), GfxError> {
+        return self.change(handle, GfxCommandTypes::Alpha(alpha));
+    }
+
+    // does nothing, for marking a place in the list to insert before
+    // or to give a viewport the commands up to
+    pub fn noop(&mut self) -> CommandHandle {
+        return self.push(GfxCommandTypes::NoOp);
+    }
+
+    pub fn line_draw(&mut self) -> CommandHandle {
+        return self.push(GfxCommandTypes::LineDraw);
     }
     
-    pub fn triangle_draw(&mut self) -> usize {
-        self.commands.push(GfxCommand { flags:0, command:GfxCommandTypes::TriangleDraw });
-        return self.commands.len() - 1;
+    pub fn triangle_draw(&mut self) -> CommandHandle {
+        return self.push(GfxCommandTypes::TriangleDraw);
     }
     
-    pub fn indices(&mut self, indices: usize) -> usize {
-        self.commands.push(GfxCommand { flags:0, command:GfxCommandTypes::Indices (indices)});
-        return self.commands.len() - 1;
+    pub fn indices(&mut self, indices: IndexHandle) -> Result<CommandHandle, GfxError> {
+        self.index_data(indices)?;
+        return Ok(self.push(GfxCommandTypes::Indices (indices)));
+    }
+
+    fn index_data(&self, indices: IndexHandle) -> Result<&GfxIndices, GfxError> {
+        match self.indices.get(indices.0) {
+            Some(Some(data)) => return Ok(data),
+            _                => return Err(GfxError::NoSuchIndices(indices))
+        }
+    }
+
+    // checks every reference in the command list, for command lists
+    // that didn't come through the methods above
+    pub fn validate(&self) -> Result<(), GfxError> {
+        return self.check_commands().map_err(|(_, error)| error);
+    }
+
+    // what validate() does, along with where in commands it went wrong
+    fn check_commands(&self) -> Result<(), (usize, GfxError)> {
+        let mut cur_program = None;
+        let mut cur_indices = None;
+        let mut depth       = 0usize;
+        for (position, command) in self.ordered().enumerate() {
+            let at = |error| (position, error);
+            if command.flags & (GFX_SKIP | GFX_FREE) != 0 {
+                continue;
+            }
+            match command.command {
+                GfxCommandTypes::Program(program) => {
+                    if program.0 >= self.programs.len() {
+                        return Err(at(GfxError::NoSuchProgram(program)));
+                    }
+                    cur_program = Some(program);
+                },
+                GfxCommandTypes::Indices(indices) => {
+                    self.index_data(indices).map_err(at)?;
+                    cur_indices = Some(indices);
+                },
+                GfxCommandTypes::LineDraw | GfxCommandTypes::TriangleDraw => {
+                    if cur_program.is_none() || cur_indices.is_none() {
+                        return Err(at(GfxError::UnboundDraw(CommandHandle(command.slot))));
+                    }
+                },
+                GfxCommandTypes::InstancedDraw(instances) => {
+                    self.instance_data(instances).map_err(at)?;
+                    let indices = match (cur_program, cur_indices) {
+                        (Some(_), Some(indices)) => self.index_data(indices).map_err(at)?,
+                        _                        => return Err(at(GfxError::UnboundDraw(CommandHandle(command.slot))))
+                    };
+                    // there's only shaders/triangle_instanced.vert
+                    if indices.draw_type() == GfxDrawType::Lines {
+                        return Err(at(GfxError::InstancedLines(CommandHandle(command.slot))));
+                    }
+                },
+                GfxCommandTypes::PushTransform => {
+                    depth += 1;
+                },
+                GfxCommandTypes::PopTransform => {
+                    if depth == 0 {
+                        return Err(at(GfxError::UnbalancedTransform(CommandHandle(command.slot))));
+                    }
+                    depth -= 1;
+                },
+                _ => {}
+            }
+        }
+        return Ok(());
+    }
+
+    // draws every viewport, and in each one every command in its range
+    // that isn't skipped
+    pub fn run(&mut self, backend: &mut dyn RenderBackend) {
+        let mut stats = GfxFrameStats::new();
+        let started   = std::time::Instant::now();
+        self.settle();
+
+        // hand the backend anything it hasn't seen yet
+        for id in self.programs_pending.drain(..) {
+            stats.programs_uploaded += 1;
+            if let Err(error) = backend.load_program(id, &self.programs[id]) {
+                self.program_errors.push(error);
+            }
+        }
+        for id in self.indices_pending.drain(..) {
+            match self.indices[id] {
+                Some(ref indices) => {
+                    stats.indices_uploaded += 1;
+                    backend.load_indices(id, indices);
+                },
+                None              => backend.free_indices(id)
+            }
+        }
+        for id in self.instances_pending.drain(..) {
+            match self.instances[id] {
+                Some(ref instances) => {
+                    stats.instances_uploaded += 1;
+                    backend.load_instances(id, instances);
+                },
+                None                => backend.free_instances(id)
+            }
+        }
+        for dirty in std::mem::take(&mut self.line_dirty) {
+            stats.vertices_uploaded += dirty.len();
+            backend.load_line_vertices(&self.line_backing, dirty);
+        }
+        for dirty in std::mem::take(&mut self.triangle_dirty) {
+            stats.vertices_uploaded += dirty.len();
+            backend.load_triangle_vertices(&self.triangle_backing, dirty);
+        }
+
+        let line_backing     = &self.line_backing;
+        let triangle_backing = &self.triangle_backing;
+        for indices in self.indices.iter_mut().flatten() {
+            if indices.bounds.is_none() {
+                let data = &indices.data;
+                indices.bounds = match indices.draw_type() {
+                    GfxDrawType::Lines     => GfxBounds::around(data.iter().filter_map(|i| line_backing.get(*i as usize))
+                                                                           .map(|vertex| vertex.position)),
+                    GfxDrawType::Triangles => GfxBounds::around(data.iter().filter_map(|i| triangle_backing.get(*i as usize))
+                                                                           .map(|vertex| vertex.position))
+                };
+            }
+        }
+
+        let (width, height) = backend.begin_frame();
+        stats.width  = width;
+        stats.height = height;
+        let mut thick_lines = std::mem::take(&mut self.thick_lines);
+        for entry in thick_lines.iter_mut() {
+            entry.used = false;
+        }
+        for viewport in self.viewports.iter() {
+            if let Some(range) = self.viewport_range(viewport) {
+                self.run_viewport(backend, viewport, range, (width, height), &mut thick_lines, &mut stats);
+            }
+        }
+        thick_lines.retain(|entry| entry.used);
+        self.thick_lines = thick_lines;
+
+        stats.render_time = started.elapsed();
+        self.stats = stats;
+        backend.end_frame();
     }
 
-    pub fn run(&mut self, display: &glium::Display) {
-        let mut target          = display.draw();
-        let mut cur_program     = 0usize;
-        let mut cur_translation = [ 0.0, 0.0f32 ];
+    fn run_viewport(&self,
+                    backend: &mut dyn RenderBackend,
+                    viewport: &GfxViewport,
+                    range: Range<usize>,
+                    frame: (u32, u32),
+                    thick_lines: &mut Vec< lines::ThickLines >,
+                    stats: &mut GfxFrameStats) {
+        let mut cur_program     = None;
         let mut cur_origin      = [ 0.0, 0.0f32 ];
         let mut cur_scene_scale = 0.5f32;
-        let mut cur_object_scale = 1.0f32;
-        let mut cur_angle       = 0.0f32;
-        let mut cur_indices     = 0usize;
-        let params = glium::DrawParameters {
-            blend: glium::Blend::alpha_blending(),
-            multisampling: true,
-            line_width: Some(2.0),
-
-            ..Default::default()
-        };
-        if self.backing_changed {
-            self.line_vertices = {
-                implement_vertex!(GfxLineVertex, position);
-                Some(glium::VertexBuffer::new(display, &self.line_backing).unwrap())
-            };
-            self.triangle_vertices = {
-                implement_vertex!(GfxTriangleVertex, position, color);
-                Some(glium::VertexBuffer::new(display, &self.triangle_backing).unwrap())
-            };
-            self.backing_changed = false;
+        let mut cur_view_angle  = 0.0f32;
+        let mut cur_transform   = GfxLocalTransform::identity();
+        let mut cur_parent      = GfxTransform::identity();
+        let mut transform_stack = Vec::new();
+        let mut cur_indices     = None;
+        let mut cur_state       = GfxDrawState::new();
+        let mut cur_tint        = [1.0, 1.0, 1.0f32];
+        let mut cur_alpha       = 1.0f32;
+        let mut queue           = Vec::new();
+
+        cur_state.viewport = viewport.rect;
+        if let Some(view) = viewport.view {
+            cur_origin      = [view.origin.0, view.origin.1];
+            cur_scene_scale = view.scale;
+            cur_view_angle  = view.rotation;
         }
+        let own_view = viewport.view.is_some();
 
         // set the aspect ratio...
-        let (width, height) = target.get_dimensions();
+        let (_, _, width, height) = viewport.rect.to_pixels(frame.0, frame.1);
+        if width == 0 || height == 0 {
+            return;
+        }
         let aspect_ratio = height as f32 / width as f32;
+        // a couple of pixels for line width and antialiasing
+        let margin = (4.0 / width as f32, 4.0 / height as f32);
         
-        target.clear_color(0.0, 0.0, 0.0, 0.0);
-        for command in self.commands.iter() {
-            if command.flags & GFX_SKIP == 0 {
-                match command.command {
-                    GfxCommandTypes::LineDraw => {
-                        match self.line_vertices {
-                            None => println!("No Line Vertices Set"),
-                            Some(ref vertices) => {
-                                target.draw(vertices, 
-                                            &self.indices[cur_indices], 
-                                            &self.programs[cur_program], 
-                                            &uniform! {translation:  cur_translation, 
-                                                       scene_scale:  cur_scene_scale, 
-                                                       object_scale: cur_object_scale, 
-                                                       angle:        cur_angle,
-                                                       origin:       cur_origin,
-                                                       aspect_ratio: aspect_ratio}, 
-                                            &params).unwrap(); 
-                            } 
-                        } 
+        for command in self.commands[range].iter() {
+            if command.flags & GFX_FREE == 0 && command.flags & GFX_SKIP != 0 {
+                stats.skipped += 1;
+            }
+            if command.flags & (GFX_SKIP | GFX_FREE) == 0 {
+                let mut instanced = None;
+                let draw_type = match command.command {
+                    GfxCommandTypes::LineDraw           => Some(GfxDrawType::Lines),
+                    GfxCommandTypes::TriangleDraw       => Some(GfxDrawType::Triangles),
+                    // triangles only, and nothing at all if the instances
+                    // have been freed
+                    GfxCommandTypes::InstancedDraw(instances) => {
+                        match self.instance_data(instances) {
+                            Ok(data) => {
+                                instanced = Some((instances, data));
+                                cur_indices.and_then(|indices| self.index_data(indices).ok())
+                                           .map(|data| data.draw_type())
+                                           .filter(|draw_type| *draw_type == GfxDrawType::Triangles)
+                            },
+                            Err(_)   => None
+                        }
                     },
-                    GfxCommandTypes::TriangleDraw => {
-                        match self.triangle_vertices {
-                            None => println!("No Triangle Vertices Set"),
-                            Some(ref vertices) => {
-                                target.draw(vertices, 
-                                            &self.indices[cur_indices], 
-                                            &self.programs[cur_program], 
-                                            &uniform! {translation:  cur_translation, 
-                                                       scene_scale:  cur_scene_scale, 
-                                                       object_scale: cur_object_scale, 
-                                                       angle:        cur_angle,
-                                                       origin:       cur_origin,
-                                                       aspect_ratio: aspect_ratio}, 
-                                            &params).unwrap(); 
-                            } 
-                        } 
+                    GfxCommandTypes::NoOp               => None,
+                    GfxCommandTypes::Indices(index)     => { cur_indices = Some(index); None },
+                    GfxCommandTypes::Program(index)     => { cur_program = Some(index); None },
+                    GfxCommandTypes::Rotate(angle)      => { cur_transform.angle = angle; None },
+                    GfxCommandTypes::SceneScale(_) if own_view => None,
+                    GfxCommandTypes::ViewRotate(_) if own_view => None,
+                    GfxCommandTypes::Origin { .. } if own_view => None,
+                    GfxCommandTypes::SceneScale(scale)  => { cur_scene_scale = scale; None },
+                    GfxCommandTypes::ViewRotate(angle)  => { cur_view_angle = angle; None },
+                    GfxCommandTypes::ObjectScale(scale) => { cur_transform.scale = [scale, scale]; None },
+                    GfxCommandTypes::Scale { x, y }     => { cur_transform.scale = [x, y]; None },
+                    GfxCommandTypes::Shear { x, y }     => { cur_transform.shear = [x, y]; None },
+                    GfxCommandTypes::Matrix(matrix)     => { cur_transform.matrix = matrix; None },
+                    GfxCommandTypes::Translate { x, y } => { cur_transform.translation = [x, y]; None },
+                    GfxCommandTypes::Origin { x, y }    => { cur_origin = [x, y]; None },
+                    GfxCommandTypes::PushTransform      => { 
+                        transform_stack.push((cur_parent, cur_transform));
+                        cur_parent    = cur_parent.multiply(&cur_transform.to_transform());
+                        cur_transform = GfxLocalTransform::identity();
+                        None 
                     },
-                    GfxCommandTypes::NoOp               => { },
-                    GfxCommandTypes::Indices(index)     => cur_indices = index,
-                    GfxCommandTypes::Program(index)     => cur_program = index,
-                    GfxCommandTypes::Rotate(angle)      => cur_angle = angle,
-                    GfxCommandTypes::SceneScale(scale)  => cur_scene_scale = scale,
-                    GfxCommandTypes::ObjectScale(scale) => cur_object_scale = scale,
-                    GfxCommandTypes::Translate { x, y } => { cur_translation[0] = x; cur_translation[1] = y }
-                    GfxCommandTypes::Origin { x, y }    => { cur_origin[0] = x; cur_origin[1] = y }
+                    GfxCommandTypes::PopTransform       => { 
+                        if let Some((parent, transform)) = transform_stack.pop() {
+                            cur_parent    = parent;
+                            cur_transform = transform;
+                        }
+                        None 
+                    },
+                    GfxCommandTypes::Blend(blend)       => { cur_state.blend = blend; None },
+                    GfxCommandTypes::LineWidth(width)   => { cur_state.line_width = width; None },
+                    GfxCommandTypes::LineStyle(style)   => { cur_state.line_style = style; None },
+                    GfxCommandTypes::Scissor(rect)      => { cur_state.scissor = rect; None },
+                    GfxCommandTypes::Stencil(stencil)   => { cur_state.stencil = stencil; None },
+                    GfxCommandTypes::Layer(layer)       => { cur_state.layer = layer; None },
+                    GfxCommandTypes::Tint(tint)         => { cur_tint = tint; None },
+                    GfxCommandTypes::Alpha(alpha)       => { cur_alpha = alpha; None }
+                };
+                // the index buffer could have been freed since it was bound
+                let cur_indices = cur_indices.and_then(|indices: IndexHandle| self.index_data(indices).ok().map(|data| (indices, data)));
+                if let (Some(draw_type), Some(program), Some((indices, data))) = (draw_type, cur_program, cur_indices) {
+                    let view = view_transform((cur_origin[0], cur_origin[1]), cur_scene_scale, 
+                                              cur_view_angle, aspect_ratio);
+                    let transform = view.multiply(&cur_parent)
+                                        .multiply(&cur_transform.to_transform());
+                    let uniforms  = GfxUniforms { transform: transform,
+                                                  tint:      cur_tint,
+                                                  alpha:     cur_alpha };
+                    match instanced {
+                        None => {
+                            // thick lines reach further than the vertices do
+                            let half_width = match (draw_type, cur_state.line_style) {
+                                (GfxDrawType::Lines, Some(style)) => Some((style, style.half_width(&transform, (width, height)))),
+                                _                                 => None
+                            };
+                            if let Some(bounds) = data.bounds {
+                                let bounds = GfxBounds { center: bounds.center,
+                                                         radius: bounds.radius + half_width.map_or(0.0, |(_, half)| half) };
+                                if !bounds.visible(&transform, margin) {
+                                    stats.culled += 1;
+                                    continue;
+                                }
+                            }
+                            let thick = half_width.map(|(style, half)| {
+                                lines::cached(thick_lines, &self.line_backing, indices.0, data, half, &style)
+                            });
+                            stats.draw_calls += 1;
+                            stats.indices    += data.data.len();
+                            stats.vertices   += match thick {
+                                Some(i) => thick_lines[i].triangles.len(),
+                                None    => data.span.len()
+                            };
+                            queue.push(GfxQueuedDraw { draw_type: draw_type,
+                                                       program:   program.0,
+                                                       indices:   indices.0,
+                                                       instances: None,
+                                                       thick:     thick,
+                                                       uniforms:  uniforms,
+                                                       state:     cur_state });
+                        },
+                        // culled only if every instance is off screen
+                        Some((instances, instance_data)) => {
+                            if instance_data.is_empty() {
+                                continue;
+                            }
+                            if let Some(bounds) = data.bounds {
+                                if !instance_data.iter().any(|instance| bounds.visible(&transform.multiply(&instance.transform()), margin)) {
+                                    stats.culled += 1;
+                                    continue;
+                                }
+                            }
+                            let count = instance_data.len();
+                            stats.draw_calls += 1;
+                            stats.instances  += count;
+                            stats.indices    += data.data.len() * count;
+                            stats.vertices   += data.span.len() * count;
+                            queue.push(GfxQueuedDraw { draw_type: draw_type,
+                                                       program:   program.0,
+                                                       indices:   indices.0,
+                                                       instances: Some(instances.0),
+                                                       thick:     None,
+                                                       uniforms:  uniforms,
+                                                       state:     cur_state });
+                        }
+                    }
                 }
             }
         }
-    
 
-        target.finish().unwrap();
+        // a stable sort, so the list order holds within a layer
+        queue.sort_by_key(|draw| draw.state.layer);
+        for draw in queue.iter() {
+            match (draw.instances, draw.thick) {
+                (Some(instances), _)  => backend.draw_instanced(draw.draw_type, draw.program, draw.indices, instances, 
+                                                                &draw.uniforms, &draw.state),
+                (None, Some(thick))   => backend.draw_thick_lines(draw.program, draw.indices, &thick_lines[thick].triangles,
+                                                                  &draw.uniforms, &draw.state),
+                (None, None)          => backend.draw(draw.draw_type, draw.program, draw.indices, 
+                                                      &draw.uniforms, &draw.state)
+            }
+        }
+    }
+
+    // one frame at any size, the window doesn't change
+    pub fn render_offscreen(&mut self, 
+                            backend: &mut dyn RenderBackend, 
+                            width: u32, 
+                            height: u32) -> Result<Option<Image>, GfxError> {
+        backend.set_offscreen(Some((width, height)))?;
+        self.run(backend);
+        let image = backend.read_frame();
+        backend.set_offscreen(None)?;
+        return Ok(image);
     }
+
     pub fn add_program(&mut self, 
-                       display: &glium::Display, 
                        vert_shader: &str, 
-                       frag_shader: &str) -> usize {
-        self.programs.push(program!(display, 
-                                    140 => {vertex:vert_shader, fragment:frag_shader}).unwrap());
-        return self.programs.len() - 1;
+                       frag_shader: &str) -> ProgramHandle {
+        self.programs.push(GfxProgram { vert_shader: vert_shader.to_string(),
+                                        frag_shader: frag_shader.to_string() });
+        self.programs_pending.push(self.programs.len() - 1);
+        return ProgramHandle(self.programs.len() - 1);
+    }
+
+    // new source for an existing program, built on the next run()
+    pub fn replace_program(&mut self, 
+                           program: ProgramHandle,
+                           vert_shader: &str, 
+                           frag_shader: &str) -> Result<(), GfxError> {
+        match self.programs.get_mut(program.0) {
+            None         => return Err(GfxError::NoSuchProgram(program)),
+            Some(source) => *source = GfxProgram { vert_shader: vert_shader.to_string(),
+                                                   frag_shader: frag_shader.to_string() }
+        }
+        self.programs_pending.push(program.0);
+        return Ok(());
+    }
+
+    // programs the backend couldn't build since this was last called
+    pub fn take_program_errors(&mut self) -> Vec< GfxProgramError > {
+        return std::mem::replace(&mut self.program_errors, Vec::new());
     }
     pub fn add_indices(&mut self, 
-                       display: &glium::Display, 
                        indices: &[u32], 
-                       primitive_type: PrimitiveType) -> usize {
-        self.indices.push(glium::IndexBuffer::new(display, 
-                                                  primitive_type,
-                                                  indices).unwrap());
-        return self.indices.len() - 1;
+                       primitive_type: PrimitiveType) -> IndexHandle {
+        let indices = GfxIndices::new(indices.to_vec(), primitive_type);
+        let id = match self.free_index_ids.pop() {
+            Some(id) => {
+                self.indices[id] = Some(indices);
+                self.forget_thick_lines(id);
+                id
+            },
+            None => {
+                self.indices.push(Some(indices));
+                self.indices.len() - 1
+            }
+        };
+        self.indices_pending.push(id);
+        self.spans_changed();
+        return IndexHandle(id);
+    }
+
+    pub fn free_indices(&mut self, indices: IndexHandle) -> Result<(), GfxError> {
+        self.index_data(indices)?;
+        self.indices[indices.0] = None;
+        self.forget_thick_lines(indices.0);
+        self.free_index_ids.push(indices.0);
+        self.indices_pending.push(indices.0);
+        self.spans_changed();
+        return Ok(());
+    }
+
+    // instead of working them out from the vertices, None to go back
+    // to that
+    pub fn set_bounds(&mut self, indices: IndexHandle, bounds: Option<GfxBounds>) -> Result<(), GfxError> {
+        self.index_data(indices)?;
+        if let Some(Some(data)) = self.indices.get_mut(indices.0) {
+            data.bounds       = bounds;
+            data.fixed_bounds = bounds.is_some();
+        }
+        return Ok(());
+    }
+
+    pub fn bounds(&self, indices: IndexHandle) -> Result<Option<GfxBounds>, GfxError> {
+        return Ok(self.index_data(indices)?.bounds);
+    }
+
+    // draws left out of the last run() for being off screen
+    pub fn num_culled(&self) -> usize {
+        return self.stats.culled;
+    }
+
+    pub fn stats(&self) -> GfxFrameStats {
+        return self.stats;
+    }
+
+    // the ones still there, freed ones waiting for compact() aren't
+    pub fn num_commands(&self) -> usize {
+        return self.ordered().filter(|command| command.flags & GFX_FREE == 0).count();
+    }
+
+    pub fn num_programs(&self) -> usize {
+        return self.programs.len();
+    }
+
+    pub fn num_indices(&self) -> usize {
+        return self.indices.len() - self.free_index_ids.len();
     }
 
     pub fn triangle_len(&self) -> usize {
@@ -339,15 +1401,520 @@ impl Gfx {
                                position: (f32, f32), 
                                color: (f32, f32, f32, f32)) {
         self.triangle_backing.push( GfxTriangleVertex { position: [position.0, position.1], color: [color.0, color.1, color.2, color.3] });
-        self.backing_changed = true;
+        let index = self.triangle_backing.len() - 1;
+        mark_dirty(&mut self.triangle_dirty, index..index+1);
     }
 
     pub fn add_line_vertex(&mut self, 
                                position: (f32, f32)) {
                                
         self.line_backing.push( GfxLineVertex { position: [position.0, position.1]});
-        self.backing_changed = true;
+        let index = self.line_backing.len() - 1;
+        mark_dirty(&mut self.line_dirty, index..index+1);
+    }
+
+    fn add_vertex_range(&mut self, draw_type: GfxDrawType, range: Range<usize>) -> VertexHandle {
+        let vertices = GfxVertexRange { draw_type: draw_type, range: range };
+        match self.free_vertex_slots.pop() {
+            Some(slot) => {
+                self.vertex_ranges[slot] = Some(vertices);
+                return VertexHandle(slot);
+            },
+            None => {
+                self.vertex_ranges.push(Some(vertices));
+                return VertexHandle(self.vertex_ranges.len() - 1);
+            }
+        }
+    }
+
+    // hands out the vertices from start to the end of the backing,
+    // for geometry built with add_*_vertex
+    pub fn claim_line_vertices(&mut self, start: usize) -> VertexHandle {
+        let end = self.line_backing.len();
+        return self.add_vertex_range(GfxDrawType::Lines, start.min(end)..end);
+    }
+
+    pub fn claim_triangle_vertices(&mut self, start: usize) -> VertexHandle {
+        let end = self.triangle_backing.len();
+        return self.add_vertex_range(GfxDrawType::Triangles, start.min(end)..end);
+    }
+
+    // count vertices, reusing freed ones where they fit, to be filled
+    // in with set_*_vertex
+    pub fn alloc_line_vertices(&mut self, count: usize) -> VertexHandle {
+        let range = match take_free(&mut self.line_free, count) {
+            Some(range) => range,
+            None        => {
+                let start = self.line_backing.len();
+                self.line_backing.resize(start + count, GfxLineVertex { position: [0.0, 0.0] });
+                start..(start + count)
+            }
+        };
+        mark_dirty(&mut self.line_dirty, range.clone());
+        return self.add_vertex_range(GfxDrawType::Lines, range);
+    }
+
+    pub fn alloc_triangle_vertices(&mut self, count: usize) -> VertexHandle {
+        let range = match take_free(&mut self.triangle_free, count) {
+            Some(range) => range,
+            None        => {
+                let start = self.triangle_backing.len();
+                self.triangle_backing.resize(start + count, GfxTriangleVertex { position: [0.0, 0.0], color: [0.0; 4] });
+                start..(start + count)
+            }
+        };
+        mark_dirty(&mut self.triangle_dirty, range.clone());
+        return self.add_vertex_range(GfxDrawType::Triangles, range);
+    }
+
+    // where the range currently starts in its backing array, only good
+    // until the next compact()
+    pub fn vertex_start(&self, vertices: VertexHandle) -> Result<usize, GfxError> {
+        match self.vertex_ranges.get(vertices.0) {
+            Some(Some(vertices)) => return Ok(vertices.range.start),
+            _                    => return Err(GfxError::NoSuchVertices(vertices))
+        }
+    }
+
+    pub fn free_vertices(&mut self, vertices: VertexHandle) -> Result<(), GfxError> {
+        let freed = match self.vertex_ranges.get_mut(vertices.0) {
+            Some(slot) if slot.is_some() => slot.take().unwrap(),
+            _                            => return Err(GfxError::NoSuchVertices(vertices))
+        };
+        self.free_vertex_slots.push(vertices.0);
+        match freed.draw_type {
+            GfxDrawType::Lines     => self.line_free.push(freed.range),
+            GfxDrawType::Triangles => self.triangle_free.push(freed.range)
+        }
+        return Ok(());
+    }
+
+    // drops freed commands and vertices for good.  Vertex indices
+    // change, so every index buffer is rewritten and loaded again;
+    // handles stay as they were.
+    pub fn compact(&mut self) {
+        self.settle();
+        self.commands.retain(|command| command.flags & GFX_FREE == 0);
+        for (i, command) in self.commands.iter().enumerate() {
+            self.command_slots[command.slot] = Some(GfxSlot::Listed(i));
+        }
+
+        let line_remap     = compact_backing(&mut self.line_backing, &mut self.line_free);
+        let triangle_remap = compact_backing(&mut self.triangle_backing, &mut self.triangle_free);
+        if line_remap.is_none() && triangle_remap.is_none() {
+            return;
+        }
+
+        for vertices in self.vertex_ranges.iter_mut().flatten() {
+            let remap = match vertices.draw_type {
+                GfxDrawType::Lines     => &line_remap,
+                GfxDrawType::Triangles => &triangle_remap
+            };
+            if let Some(remap) = remap {
+                vertices.range = (remap[vertices.range.start] as usize)..(remap[vertices.range.end] as usize);
+            }
+        }
+        for (id, indices) in self.indices.iter_mut().enumerate() {
+            if let Some(indices) = indices {
+                let remap = match indices.draw_type() {
+                    GfxDrawType::Lines     => &line_remap,
+                    GfxDrawType::Triangles => &triangle_remap
+                };
+                if let Some(remap) = remap {
+                    for index in indices.data.iter_mut() {
+                        *index = remap[(*index as usize).min(remap.len() - 1)];
+                    }
+                    indices.update_span();
+                    self.indices_pending.push(id);
+                }
+            }
+        }
+        self.spans_changed();
+        // dirty ranges from before were in the old numbering
+        if line_remap.is_some() {
+            self.line_dirty.clear();
+            mark_dirty(&mut self.line_dirty, 0..self.line_backing.len());
+            self.thick_lines.clear();
+        }
+        if triangle_remap.is_some() {
+            self.triangle_dirty.clear();
+            mark_dirty(&mut self.triangle_dirty, 0..self.triangle_backing.len());
+        }
+    }
+
+    fn forget_thick_lines(&mut self, id: usize) {
+        self.thick_lines.retain(|entry| entry.indices != id);
+    }
+
+    fn spans_changed(&mut self) {
+        self.line_spans     = None;
+        self.triangle_spans = None;
+    }
+
+    // some vertices moved, so whatever was worked out from them has to
+    // be again.  Fixed bounds stay, thick lines go either way.
+    fn stale_bounds(&mut self, draw_type: GfxDrawType, vertices: Range<usize>) {
+        let spans = match draw_type {
+            GfxDrawType::Lines     => &mut self.line_spans,
+            GfxDrawType::Triangles => &mut self.triangle_spans
+        };
+        let indices = &mut self.indices;
+        let spans = spans.get_or_insert_with(|| spans::GfxSpans::new(indices, draw_type));
+        for id in spans.overlapping(vertices) {
+            if let Some(indices) = &mut indices[id] {
+                if !indices.fixed_bounds {
+                    indices.bounds = None;
+                }
+            }
+            if draw_type == GfxDrawType::Lines {
+                self.thick_lines.retain(|entry| entry.indices != id);
+            }
+        }
+    }
+
+    // overwrite vertices in place, only the changed range goes back
+    // to the gpu
+    pub fn set_triangle_vertex(&mut self, 
+                               index: usize,
+                               position: (f32, f32), 
+                               color: (f32, f32, f32, f32)) -> Result<(), GfxError> {
+        match self.triangle_backing.get_mut(index) {
+            None         => return Err(GfxError::NoSuchVertex(index)),
+            Some(vertex) => *vertex = GfxTriangleVertex { position: [position.0, position.1], color: [color.0, color.1, color.2, color.3] }
+        }
+        mark_dirty(&mut self.triangle_dirty, index..index+1);
+        self.stale_bounds(GfxDrawType::Triangles, index..index+1);
+        return Ok(());
+    }
+
+    // a run of them from start, for anything rewriting a lot at once
+    pub fn set_triangle_vertices(&mut self,
+                                 start: usize,
+                                 vertices: &[GfxTriangleVertex]) -> Result<(), GfxError> {
+        let end = start + vertices.len();
+        if end > self.triangle_backing.len() {
+            return Err(GfxError::NoSuchVertex(end - 1));
+        }
+        self.triangle_backing[start..end].copy_from_slice(vertices);
+        mark_dirty(&mut self.triangle_dirty, start..end);
+        self.stale_bounds(GfxDrawType::Triangles, start..end);
+        return Ok(());
+    }
+
+    pub fn set_triangle_color(&mut self, 
+                              index: usize,
+                              color: (f32, f32, f32, f32)) -> Result<(), GfxError> {
+        match self.triangle_backing.get_mut(index) {
+            None         => return Err(GfxError::NoSuchVertex(index)),
+            Some(vertex) => vertex.color = [color.0, color.1, color.2, color.3]
+        }
+        // bounds only go by position
+        mark_dirty(&mut self.triangle_dirty, index..index+1);
+        return Ok(());
+    }
+
+    pub fn set_line_vertex(&mut self, 
+                           index: usize,
+                           position: (f32, f32)) -> Result<(), GfxError> {
+        match self.line_backing.get_mut(index) {
+            None         => return Err(GfxError::NoSuchVertex(index)),
+            Some(vertex) => vertex.position = [position.0, position.1]
+        }
+        mark_dirty(&mut self.line_dirty, index..index+1);
+        self.stale_bounds(GfxDrawType::Lines, index..index+1);
+        return Ok(());
+    }
+}
+
+// first fit out of the freed ranges
+fn take_free(free: &mut Vec< Range<usize> >, count: usize) -> Option<Range<usize>> {
+    let i = free.iter().position(|range| range.len() >= count)?;
+    let start = free[i].start;
+    free[i].start += count;
+    if free[i].is_empty() {
+        free.remove(i);
+    }
+    return Some(start..(start + count));
+}
+
+// squeezes the freed ranges out of a backing array, giving where each
+// old index ended up plus one past the end.  Indices of freed vertices
+// end up on the next live one.
+fn compact_backing<T: Copy>(backing: &mut Vec<T>, free: &mut Vec< Range<usize> >) -> Option<Vec<u32>> {
+    if free.is_empty() {
+        return None;
+    }
+    let mut freed = vec![false; backing.len()];
+    for range in free.drain(..) {
+        for i in range {
+            freed[i] = true;
+        }
+    }
+    let mut remap = Vec::with_capacity(backing.len() + 1);
+    let mut kept  = 0usize;
+    for i in 0..backing.len() {
+        remap.push(kept as u32);
+        if !freed[i] {
+            backing[kept] = backing[i];
+            kept += 1;
+        }
+    }
+    remap.push(kept as u32);
+    backing.truncate(kept);
+    return Some(remap);
+}
+
+// the ranges to send again, kept apart so a few vertices changing at
+// either end of the backing don't send everything in between.  They're
+// run together once there are too many to be worth separate uploads.
+const MAX_DIRTY_RANGES: usize = 16;
+
+fn mark_dirty(dirty: &mut Vec< Range<usize> >, range: Range<usize>) {
+    if range.is_empty() {
+        return;
+    }
+    let mut merged = range;
+    dirty.retain(|existing| {
+        if existing.start > merged.end || merged.start > existing.end {
+            return true;
+        }
+        merged = existing.start.min(merged.start)..existing.end.max(merged.end);
+        return false;
+    });
+    dirty.push(merged);
+    if dirty.len() > MAX_DIRTY_RANGES {
+        let start = dirty.iter().map(|range| range.start).min().unwrap();
+        let end   = dirty.iter().map(|range| range.end).max().unwrap();
+        dirty.clear();
+        dirty.push(start..end);
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::recording::RecordingBackend;
+    use super::state::GfxLineUnits;
+
+    #[test]
+    fn thick_lines_kept_until_their_vertices_move() {
+        let mut gfx = Gfx::new();
+        let program = gfx.add_program("", "");
+        gfx.add_line_vertex((0.0, 0.0));
+        gfx.add_line_vertex((0.5, 0.0));
+        let indices = gfx.add_indices(&[0, 1], PrimitiveType::LinesList);
+        gfx.program(program).unwrap();
+        gfx.line_style(Some(GfxLineStyle::new(0.1, GfxLineUnits::World)));
+        gfx.indices(indices).unwrap();
+        gfx.line_draw();
+
+        let mut backend = RecordingBackend::new(100, 100);
+        gfx.run(&mut backend);
+        assert_eq!(gfx.thick_lines.len(), 1);
+        let first = gfx.thick_lines[0].triangles.as_ptr();
+        gfx.run(&mut backend);
+        assert_eq!(gfx.thick_lines.len(), 1);
+        assert_eq!(gfx.thick_lines[0].triangles.as_ptr(), first);
+
+        gfx.set_line_vertex(1, (0.0, 0.5)).unwrap();
+        assert!(gfx.thick_lines.is_empty());
+        gfx.run(&mut backend);
+        assert!(gfx.thick_lines[0].triangles.iter().any(|vertex| vertex.position[1] > 0.4));
+
+        // nothing drew it, so it's let go
+        gfx.free_indices(indices).unwrap();
+        gfx.run(&mut backend);
+        assert!(gfx.thick_lines.is_empty());
+    }
+
+    #[test]
+    fn only_changed_vertices_go_back() {
+        let mut gfx = Gfx::new();
+        let dynamic = gfx.alloc_triangle_vertices(4);
+        for _ in 0..100 {
+            gfx.add_triangle_vertex((0.0, 0.0), (1.0, 1.0, 1.0, 1.0));
+        }
+        let particles = gfx.alloc_triangle_vertices(8);
+        let mut backend = RecordingBackend::new(100, 100);
+        gfx.run(&mut backend);
+        assert_eq!(backend.vertices_uploaded, 112);
+
+        let vertex = GfxTriangleVertex { position: [0.5, 0.5], color: [1.0; 4] };
+        gfx.set_triangle_vertex(gfx.vertex_start(dynamic).unwrap(), (0.5, 0.5), (1.0, 1.0, 1.0, 1.0)).unwrap();
+        gfx.set_triangle_vertices(gfx.vertex_start(particles).unwrap(), &[vertex; 8]).unwrap();
+        gfx.run(&mut backend);
+        assert_eq!(backend.vertices_uploaded, 112 + 9);
+
+        assert!(matches!(gfx.set_triangle_vertices(110, &[vertex; 3]), Err(GfxError::NoSuchVertex(112))));
+    }
+
+    #[test]
+    fn dirty_ranges_merge_when_they_touch() {
+        let mut dirty = Vec::new();
+        mark_dirty(&mut dirty, 10..20);
+        mark_dirty(&mut dirty, 30..40);
+        mark_dirty(&mut dirty, 0..0);
+        assert_eq!(dirty, vec![10..20, 30..40]);
+        mark_dirty(&mut dirty, 20..30);
+        assert_eq!(dirty, vec![10..40]);
+        for i in 0..MAX_DIRTY_RANGES {
+            mark_dirty(&mut dirty, 100 + i*10..101 + i*10);
+        }
+        assert_eq!(dirty, vec![10..(101 + (MAX_DIRTY_RANGES - 1)*10)]);
+    }
+
+    #[test]
+    fn only_triangles_are_instanced() {
+        let mut gfx = Gfx::new();
+        let program = gfx.add_program("", "");
+        gfx.add_line_vertex((0.0, 0.0));
+        gfx.add_line_vertex((0.5, 0.0));
+        let lines = gfx.add_indices(&[0, 1], PrimitiveType::LinesList);
+        let instances = gfx.add_instances(&[instances::GfxInstance::new((0.0, 0.0), 0.0, 1.0)]);
+        gfx.program(program).unwrap();
+        gfx.indices(lines).unwrap();
+        let draw = gfx.instanced_draw(instances).unwrap();
+        match gfx.validate() {
+            Err(GfxError::InstancedLines(handle)) => assert_eq!(handle.0, draw.0),
+            other                                 => panic!("{0:?}", other)
+        }
+
+        let mut backend = RecordingBackend::new(100, 100);
+        gfx.run(&mut backend);
+        assert!(backend.draws.is_empty());
+    }
+
+    #[test]
+    fn inserted_commands_go_in_front() {
+        let mut gfx = Gfx::new();
+        let first  = gfx.noop();
+        let second = gfx.noop();
+        let third  = gfx.noop();
+        gfx.insert_before(second).unwrap();
+        let inserted = [gfx.noop(), gfx.noop()];
+        gfx.skip(inserted[0]).unwrap();
+        let order = |gfx: &Gfx| {
+            [first, inserted[0], inserted[1], second, third].iter()
+                .map(|handle| gfx.position(*handle).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(order(&gfx), vec![0, 1, 2, 3, 4]);
+        gfx.append();
+        assert_eq!(order(&gfx), vec![0, 1, 2, 3, 4]);
+        assert_eq!(gfx.commands[1].flags, GFX_SKIP);
+        let last = gfx.noop();
+        assert_eq!(gfx.position(last).unwrap(), 5);
+    }
+
+    #[test]
+    fn freeing_where_commands_go_in_puts_them_on_the_end() {
+        let mut gfx = Gfx::new();
+        let first = gfx.noop();
+        gfx.noop();
+        gfx.insert_before(first).unwrap();
+        let inserted = gfx.noop();
+        gfx.free_commands(first, first).unwrap();
+        let after = gfx.noop();
+        assert_eq!(gfx.position(inserted).unwrap(), 0);
+        assert_eq!(gfx.position(after).unwrap(), 3);
+        assert_eq!(gfx.num_commands(), 3);
+    }
+
+    #[test]
+    fn num_commands_leaves_out_freed() {
+        let mut gfx = Gfx::new();
+        let first = gfx.noop();
+        let last  = gfx.noop();
+        gfx.noop();
+        gfx.free_commands(first, last).unwrap();
+        assert_eq!(gfx.num_commands(), 1);
+        gfx.compact();
+        assert_eq!(gfx.num_commands(), 1);
+    }
+
+    #[test]
+    fn take_free_first_fit() {
+        let mut free = vec![0..2, 5..10];
+        assert_eq!(take_free(&mut free, 3), Some(5..8));
+        assert_eq!(free, vec![0..2, 8..10]);
+        assert_eq!(take_free(&mut free, 2), Some(0..2));
+        assert_eq!(free, vec![8..10]);
+        assert_eq!(take_free(&mut free, 5), None);
+        assert_eq!(free, vec![8..10]);
+    }
+
+    #[test]
+    fn compact_backing_remaps_onto_the_next_live_vertex() {
+        let mut backing = vec![0, 1, 2, 3, 4, 5];
+        let mut free    = vec![1..3, 4..5];
+        assert_eq!(compact_backing(&mut backing, &mut free), Some(vec![0, 1, 1, 1, 2, 2, 3]));
+        assert_eq!(backing, vec![0, 3, 5]);
+        assert!(free.is_empty());
+        assert_eq!(compact_backing(&mut backing, &mut free), None);
+    }
 
+    #[test]
+    fn compact_moves_vertices_and_keeps_handles() {
+        let mut gfx = Gfx::new();
+        let freed = gfx.alloc_triangle_vertices(2);
+        let kept  = gfx.alloc_triangle_vertices(3);
+        let start = gfx.vertex_start(kept).unwrap() as u32;
+        let indices = gfx.add_indices(&[start, start + 1, start + 2], PrimitiveType::TrianglesList);
+        let gone = gfx.noop();
+        let draw = gfx.noop();
+        gfx.free_vertices(freed).unwrap();
+        gfx.free_commands(gone, gone).unwrap();
+
+        gfx.compact();
+        assert_eq!(gfx.triangle_len(), 3);
+        assert_eq!(gfx.vertex_start(kept).unwrap(), 0);
+        assert_eq!(gfx.index_data(indices).unwrap().data, vec![0, 1, 2]);
+        assert_eq!(gfx.position(draw).unwrap(), 0);
+        assert!(gfx.position(gone).is_err());
+    }
+
+    #[test]
+    fn only_moving_a_vertex_stales_bounds() {
+        let mut gfx = Gfx::new();
+        for i in 0..6 {
+            gfx.add_triangle_vertex((i as f32, 0.0), (1.0, 1.0, 1.0, 1.0));
+        }
+        let first  = gfx.add_indices(&[0, 1, 2], PrimitiveType::TrianglesList);
+        let second = gfx.add_indices(&[3, 4, 5], PrimitiveType::TrianglesList);
+        let mut backend = RecordingBackend::new(100, 100);
+        gfx.run(&mut backend);
+
+        gfx.set_triangle_color(1, (0.0, 0.0, 0.0, 1.0)).unwrap();
+        assert!(gfx.index_data(first).unwrap().bounds.is_some());
+        gfx.set_triangle_vertex(4, (4.0, 1.0), (1.0, 1.0, 1.0, 1.0)).unwrap();
+        assert!(gfx.index_data(first).unwrap().bounds.is_some());
+        assert!(gfx.index_data(second).unwrap().bounds.is_none());
+    }
+
+    #[test]
+    fn software_backend_keeps_what_it_cant_draw() {
+        let mut gfx = Gfx::new();
+        let program = gfx.add_program("", "");
+        gfx.add_line_vertex((0.0, 0.0));
+        gfx.add_line_vertex((0.5, 0.0));
+        let indices = gfx.add_indices(&[0, 1], PrimitiveType::Points);
+        gfx.program(program).unwrap();
+        gfx.indices(indices).unwrap();
+        gfx.line_draw();
+
+        let mut backend = software::SoftwareBackend::new(10, 10);
+        gfx.run(&mut backend);
+        assert_eq!(backend.errors, vec!["software backend can't draw lines as Points".to_string()]);
+    }
+
+    #[test]
+    fn offscreen_sizes_are_bounded() {
+        let mut gfx = Gfx::new();
+        let mut backend = software::SoftwareBackend::new(10, 10);
+        assert!(matches!(gfx.render_offscreen(&mut backend, 0, 10), Err(GfxError::OffscreenSize(0, 10))));
+        assert!(matches!(gfx.render_offscreen(&mut backend, 70000, 70000), Err(GfxError::OffscreenSize(..))));
+        let image = gfx.render_offscreen(&mut backend, 20, 5).unwrap().unwrap();
+        assert_eq!((image.width, image.height), (20, 5));
+        assert_eq!((backend.image().width, backend.image().height), (10, 10));
+    }
+}
@@ -0,0 +1,752 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use glium::index::PrimitiveType;
+
+use super::{CommandHandle, Gfx, GfxBounds, GfxCommandTypes, GfxIndices, GfxLineVertex,
+            GfxTriangleVertex, IndexHandle, InstanceHandle, ProgramHandle, mark_dirty, GFX_FREE, GFX_SKIP};
+use super::instances::GfxInstance;
+use super::state::{GfxBlend, GfxLineCap, GfxLineJoin, GfxLineStyle, GfxLineUnits, GfxRect, GfxStencil};
+use super::transform::GfxTransform;
+use super::viewport::{GfxView, GfxViewport};
+
+// Scene files are line based, one record per line, fields separated
+// by whitespace.  Floats are written with {:?} so they read back bit
+// for bit.  Bump the version if a record changes shape.
+const SCENE_HEADER: &str = "solartaxi-gfx 1";
+
+#[derive(Debug)]
+pub struct GfxParseError {
+    pub line:    usize,
+    pub message: String
+}
+
+impl fmt::Display for GfxParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {0}: {1}", self.line, self.message)
+    }
+}
+
+fn primitive_name(primitive_type: PrimitiveType) -> &'static str {
+    match primitive_type {
+        PrimitiveType::Points        => "points",
+        PrimitiveType::LinesList     => "lines_list",
+        PrimitiveType::LineStrip     => "line_strip",
+        PrimitiveType::LineLoop      => "line_loop",
+        PrimitiveType::TrianglesList => "triangles_list",
+        PrimitiveType::TriangleStrip => "triangle_strip",
+        PrimitiveType::TriangleFan   => "triangle_fan",
+        _                            => "unsupported"
+    }
+}
+
+fn primitive_from_name(name: &str) -> Option<PrimitiveType> {
+    match name {
+        "points"         => Some(PrimitiveType::Points),
+        "lines_list"     => Some(PrimitiveType::LinesList),
+        "line_strip"     => Some(PrimitiveType::LineStrip),
+        "line_loop"      => Some(PrimitiveType::LineLoop),
+        "triangles_list" => Some(PrimitiveType::TrianglesList),
+        "triangle_strip" => Some(PrimitiveType::TriangleStrip),
+        "triangle_fan"   => Some(PrimitiveType::TriangleFan),
+        _                => None
+    }
+}
+
+// shader source has to fit in one whitespace separated field, and
+// an empty string still needs something there to be a field at all
+fn escape(text: &str) -> String {
+    if text.is_empty() {
+        return "\\e".to_string();
+    }
+    let mut out = String::new();
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            ' '  => out.push_str("\\s"),
+            _    => out.push(c)
+        }
+    }
+    return out;
+}
+
+fn unescape(text: &str) -> Option<String> {
+    let mut out = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '\\' => out.push('\\'),
+            'n'  => out.push('\n'),
+            'r'  => out.push('\r'),
+            't'  => out.push('\t'),
+            's'  => out.push(' '),
+            'e'  => {},
+            _    => return None
+        }
+    }
+    return Some(out);
+}
+
+fn encode_command(command: &GfxCommandTypes) -> String {
+    match *command {
+        GfxCommandTypes::LineDraw           => "line_draw".to_string(),
+        GfxCommandTypes::TriangleDraw       => "triangle_draw".to_string(),
+        GfxCommandTypes::InstancedDraw(index) => format!("instanced_draw {0}", index.0),
+        GfxCommandTypes::NoOp               => "noop".to_string(),
+        GfxCommandTypes::Program(index)     => format!("program {0}", index.0),
+        GfxCommandTypes::Indices(index)     => format!("indices {0}", index.0),
+        GfxCommandTypes::Rotate(angle)      => format!("rotate {0:?}", angle),
+        GfxCommandTypes::ViewRotate(angle)  => format!("view_rotate {0:?}", angle),
+        GfxCommandTypes::SceneScale(scale)  => format!("scene_scale {0:?}", scale),
+        GfxCommandTypes::ObjectScale(scale) => format!("object_scale {0:?}", scale),
+        GfxCommandTypes::Translate { x, y } => format!("translate {0:?} {1:?}", x, y),
+        GfxCommandTypes::Origin { x, y }    => format!("origin {0:?} {1:?}", x, y),
+        GfxCommandTypes::Scale { x, y }     => format!("scale {0:?} {1:?}", x, y),
+        GfxCommandTypes::Shear { x, y }     => format!("shear {0:?} {1:?}", x, y),
+        GfxCommandTypes::Matrix(matrix)     => {
+            format!("matrix {0:?} {1:?} {2:?} {3:?} {4:?} {5:?}",
+                    matrix.m[0], matrix.m[1], matrix.m[2], matrix.m[3], matrix.m[4], matrix.m[5])
+        },
+        GfxCommandTypes::PushTransform      => "push_transform".to_string(),
+        GfxCommandTypes::PopTransform       => "pop_transform".to_string(),
+        GfxCommandTypes::Blend(blend)       => format!("blend {0}", blend_name(blend)),
+        GfxCommandTypes::LineWidth(width)   => format!("line_width {0:?}", width),
+        GfxCommandTypes::LineStyle(None)    => "line_style off".to_string(),
+        GfxCommandTypes::LineStyle(Some(style)) => {
+            format!("line_style {0:?} {1} {2} {3}", style.width, 
+                    units_name(style.units), join_name(style.join), cap_name(style.cap))
+        },
+        GfxCommandTypes::Scissor(None)      => "scissor off".to_string(),
+        GfxCommandTypes::Scissor(Some(rect)) => {
+            format!("scissor {0:?} {1:?} {2:?} {3:?}", rect.left, rect.bottom, rect.width, rect.height)
+        },
+        GfxCommandTypes::Stencil(GfxStencil::Off)             => "stencil off".to_string(),
+        GfxCommandTypes::Stencil(GfxStencil::Write(value))    => format!("stencil write {0}", value),
+        GfxCommandTypes::Stencil(GfxStencil::Equal(value))    => format!("stencil equal {0}", value),
+        GfxCommandTypes::Stencil(GfxStencil::NotEqual(value)) => format!("stencil not_equal {0}", value),
+        GfxCommandTypes::Layer(layer)       => format!("layer {0}", layer),
+        GfxCommandTypes::Tint(tint)         => format!("tint {0:?} {1:?} {2:?}", tint[0], tint[1], tint[2]),
+        GfxCommandTypes::Alpha(alpha)       => format!("alpha {0:?}", alpha)
+    }
+}
+
+fn blend_name(blend: GfxBlend) -> &'static str {
+    match blend {
+        GfxBlend::Alpha    => "alpha",
+        GfxBlend::Additive => "additive",
+        GfxBlend::Replace  => "replace"
+    }
+}
+
+fn blend_from_name(name: &str) -> Option<GfxBlend> {
+    match name {
+        "alpha"    => Some(GfxBlend::Alpha),
+        "additive" => Some(GfxBlend::Additive),
+        "replace"  => Some(GfxBlend::Replace),
+        _          => None
+    }
+}
+
+fn units_name(units: GfxLineUnits) -> &'static str {
+    match units {
+        GfxLineUnits::World  => "world",
+        GfxLineUnits::Pixels => "pixels"
+    }
+}
+
+fn join_name(join: GfxLineJoin) -> &'static str {
+    match join {
+        GfxLineJoin::Miter => "miter",
+        GfxLineJoin::Bevel => "bevel",
+        GfxLineJoin::Round => "round"
+    }
+}
+
+fn cap_name(cap: GfxLineCap) -> &'static str {
+    match cap {
+        GfxLineCap::Butt   => "butt",
+        GfxLineCap::Square => "square",
+        GfxLineCap::Round  => "round"
+    }
+}
+
+fn line_style_from_names(width: f32, units: &str, join: &str, cap: &str) -> Option<GfxLineStyle> {
+    let units = match units {
+        "world"  => GfxLineUnits::World,
+        "pixels" => GfxLineUnits::Pixels,
+        _        => return None
+    };
+    let join = match join {
+        "miter" => GfxLineJoin::Miter,
+        "bevel" => GfxLineJoin::Bevel,
+        "round" => GfxLineJoin::Round,
+        _       => return None
+    };
+    let cap = match cap {
+        "butt"   => GfxLineCap::Butt,
+        "square" => GfxLineCap::Square,
+        "round"  => GfxLineCap::Round,
+        _        => return None
+    };
+    return Some(GfxLineStyle { width: width, units: units, join: join, cap: cap });
+}
+
+struct Fields<'a> {
+    line:   usize,
+    fields: std::str::SplitWhitespace<'a>
+}
+
+impl<'a> Fields<'a> {
+    fn error<T>(&self, message: String) -> Result<T, GfxParseError> {
+        return Err(GfxParseError { line: self.line, message: message });
+    }
+
+    fn word(&mut self) -> Result<&'a str, GfxParseError> {
+        match self.fields.next() {
+            Some(word) => return Ok(word),
+            None       => return self.error("missing field".to_string())
+        }
+    }
+
+    fn float(&mut self) -> Result<f32, GfxParseError> {
+        let word = self.word()?;
+        match word.parse::<f32>() {
+            Ok(value) => return Ok(value),
+            Err(_)    => return self.error(format!("bad number {0}", word))
+        }
+    }
+
+    fn int(&mut self) -> Result<usize, GfxParseError> {
+        let word = self.word()?;
+        match word.parse::<usize>() {
+            Ok(value) => return Ok(value),
+            Err(_)    => return self.error(format!("bad index {0}", word))
+        }
+    }
+
+    // indices and flags, which are u32 wherever they end up
+    fn int32(&mut self) -> Result<u32, GfxParseError> {
+        let word = self.word()?;
+        match word.parse::<u64>().ok().and_then(|value| u32::try_from(value).ok()) {
+            Some(value) => return Ok(value),
+            None        => return self.error(format!("bad index {0}", word))
+        }
+    }
+
+    fn stencil_value(&mut self) -> Result<u8, GfxParseError> {
+        let word = self.word()?;
+        match word.parse::<u8>() {
+            Ok(value) => return Ok(value),
+            Err(_)    => return self.error(format!("bad stencil value {0}", word))
+        }
+    }
+
+    fn text(&mut self) -> Result<String, GfxParseError> {
+        let word = self.word()?;
+        match unescape(word) {
+            Some(text) => return Ok(text),
+            None       => return self.error("bad escape in text".to_string())
+        }
+    }
+
+    fn done(&mut self) -> Result<(), GfxParseError> {
+        match self.fields.next() {
+            None        => return Ok(()),
+            Some(extra) => return self.error(format!("unexpected {0}", extra))
+        }
+    }
+}
+
+impl Gfx {
+    // one line per command with whatever buffers it points at, for
+    // people to read and diff
+    pub fn dump_text(&self) -> String {
+        let mut out = String::new();
+        let mut cur_program = 0usize;
+        let mut cur_indices = 0usize;
+
+        out.push_str(&format!("{0} programs, {1} index buffers, {2} line vertices, {3} triangle vertices\n",
+                              self.programs.len(), self.num_indices(),
+                              self.line_backing.len(), self.triangle_backing.len()));
+        for (i, command) in self.ordered().enumerate() {
+            let skip = if command.flags & GFX_FREE != 0 { 
+                "free" 
+            } else if command.flags & GFX_SKIP != 0 { 
+                "skip" 
+            } else { 
+                "    " 
+            };
+            let mut line = format!("{0:5} {1} {2}", i, skip, command.describe());
+            match command.command {
+                GfxCommandTypes::Program(index) => {
+                    cur_program = index.0;
+                },
+                GfxCommandTypes::Indices(index) => {
+                    cur_indices = index.0;
+                    if let Ok(indices) = self.index_data(index) {
+                        line.push_str(&format!(" ({0}, {1} indices)",
+                                               primitive_name(indices.primitive_type),
+                                               indices.data.len()));
+                    }
+                },
+                GfxCommandTypes::LineDraw => {
+                    line.push_str(&format!(" (program {0}, indices {1}, line vertices)",
+                                           cur_program, cur_indices));
+                },
+                GfxCommandTypes::TriangleDraw => {
+                    line.push_str(&format!(" (program {0}, indices {1}, triangle vertices)",
+                                           cur_program, cur_indices));
+                },
+                GfxCommandTypes::InstancedDraw(index) => {
+                    if let Ok(instances) = self.instance_data(index) {
+                        line.push_str(&format!(" (program {0}, indices {1}, {2} instances)",
+                                               cur_program, cur_indices, instances.len()));
+                    }
+                },
+                _ => {}
+            }
+            out.push_str(&line);
+            out.push('\n');
+        }
+        return out;
+    }
+
+    pub fn print_commands(&self) {
+        print!("{0}", self.dump_text());
+    }
+
+    // everything needed to rebuild this Gfx with Gfx::load
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        out.push_str(SCENE_HEADER);
+        out.push('\n');
+        for program in &self.programs {
+            out.push_str(&format!("program {0} {1}\n",
+                                  escape(&program.vert_shader),
+                                  escape(&program.frag_shader)));
+        }
+        // freed index buffers keep their place so the ids line up
+        for indices in &self.indices {
+            let indices = match indices {
+                Some(indices) => indices,
+                None          => {
+                    out.push_str("free_indices\n");
+                    continue;
+                }
+            };
+            out.push_str(&format!("indices {0} {1}",
+                                  primitive_name(indices.primitive_type),
+                                  indices.data.len()));
+            for index in &indices.data {
+                out.push_str(&format!(" {0}", index));
+            }
+            out.push('\n');
+        }
+        for (id, indices) in self.indices.iter().enumerate() {
+            if let Some(GfxIndices { bounds: Some(bounds), fixed_bounds: true, .. }) = indices {
+                out.push_str(&format!("bounds {0} {1:?} {2:?} {3:?}\n",
+                                      id, bounds.center[0], bounds.center[1], bounds.radius));
+            }
+        }
+        // position angle scale and tint for each instance
+        for instances in &self.instances {
+            let instances = match instances {
+                Some(instances) => instances,
+                None            => {
+                    out.push_str("free_instances\n");
+                    continue;
+                }
+            };
+            out.push_str(&format!("instances {0}", instances.len()));
+            for instance in instances {
+                out.push_str(&format!(" {0:?} {1:?} {2:?} {3:?} {4:?} {5:?} {6:?}",
+                                      instance.position[0], instance.position[1],
+                                      instance.angle, instance.scale,
+                                      instance.tint[0], instance.tint[1], instance.tint[2]));
+            }
+            out.push('\n');
+        }
+        for vertex in &self.line_backing {
+            out.push_str(&format!("line_vertex {0:?} {1:?}\n",
+                                  vertex.position[0], vertex.position[1]));
+        }
+        for vertex in &self.triangle_backing {
+            out.push_str(&format!("triangle_vertex {0:?} {1:?} {2:?} {3:?} {4:?} {5:?}\n",
+                                  vertex.position[0], vertex.position[1],
+                                  vertex.color[0], vertex.color[1], vertex.color[2], vertex.color[3]));
+        }
+        for command in self.ordered().filter(|command| command.flags & GFX_FREE == 0) {
+            out.push_str(&format!("command {0} {1}\n",
+                                  command.flags,
+                                  encode_command(&command.command)));
+        }
+        // command ranges go by where they are among the commands above
+        let live = |handle: CommandHandle| {
+            self.position(handle).map(|position| {
+                self.ordered().take(position).filter(|command| command.flags & GFX_FREE == 0).count()
+            })
+        };
+        for viewport in &self.viewports {
+            out.push_str(&format!("viewport {0} {1:?} {2:?} {3:?} {4:?}",
+                                  escape(&viewport.name),
+                                  viewport.rect.left, viewport.rect.bottom,
+                                  viewport.rect.width, viewport.rect.height));
+            match viewport.view {
+                Some(view) => out.push_str(&format!(" view {0:?} {1:?} {2:?} {3:?}",
+                                                    view.origin.0, view.origin.1, view.scale, view.rotation)),
+                None       => out.push_str(" list_view")
+            }
+            // run() leaves out one whose commands have been freed, so
+            // it's written as one that draws nothing
+            match viewport.commands.map(|(first, last)| (live(first), live(last))) {
+                None                        => out.push_str(" all_commands\n"),
+                Some((Ok(first), Ok(last))) => out.push_str(&format!(" commands {0} {1}\n", first, last)),
+                Some(_)                     => out.push_str(" no_commands\n")
+            }
+        }
+        return out;
+    }
+
+    pub fn load(text: &str) -> Result<Gfx, GfxParseError> {
+        let mut gfx = Gfx::new();
+        let mut lines = text.lines().enumerate();
+        let mut handles   = Vec::new();
+        // the line each command came from, for complaining about it
+        let mut command_lines = Vec::new();
+        // files from before viewports get the usual "main"
+        let mut viewports = Vec::new();
+
+        match lines.next() {
+            Some((_, header)) if header.trim() == SCENE_HEADER => {},
+            _ => return Err(GfxParseError { line:    1,
+                                            message: format!("expected \"{0}\"", SCENE_HEADER) })
+        }
+
+        for (i, line) in lines {
+            let mut fields = Fields { line: i + 1, fields: line.split_whitespace() };
+            let record = match fields.fields.next() {
+                None         => continue,
+                Some(record) => record
+            };
+            match record {
+                "program" => {
+                    let vert_shader = fields.text()?;
+                    let frag_shader = fields.text()?;
+                    gfx.add_program(&vert_shader, &frag_shader);
+                },
+                "indices" => {
+                    let name = fields.word()?;
+                    let primitive_type = match primitive_from_name(name) {
+                        Some(primitive_type) => primitive_type,
+                        None => return fields.error(format!("unknown primitive type {0}", name))
+                    };
+                    // pushed as they're read, so a count that's far too
+                    // big runs out of fields rather than memory
+                    let count = fields.int()?;
+                    let mut data = Vec::new();
+                    for _ in 0..count {
+                        data.push(fields.int32()?);
+                    }
+                    gfx.indices_pending.push(gfx.indices.len());
+                    gfx.indices.push(Some(GfxIndices::new(data, primitive_type)));
+                },
+                "free_indices" => {
+                    gfx.indices.push(None);
+                    gfx.free_index_ids.push(gfx.indices.len() - 1);
+                },
+                "bounds" => {
+                    let id     = fields.int()?;
+                    let bounds = GfxBounds { center: [fields.float()?, fields.float()?],
+                                             radius: fields.float()? };
+                    if let Err(error) = gfx.set_bounds(IndexHandle(id), Some(bounds)) {
+                        return fields.error(error.to_string());
+                    }
+                },
+                "instances" => {
+                    let count = fields.int()?;
+                    let mut data = Vec::new();
+                    for _ in 0..count {
+                        data.push(GfxInstance { position: [fields.float()?, fields.float()?],
+                                                angle:    fields.float()?,
+                                                scale:    fields.float()?,
+                                                tint:     [fields.float()?, fields.float()?, fields.float()?] });
+                    }
+                    gfx.instances_pending.push(gfx.instances.len());
+                    gfx.instances.push(Some(data));
+                },
+                "free_instances" => {
+                    gfx.instances.push(None);
+                    gfx.free_instance_ids.push(gfx.instances.len() - 1);
+                },
+                "line_vertex" => {
+                    let x = fields.float()?;
+                    let y = fields.float()?;
+                    gfx.line_backing.push(GfxLineVertex { position: [x, y] });
+                },
+                "triangle_vertex" => {
+                    let x = fields.float()?;
+                    let y = fields.float()?;
+                    let color = [fields.float()?, fields.float()?, fields.float()?, fields.float()?];
+                    gfx.triangle_backing.push(GfxTriangleVertex { position: [x, y], color: color });
+                },
+                "command" => {
+                    let flags = fields.int32()?;
+                    let name  = fields.word()?;
+                    let command = match name {
+                        "line_draw"     => GfxCommandTypes::LineDraw,
+                        "triangle_draw" => GfxCommandTypes::TriangleDraw,
+                        "instanced_draw" => GfxCommandTypes::InstancedDraw(InstanceHandle(fields.int()?)),
+                        "noop"          => GfxCommandTypes::NoOp,
+                        "program"       => GfxCommandTypes::Program(ProgramHandle(fields.int()?)),
+                        "indices"       => GfxCommandTypes::Indices(IndexHandle(fields.int()?)),
+                        "rotate"        => GfxCommandTypes::Rotate(fields.float()?),
+                        "view_rotate"   => GfxCommandTypes::ViewRotate(fields.float()?),
+                        "scene_scale"   => GfxCommandTypes::SceneScale(fields.float()?),
+                        "object_scale"  => GfxCommandTypes::ObjectScale(fields.float()?),
+                        "translate"     => GfxCommandTypes::Translate { x: fields.float()?, y: fields.float()? },
+                        "origin"        => GfxCommandTypes::Origin { x: fields.float()?, y: fields.float()? },
+                        "scale"         => GfxCommandTypes::Scale { x: fields.float()?, y: fields.float()? },
+                        "shear"         => GfxCommandTypes::Shear { x: fields.float()?, y: fields.float()? },
+                        "matrix"        => {
+                            let mut m = [0.0; 6];
+                            for value in m.iter_mut() {
+                                *value = fields.float()?;
+                            }
+                            GfxCommandTypes::Matrix(GfxTransform { m: m })
+                        },
+                        "push_transform" => GfxCommandTypes::PushTransform,
+                        "pop_transform"  => GfxCommandTypes::PopTransform,
+                        "blend"          => {
+                            let name = fields.word()?;
+                            match blend_from_name(name) {
+                                Some(blend) => GfxCommandTypes::Blend(blend),
+                                None        => return fields.error(format!("unknown blend {0}", name))
+                            }
+                        },
+                        "line_width"     => GfxCommandTypes::LineWidth(fields.float()?),
+                        "line_style"     => {
+                            match fields.word()? {
+                                "off" => GfxCommandTypes::LineStyle(None),
+                                width => {
+                                    let width = match width.parse::<f32>() {
+                                        Ok(width) => width,
+                                        Err(_)    => return fields.error(format!("bad number {0}", width))
+                                    };
+                                    let (units, join, cap) = (fields.word()?, fields.word()?, fields.word()?);
+                                    match line_style_from_names(width, units, join, cap) {
+                                        Some(style) => GfxCommandTypes::LineStyle(Some(style)),
+                                        None        => return fields.error(format!("unknown line style {0} {1} {2}", 
+                                                                                   units, join, cap))
+                                    }
+                                }
+                            }
+                        },
+                        "layer"          => {
+                            let word = fields.word()?;
+                            match word.parse::<i32>() {
+                                Ok(layer) => GfxCommandTypes::Layer(layer),
+                                Err(_)    => return fields.error(format!("bad layer {0}", word))
+                            }
+                        },
+                        "tint"           => GfxCommandTypes::Tint([fields.float()?, fields.float()?, fields.float()?]),
+                        "alpha"          => GfxCommandTypes::Alpha(fields.float()?),
+                        "scissor"        => {
+                            match fields.word()? {
+                                "off" => GfxCommandTypes::Scissor(None),
+                                left  => {
+                                    let left = match left.parse::<f32>() {
+                                        Ok(left) => left,
+                                        Err(_)   => return fields.error(format!("bad number {0}", left))
+                                    };
+                                    GfxCommandTypes::Scissor(Some(GfxRect::new(left, fields.float()?, 
+                                                                               fields.float()?, fields.float()?)))
+                                }
+                            }
+                        },
+                        "stencil"        => {
+                            match fields.word()? {
+                                "off"       => GfxCommandTypes::Stencil(GfxStencil::Off),
+                                "write"     => GfxCommandTypes::Stencil(GfxStencil::Write(fields.stencil_value()?)),
+                                "equal"     => GfxCommandTypes::Stencil(GfxStencil::Equal(fields.stencil_value()?)),
+                                "not_equal" => GfxCommandTypes::Stencil(GfxStencil::NotEqual(fields.stencil_value()?)),
+                                other       => return fields.error(format!("unknown stencil {0}", other))
+                            }
+                        },
+                        _ => return fields.error(format!("unknown command {0}", name))
+                    };
+                    if flags & GFX_FREE != 0 {
+                        return fields.error("freed command".to_string());
+                    }
+                    let handle = gfx.push(command);
+                    if let Ok(command) = gfx.command_mut(handle) {
+                        command.flags = flags;
+                    }
+                    handles.push(handle);
+                    command_lines.push(i + 1);
+                },
+                "viewport" => {
+                    let name = fields.text()?;
+                    let mut viewport = GfxViewport::new(&name, GfxRect::new(fields.float()?, fields.float()?,
+                                                                            fields.float()?, fields.float()?));
+                    viewport.view = match fields.word()? {
+                        "list_view" => None,
+                        "view"      => Some(GfxView::new((fields.float()?, fields.float()?),
+                                                         fields.float()?, fields.float()?)),
+                        other       => return fields.error(format!("unknown view {0}", other))
+                    };
+                    viewport.commands = match fields.word()? {
+                        "all_commands" => None,
+                        "commands"     => {
+                            let first = fields.int()?;
+                            let last  = fields.int()?;
+                            match (handles.get(first), handles.get(last)) {
+                                (Some(first), Some(last)) => Some((*first, *last)),
+                                _ => return fields.error(format!("no commands {0} to {1}", first, last))
+                            }
+                        },
+                        // a handle that never was a command, so it's skipped
+                        // the same as one that's been freed
+                        "no_commands"  => {
+                            let none = CommandHandle(gfx.command_slots.len());
+                            gfx.command_slots.push(None);
+                            Some((none, none))
+                        },
+                        other => return fields.error(format!("unknown commands {0}", other))
+                    };
+                    viewports.push(viewport);
+                },
+                _ => return fields.error(format!("unknown record {0}", record))
+            }
+            fields.done()?;
+        }
+
+        if !viewports.is_empty() {
+            gfx.viewports = viewports;
+        }
+        // nothing's been freed, so commands are where they were read
+        if let Err((position, error)) = gfx.check_commands() {
+            return Err(GfxParseError { line:    command_lines.get(position).cloned().unwrap_or(0),
+                                       message: error.to_string() });
+        }
+        mark_dirty(&mut gfx.line_dirty, 0..gfx.line_backing.len());
+        mark_dirty(&mut gfx.triangle_dirty, 0..gfx.triangle_backing.len());
+        return Ok(gfx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::state::GfxLineUnits;
+    use super::super::viewport::GfxView;
+
+    // a bit of everything dump() writes, including an empty shader
+    fn scene() -> Gfx {
+        let mut gfx = Gfx::new();
+        let lines     = gfx.add_program("void main() {}\n", "");
+        let triangles = gfx.add_program("line one\n\tline two", "back\\slash");
+        gfx.add_line_vertex((0.0, 1.0));
+        gfx.add_line_vertex((0.1, -2.5));
+        gfx.add_triangle_vertex((1.0, 2.0), (0.1, 0.2, 0.3, 1.0));
+        gfx.add_triangle_vertex((3.0, 4.0), (0.4, 0.5, 0.6, 0.5));
+        gfx.add_triangle_vertex((5.0, 6.0), (0.7, 0.8, 0.9, 0.0));
+        let outline = gfx.add_indices(&[0, 1], PrimitiveType::LinesList);
+        let freed   = gfx.add_indices(&[0, 1], PrimitiveType::LineStrip);
+        let shape   = gfx.add_indices(&[0, 1, 2], PrimitiveType::TrianglesList);
+        gfx.free_indices(freed).unwrap();
+        let copies  = gfx.add_instances(&[GfxInstance::new((1.0, 2.0), 0.5, 2.0)]);
+
+        let first = gfx.program(triangles).unwrap();
+        gfx.layer(3);
+        gfx.blend(GfxBlend::Additive);
+        gfx.scissor(Some(GfxRect::new(0.1, 0.2, 0.3, 0.4)));
+        gfx.stencil(GfxStencil::NotEqual(7));
+        gfx.push_transform();
+        gfx.translate(1.5, -2.0);
+        gfx.rotate(0.25);
+        gfx.tint(0.5, 0.6, 0.7);
+        gfx.alpha(0.8);
+        gfx.indices(shape).unwrap();
+        gfx.triangle_draw();
+        gfx.instanced_draw(copies).unwrap();
+        gfx.pop_transform();
+        gfx.program(lines).unwrap();
+        gfx.line_style(Some(GfxLineStyle::new(3.0, GfxLineUnits::Pixels)));
+        gfx.indices(outline).unwrap();
+        let last = gfx.line_draw();
+        gfx.set_viewport("radar", GfxRect::new(0.7, 0.0, 0.3, 0.3));
+        gfx.set_viewport_view("radar", Some(GfxView::new((1.0, 2.0), 0.5, 0.1))).unwrap();
+        gfx.set_viewport_commands("radar", Some((first, last))).unwrap();
+        return gfx;
+    }
+
+    #[test]
+    fn escape_round_trip() {
+        for text in ["", " ", "a b\tc\r\n", "\\s", "\\"].iter() {
+            let escaped = escape(text);
+            assert!(!escaped.is_empty() && !escaped.contains(char::is_whitespace));
+            assert_eq!(unescape(&escaped).as_deref(), Some(*text));
+        }
+    }
+
+    #[test]
+    fn dump_load_dump() {
+        let text   = scene().dump();
+        let loaded = Gfx::load(&text).unwrap();
+        assert_eq!(loaded.programs[0].frag_shader, "");
+        assert_eq!(loaded.programs[1].frag_shader, "back\\slash");
+        assert_eq!(loaded.dump(), text);
+    }
+
+    #[test]
+    fn freed_viewport_commands_stay_skipped() {
+        let mut gfx = scene();
+        let first = gfx.noop();
+        let last  = gfx.noop();
+        gfx.set_viewport("gone", GfxRect::new(0.0, 0.0, 0.5, 0.5));
+        gfx.set_viewport_commands("gone", Some((first, last))).unwrap();
+        gfx.free_commands(first, first).unwrap();
+
+        let text = gfx.dump();
+        assert!(text.contains(" no_commands\n"));
+        let loaded = Gfx::load(&text).unwrap();
+        let gone = loaded.viewports().iter().find(|viewport| viewport.name == "gone").unwrap();
+        assert_eq!(loaded.viewport_range(gone), None);
+        assert_eq!(loaded.dump(), text);
+    }
+
+    fn load_error(text: &str) -> GfxParseError {
+        match Gfx::load(&format!("{0}\n{1}", SCENE_HEADER, text)) {
+            Ok(_)      => panic!("loaded {0}", text),
+            Err(error) => return error
+        }
+    }
+
+    #[test]
+    fn huge_counts_run_out_of_fields() {
+        assert_eq!(load_error("indices lines_list 18446744073709551615 0 1").line, 2);
+        assert_eq!(load_error("instances 18446744073709551615 1.0").line, 2);
+    }
+
+    #[test]
+    fn indices_and_flags_fit_u32() {
+        assert_eq!(load_error("indices lines_list 1 4294967296").message, "bad index 4294967296");
+        assert_eq!(load_error("command 4294967296 noop").message, "bad index 4294967296");
+        assert!(Gfx::load(&format!("{0}\nindices lines_list 1 4294967295", SCENE_HEADER)).is_ok());
+    }
+
+    #[test]
+    fn validation_errors_have_lines() {
+        let error = load_error("command 0 noop\ncommand 0 pop_transform");
+        assert_eq!(error.line, 3);
+        let error = load_error("command 0 noop\n\ncommand 0 program 4");
+        assert_eq!(error.line, 4);
+    }
+}
@@ -0,0 +1,469 @@
+use glium::Surface;
+
+use std::ops::Range;
+
+use super::image::Image;
+use super::instances::GfxInstance;
+use super::{check_offscreen_size, GfxDrawType, GfxError, GfxIndices, GfxLineVertex,
+            GfxProgram, ProgramHandle, GfxTriangleVertex, GfxUniforms, RenderBackend};
+use super::shaders::{GfxProgramError, GfxShaderStage};
+use super::state::{GfxBlend, GfxDrawState, GfxRect, GfxStencil};
+
+implement_vertex!(GfxLineVertex, position);
+implement_vertex!(GfxTriangleVertex, position, color);
+
+// a GfxInstance as the per instance attributes of
+// shaders/triangle_instanced.vert, named so they can't clash with the
+// per vertex ones or the uniforms
+#[derive(Copy, Clone)]
+struct InstanceAttributes {
+    instance_position: [f32; 2],
+    instance_angle:    f32,
+    instance_scale:    f32,
+    instance_tint:     [f32; 3]
+}
+
+implement_vertex!(InstanceAttributes, instance_position, instance_angle, instance_scale, instance_tint);
+
+const NO_INSTANCE: InstanceAttributes = InstanceAttributes { instance_position: [0.0, 0.0],
+                                                             instance_angle:    0.0,
+                                                             instance_scale:    1.0,
+                                                             instance_tint:     [1.0, 1.0, 1.0] };
+
+pub struct GliumBackend {
+    display:           glium::Display,
+    programs:          Vec< Option<glium::Program> >,
+    indices:           Vec< Option<glium::IndexBuffer<u32>> >,
+    instances:         Vec< Option<glium::VertexBuffer<InstanceAttributes>> >,
+    // without gl 3.3 or ARB_instanced_arrays the instances are kept
+    // here instead and drawn one at a time, folded into the uniforms,
+    // with no_instance standing in for the attributes at every vertex
+    instancing:        bool,
+    instance_copies:   Vec< Option<Vec< GfxInstance >> >,
+    no_instance:       Option<glium::VertexBuffer<InstanceAttributes>>,
+    line_vertices:     Option<glium::VertexBuffer<GfxLineVertex>>,
+    triangle_vertices: Option<glium::VertexBuffer<GfxTriangleVertex>>,
+    // every thick line draw in a frame, one after another from the
+    // start, grown when a frame needs more
+    thick_vertices:    Option<glium::VertexBuffer<GfxLineVertex>>,
+    thick_used:        usize,
+    target:            Option<glium::Frame>,
+    offscreen:         Option<Offscreen>
+}
+
+// what gets drawn into instead of the window while set_offscreen is on
+struct Offscreen {
+    color:   glium::texture::Texture2d,
+    stencil: glium::framebuffer::StencilRenderBuffer
+}
+
+// glium reads back bottom row first
+fn to_image(raw: glium::texture::RawImage2d<u8>) -> Image {
+    let mut image = Image::new(raw.width, raw.height);
+    let row = (raw.width * 4) as usize;
+    for y in 0..raw.height as usize {
+        let from = (raw.height as usize - 1 - y) * row;
+        image.pixels[y*row..(y+1)*row].copy_from_slice(&raw.data[from..from+row]);
+    }
+    return image;
+}
+
+fn store<T>(slots: &mut Vec< Option<T> >, id: usize, item: T) {
+    if slots.len() <= id {
+        slots.resize_with(id + 1, || None);
+    }
+    slots[id] = Some(item);
+}
+
+// vertex buffers are dynamic and grow in steps, so appending a few
+// vertices or changing some in place only sends the changed range
+fn update_vertices<T: glium::Vertex>(display: &glium::Display,
+                                     buffer: &mut Option<glium::VertexBuffer<T>>,
+                                     vertices: &[T],
+                                     dirty: Range<usize>) {
+    let capacity = match buffer {
+        Some(buffer) => buffer.len(),
+        None         => 0
+    };
+    if vertices.len() > capacity {
+        let mut grown = glium::VertexBuffer::empty_dynamic(display, vertices.len().max(capacity*2)).unwrap();
+        grown.slice_mut(0..vertices.len()).unwrap().write(vertices);
+        *buffer = Some(grown);
+    } else if let Some(buffer) = buffer {
+        if dirty.start < dirty.end {
+            buffer.slice_mut(dirty.clone()).unwrap().write(&vertices[dirty]);
+        }
+    }
+}
+
+// instances are per instance if there's instancing, otherwise they're
+// no_instance and go with every vertex
+fn draw_vertices<S, T, U>(target: &mut S,
+                          vertices: &glium::VertexBuffer<T>,
+                          instances: Option<&glium::VertexBuffer<InstanceAttributes>>,
+                          instancing: bool,
+                          indices: &glium::IndexBuffer<u32>,
+                          program: &glium::Program,
+                          uniforms: &U,
+                          params: &glium::DrawParameters) 
+    where S: Surface, T: Copy + glium::Vertex, U: glium::uniforms::Uniforms {
+    match instances {
+        None                          => target.draw(vertices, indices, program, uniforms, params).unwrap(),
+        Some(instances) if instancing => target.draw((vertices, instances.per_instance().unwrap()),
+                                                     indices, program, uniforms, params).unwrap(),
+        Some(instances)               => target.draw((vertices, instances),
+                                                     indices, program, uniforms, params).unwrap()
+    }
+}
+
+// enough of NO_INSTANCE to go alongside count vertices
+fn cover_vertices(display: &glium::Display,
+                  buffer: &mut Option<glium::VertexBuffer<InstanceAttributes>>,
+                  count: usize) {
+    let capacity = buffer.as_ref().map_or(0, |buffer| buffer.len());
+    if count > capacity {
+        *buffer = Some(glium::VertexBuffer::new(display, &vec![NO_INSTANCE; count.max(capacity*2)]).unwrap());
+    }
+}
+
+fn blend(blend: GfxBlend) -> glium::Blend {
+    match blend {
+        GfxBlend::Alpha    => glium::Blend::alpha_blending(),
+        GfxBlend::Additive => {
+            let add = glium::BlendingFunction::Addition { 
+                source:      glium::LinearBlendingFactor::SourceAlpha,
+                destination: glium::LinearBlendingFactor::One 
+            };
+            glium::Blend { color: add, alpha: add, constant_value: (0.0, 0.0, 0.0, 0.0) }
+        },
+        GfxBlend::Replace  => Default::default()
+    }
+}
+
+// same test and operation for both windings, the 2d geometry comes
+// either way round
+fn stencil(stencil: GfxStencil) -> glium::draw_parameters::Stencil {
+    use glium::{StencilOperation, StencilTest};
+    let (test, value, operation) = match stencil {
+        GfxStencil::Off             => return Default::default(),
+        GfxStencil::Write(value)    => (StencilTest::AlwaysPass, value, StencilOperation::Replace),
+        GfxStencil::Equal(value)    => (StencilTest::IfEqual { mask: 0xff }, value, StencilOperation::Keep),
+        GfxStencil::NotEqual(value) => (StencilTest::IfNotEqual { mask: 0xff }, value, StencilOperation::Keep)
+    };
+    glium::draw_parameters::Stencil {
+        test_clockwise:                         test,
+        reference_value_clockwise:              value as i32,
+        depth_pass_operation_clockwise:         operation,
+        test_counter_clockwise:                 test,
+        reference_value_counter_clockwise:      value as i32,
+        depth_pass_operation_counter_clockwise: operation,
+        ..Default::default()
+    }
+}
+
+impl GliumBackend {
+    pub fn new(display: &glium::Display) -> GliumBackend {
+        // glium's own test, which per_instance() fails on
+        let instancing = glium::VertexBuffer::new(display, &[NO_INSTANCE]).unwrap().per_instance().is_ok();
+        GliumBackend { display:           display.clone(),
+                       programs:          Vec::new(),
+                       indices:           Vec::new(),
+                       instances:         Vec::new(),
+                       instancing:        instancing,
+                       instance_copies:   Vec::new(),
+                       no_instance:       None,
+                       line_vertices:     None,
+                       triangle_vertices: None,
+                       thick_vertices:    None,
+                       thick_used:        0,
+                       target:            None,
+                       offscreen:         None }
+    }
+}
+
+impl GliumBackend {
+    fn draw_to<S: Surface>(&self,
+                           target: &mut S,
+                           draw_type: GfxDrawType,
+                           program: usize,
+                           indices: usize,
+                           instances: Option<usize>,
+                           thick: Option<Range<usize>>,
+                           uniforms: &GfxUniforms,
+                           state: &GfxDrawState) {
+        let (width, height) = target.get_dimensions();
+        let to_rect = |rect: GfxRect| {
+            let (left, bottom, width, height) = rect.to_pixels(width, height);
+            glium::Rect { left: left, bottom: bottom, width: width, height: height }
+        };
+        let params = glium::DrawParameters {
+            blend: blend(state.blend),
+            multisampling: true,
+            line_width: Some(state.line_width),
+            viewport: Some(to_rect(state.viewport)),
+            // wide lines can spill out of the viewport, so it's
+            // scissored to as well
+            scissor: state.clip().map(to_rect),
+            stencil: stencil(state.stencil),
+
+            ..Default::default()
+        };
+        let uniforms = uniform! {transform: uniforms.transform.to_mat3(),
+                                 tint:      uniforms.tint,
+                                 alpha:     uniforms.alpha};
+        let program      = match self.programs.get(program) {
+            Some(Some(program)) => program,
+            _                   => return
+        };
+        if let Some(range) = thick {
+            if let Some(vertices) = self.thick_vertices.as_ref().and_then(|buffer| buffer.slice(range)) {
+                target.draw(vertices, glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList),
+                            program, &uniforms, &params).unwrap();
+            }
+            return;
+        }
+        let index_buffer = self.indices[indices].as_ref().unwrap();
+        let instances = match instances {
+            None                        => None,
+            Some(_) if !self.instancing => self.no_instance.as_ref(),
+            Some(id)                    => match self.instances.get(id) {
+                Some(Some(buffer)) => Some(buffer),
+                _                  => return
+            }
+        };
+
+        match draw_type {
+            GfxDrawType::Lines => {
+                match self.line_vertices {
+                    None => println!("No Line Vertices Set"),
+                    Some(ref vertices) => {
+                        draw_vertices(target, vertices, instances, self.instancing, index_buffer, program, &uniforms, &params);
+                    }
+                }
+            },
+            GfxDrawType::Triangles => {
+                match self.triangle_vertices {
+                    None => println!("No Triangle Vertices Set"),
+                    Some(ref vertices) => {
+                        draw_vertices(target, vertices, instances, self.instancing, index_buffer, program, &uniforms, &params);
+                    }
+                }
+            }
+        }
+    }
+
+    // into the offscreen image if there is one, else the window
+    fn draw_any(&mut self,
+                draw_type: GfxDrawType,
+                program: usize,
+                indices: usize,
+                instances: Option<usize>,
+                thick: Option<Range<usize>>,
+                uniforms: &GfxUniforms,
+                state: &GfxDrawState) {
+        match self.offscreen {
+            Some(ref offscreen) => {
+                let mut framebuffer = glium::framebuffer::SimpleFrameBuffer::with_stencil_buffer(&self.display,
+                                                                                                 &offscreen.color,
+                                                                                                 &offscreen.stencil).unwrap();
+                self.draw_to(&mut framebuffer, draw_type, program, indices, instances, thick, uniforms, state);
+            },
+            None => {
+                let mut target = self.target.take().unwrap();
+                self.draw_to(&mut target, draw_type, program, indices, instances, thick, uniforms, state);
+                self.target = Some(target);
+            }
+        }
+    }
+}
+
+impl RenderBackend for GliumBackend {
+    fn load_program(&mut self, id: usize, program: &GfxProgram) -> Result<(), GfxProgramError> {
+        use glium::program::{ProgramChooserCreationError, ProgramCreationError, ShaderType};
+        let vert_shader: &str = &program.vert_shader;
+        let frag_shader: &str = &program.frag_shader;
+        let error = match program!(&self.display,
+                                   140 => {vertex:vert_shader, fragment:frag_shader}) {
+            Ok(built) => {
+                store(&mut self.programs, id, built);
+                return Ok(());
+            },
+            Err(error) => error
+        };
+        let (stage, log) = match error {
+            ProgramChooserCreationError::ProgramCreationError(ProgramCreationError::CompilationError(log, ShaderType::Vertex)) => {
+                (GfxShaderStage::Vertex, log)
+            },
+            ProgramChooserCreationError::ProgramCreationError(ProgramCreationError::CompilationError(log, ShaderType::Fragment)) => {
+                (GfxShaderStage::Fragment, log)
+            },
+            ProgramChooserCreationError::ProgramCreationError(ProgramCreationError::LinkingError(log)) => {
+                (GfxShaderStage::Link, log)
+            },
+            other => (GfxShaderStage::Link, other.to_string())
+        };
+        return Err(GfxProgramError::new(ProgramHandle(id), program, stage, &log));
+    }
+
+    fn load_indices(&mut self, id: usize, indices: &GfxIndices) {
+        store(&mut self.indices,
+              id,
+              glium::IndexBuffer::new(&self.display,
+                                      indices.primitive_type,
+                                      &indices.data).unwrap());
+    }
+
+    fn free_indices(&mut self, id: usize) {
+        if let Some(slot) = self.indices.get_mut(id) {
+            *slot = None;
+        }
+    }
+
+    fn load_instances(&mut self, id: usize, instances: &[GfxInstance]) {
+        if !self.instancing {
+            store(&mut self.instance_copies, id, instances.to_vec());
+            return;
+        }
+        let attributes: Vec<_> = instances.iter().map(|instance| {
+            InstanceAttributes { instance_position: instance.position,
+                                 instance_angle:    instance.angle,
+                                 instance_scale:    instance.scale,
+                                 instance_tint:     instance.tint }
+        }).collect();
+        store(&mut self.instances, id, glium::VertexBuffer::new(&self.display, &attributes).unwrap());
+    }
+
+    fn free_instances(&mut self, id: usize) {
+        if let Some(slot) = self.instances.get_mut(id) {
+            *slot = None;
+        }
+        if let Some(slot) = self.instance_copies.get_mut(id) {
+            *slot = None;
+        }
+    }
+
+    fn load_line_vertices(&mut self,
+                          vertices: &[GfxLineVertex],
+                          dirty: Range<usize>) {
+        update_vertices(&self.display, &mut self.line_vertices, vertices, dirty);
+        if !self.instancing {
+            cover_vertices(&self.display, &mut self.no_instance, vertices.len());
+        }
+    }
+
+    fn load_triangle_vertices(&mut self,
+                              vertices: &[GfxTriangleVertex],
+                              dirty: Range<usize>) {
+        update_vertices(&self.display, &mut self.triangle_vertices, vertices, dirty);
+        if !self.instancing {
+            cover_vertices(&self.display, &mut self.no_instance, vertices.len());
+        }
+    }
+
+    // past GL_MAX_TEXTURE_SIZE or the renderbuffer size the driver
+    // turns it down, rather than anything being checked here
+    fn set_offscreen(&mut self, size: Option<(u32, u32)>) -> Result<(), GfxError> {
+        let (width, height) = match size {
+            Some(size) => size,
+            None       => {
+                self.offscreen = None;
+                return Ok(());
+            }
+        };
+        check_offscreen_size(width, height)?;
+        let color   = glium::texture::Texture2d::empty(&self.display, width, height)
+                          .map_err(|_| GfxError::OffscreenSize(width, height))?;
+        let stencil = glium::framebuffer::StencilRenderBuffer::new(&self.display,
+                                                                   glium::texture::StencilFormat::I8,
+                                                                   width, height)
+                          .map_err(|_| GfxError::OffscreenSize(width, height))?;
+        self.offscreen = Some(Offscreen { color: color, stencil: stencil });
+        return Ok(());
+    }
+
+    fn begin_frame(&mut self) -> (u32, u32) {
+        self.thick_used = 0;
+        if let Some(ref offscreen) = self.offscreen {
+            let mut framebuffer = glium::framebuffer::SimpleFrameBuffer::with_stencil_buffer(&self.display,
+                                                                                             &offscreen.color,
+                                                                                             &offscreen.stencil).unwrap();
+            framebuffer.clear_color_and_stencil((0.0, 0.0, 0.0, 0.0), 0);
+            return framebuffer.get_dimensions();
+        }
+        let mut target = self.display.draw();
+        target.clear_color_and_stencil((0.0, 0.0, 0.0, 0.0), 0);
+        let dimensions = target.get_dimensions();
+        self.target = Some(target);
+        return dimensions;
+    }
+
+    fn draw(&mut self,
+            draw_type: GfxDrawType,
+            program: usize,
+            indices: usize,
+            uniforms: &GfxUniforms,
+            state: &GfxDrawState) {
+        self.draw_any(draw_type, program, indices, None, None, uniforms, state);
+    }
+
+    fn draw_instanced(&mut self,
+                      draw_type: GfxDrawType,
+                      program: usize,
+                      indices: usize,
+                      instances: usize,
+                      uniforms: &GfxUniforms,
+                      state: &GfxDrawState) {
+        if !self.instancing {
+            let copies = match self.instance_copies.get_mut(instances) {
+                Some(Some(copies)) => std::mem::take(copies),
+                _                  => return
+            };
+            for instance in copies.iter() {
+                self.draw_any(draw_type, program, indices, Some(instances), None, &instance.uniforms(uniforms), state);
+            }
+            self.instance_copies[instances] = Some(copies);
+            return;
+        }
+        self.draw_any(draw_type, program, indices, Some(instances), None, uniforms, state);
+    }
+
+    fn draw_thick_lines(&mut self,
+                        program: usize,
+                        indices: usize,
+                        triangles: &[GfxLineVertex],
+                        uniforms: &GfxUniforms,
+                        state: &GfxDrawState) {
+        if triangles.is_empty() {
+            return;
+        }
+        // after whatever's already been drawn from it this frame, so
+        // nothing waits on those draws to overwrite them.  Growing
+        // starts over in a new buffer, the old one lives until the
+        // draws that use it are done.
+        let capacity = self.thick_vertices.as_ref().map_or(0, |buffer| buffer.len());
+        if self.thick_used + triangles.len() > capacity {
+            let size = triangles.len().max(capacity*2);
+            self.thick_vertices = Some(glium::VertexBuffer::empty_dynamic(&self.display, size).unwrap());
+            self.thick_used     = 0;
+        }
+        let range = self.thick_used..(self.thick_used + triangles.len());
+        if let Some(ref buffer) = self.thick_vertices {
+            buffer.slice(range.clone()).unwrap().write(triangles);
+        }
+        self.thick_used = range.end;
+        self.draw_any(GfxDrawType::Triangles, program, indices, None, Some(range), uniforms, state);
+    }
+
+    fn end_frame(&mut self) {
+        if let Some(target) = self.target.take() {
+            target.finish().unwrap();
+        }
+    }
+
+    fn read_frame(&mut self) -> Option<Image> {
+        match self.offscreen {
+            Some(ref offscreen) => return Some(to_image(offscreen.color.read())),
+            None                => return self.display.read_front_buffer().ok().map(to_image)
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter};
+
+// plain rgba8 image, top row first
+#[derive(Clone)]
+pub struct Image {
+    pub width:  u32,
+    pub height: u32,
+    pub pixels: Vec< u8 >
+}
+
+pub struct ImageDifference {
+    pub max_difference:   u8,
+    pub differing_pixels: usize
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32) -> Image {
+        Image { width:  width,
+                height: height,
+                pixels: vec![0; width as usize * height as usize * 4] }
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = ((y * self.width + x) * 4) as usize;
+        return [self.pixels[i], self.pixels[i+1], self.pixels[i+2], self.pixels[i+3]];
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, color: [u8; 4]) {
+        let i = ((y * self.width + x) * 4) as usize;
+        self.pixels[i..i+4].copy_from_slice(&color);
+    }
+
+    pub fn clear(&mut self, color: [u8; 4]) {
+        for pixel in self.pixels.chunks_mut(4) {
+            pixel.copy_from_slice(&color);
+        }
+    }
+
+    // written as rgb, the alpha channel is whatever was left in the
+    // framebuffer and isn't what you see on screen
+    pub fn save_png(&self, path: &str) -> io::Result<()> {
+        let file = File::create(path)?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut rgb = Vec::with_capacity((self.width * self.height * 3) as usize);
+        for pixel in self.pixels.chunks(4) {
+            rgb.extend_from_slice(&pixel[0..3]);
+        }
+        let mut writer = encoder.write_header().map_err(io::Error::other)?;
+        writer.write_image_data(&rgb).map_err(io::Error::other)?;
+        return writer.finish().map_err(io::Error::other);
+    }
+
+    pub fn load_png(path: &str) -> io::Result<Image> {
+        let file = File::open(path)?;
+        let mut decoder = png::Decoder::new(BufReader::new(file));
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info().map_err(io::Error::other)?;
+        let mut buffer = vec![0; reader.output_buffer_size().unwrap_or(0)];
+        let info = reader.next_frame(&mut buffer).map_err(io::Error::other)?;
+
+        let channels = match info.color_type {
+            png::ColorType::Rgb  => 3,
+            png::ColorType::Rgba => 4,
+            other => {
+                return Err(io::Error::other(format!("unsupported png color type {0:?}", other)));
+            }
+        };
+        let mut image = Image::new(info.width, info.height);
+        for y in 0..info.height {
+            let row = &buffer[(y as usize)*info.line_size..];
+            for x in 0..info.width {
+                let p = (x as usize)*channels;
+                let alpha = if channels == 4 { row[p+3] } else { 255 };
+                image.set(x, y, [row[p], row[p+1], row[p+2], alpha]);
+            }
+        }
+        return Ok(image);
+    }
+
+    // compares colour only, same as what save_png keeps
+    pub fn difference(&self, other: &Image) -> Option<ImageDifference> {
+        if self.width != other.width || self.height != other.height {
+            return None;
+        }
+        let mut max_difference   = 0u8;
+        let mut differing_pixels = 0usize;
+        for (a, b) in self.pixels.chunks(4).zip(other.pixels.chunks(4)) {
+            let difference = (0..3).map(|c| (a[c] as i32 - b[c] as i32).unsigned_abs() as u8)
+                                   .max()
+                                   .unwrap();
+            if difference > 0 {
+                differing_pixels += 1;
+            }
+            max_difference = max_difference.max(difference);
+        }
+        return Some(ImageDifference { max_difference:   max_difference,
+                                      differing_pixels: differing_pixels });
+    }
+}
@@ -0,0 +1,89 @@
+use super::{CommandHandle, Gfx, GfxCommandTypes, GfxError, GfxUniforms, InstanceHandle};
+use super::transform::GfxTransform;
+
+// One copy of whatever the bound indices draw, scaled and turned about
+// its own origin, then moved to position.  The angle turns the same way
+// as the Rotate command and tint multiplies the vertex colours on top
+// of the Tint command's.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GfxInstance {
+    pub position: [f32; 2],
+    pub angle:    f32,
+    pub scale:    f32,
+    pub tint:     [f32; 3]
+}
+
+impl GfxInstance {
+    pub fn new(position: (f32, f32), angle: f32, scale: f32) -> GfxInstance {
+        GfxInstance { position: [position.0, position.1],
+                      angle:    angle,
+                      scale:    scale,
+                      tint:     [1.0, 1.0, 1.0] }
+    }
+
+    // from the geometry's space to wherever the draw's transform
+    // starts from
+    pub fn transform(&self) -> GfxTransform {
+        return GfxTransform::translation(self.position[0], self.position[1])
+                   .multiply(&GfxTransform::rotation(self.angle))
+                   .multiply(&GfxTransform::scale(self.scale, self.scale));
+    }
+
+    // a draw's uniforms with this folded in, for drawing one instance
+    // at a time where there's no instancing to do it
+    pub fn uniforms(&self, uniforms: &GfxUniforms) -> GfxUniforms {
+        return GfxUniforms { transform: uniforms.transform.multiply(&self.transform()),
+                             tint:      [uniforms.tint[0] * self.tint[0],
+                                         uniforms.tint[1] * self.tint[1],
+                                         uniforms.tint[2] * self.tint[2]],
+                             alpha:     uniforms.alpha };
+    }
+}
+
+impl Gfx {
+    // a buffer of instances for instanced_draw, loaded as a whole like
+    // an index buffer
+    pub fn add_instances(&mut self, instances: &[GfxInstance]) -> InstanceHandle {
+        let id = match self.free_instance_ids.pop() {
+            Some(id) => {
+                self.instances[id] = Some(instances.to_vec());
+                id
+            },
+            None => {
+                self.instances.push(Some(instances.to_vec()));
+                self.instances.len() - 1
+            }
+        };
+        self.instances_pending.push(id);
+        return InstanceHandle(id);
+    }
+
+    pub fn free_instances(&mut self, instances: InstanceHandle) -> Result<(), GfxError> {
+        self.instance_data(instances)?;
+        self.instances[instances.0] = None;
+        self.free_instance_ids.push(instances.0);
+        self.instances_pending.push(instances.0);
+        return Ok(());
+    }
+
+    // draws the bound indices with the bound program once for every
+    // instance.  The indices have to be triangles and the program has
+    // to take the instances as attributes, see
+    // shaders/triangle_instanced.vert; validate() turns down anything
+    // else.
+    pub fn instanced_draw(&mut self, instances: InstanceHandle) -> Result<CommandHandle, GfxError> {
+        self.instance_data(instances)?;
+        return Ok(self.push(GfxCommandTypes::InstancedDraw(instances)));
+    }
+
+    pub(super) fn instance_data(&self, instances: InstanceHandle) -> Result<&Vec< GfxInstance >, GfxError> {
+        match self.instances.get(instances.0) {
+            Some(Some(data)) => return Ok(data),
+            _                => return Err(GfxError::NoSuchInstances(instances))
+        }
+    }
+
+    pub fn num_instance_buffers(&self) -> usize {
+        return self.instances.iter().flatten().count();
+    }
+}
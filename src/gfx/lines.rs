@@ -0,0 +1,364 @@
+use glium::index::PrimitiveType;
+
+use std::collections::HashMap;
+
+use super::{add_points, rotate, scale_point, GfxIndices, GfxLineVertex};
+use super::state::{GfxLineCap, GfxLineJoin, GfxLineStyle};
+
+// a miter reaching further than this many half widths from the corner
+// is bevelled instead, about 30 degrees
+const MITER_LIMIT: f32 = 4.0;
+// the most a round join or cap turns in one triangle, radians
+const ROUND_STEP: f32 = 0.4;
+
+fn direction(from: (f32, f32), to: (f32, f32)) -> (f32, f32) {
+    let (x, y) = (to.0 - from.0, to.1 - from.1);
+    let length = (x*x + y*y).sqrt();
+    return (x / length, y / length);
+}
+
+// a quarter turn anticlockwise
+fn normal(direction: (f32, f32)) -> (f32, f32) {
+    return (-direction.1, direction.0);
+}
+
+fn cross(a: (f32, f32), b: (f32, f32)) -> f32 {
+    return a.0*b.1 - a.1*b.0;
+}
+
+fn dot(a: (f32, f32), b: (f32, f32)) -> f32 {
+    return a.0*b.0 + a.1*b.1;
+}
+
+fn triangle(out: &mut Vec< GfxLineVertex >, a: (f32, f32), b: (f32, f32), c: (f32, f32)) {
+    for point in [a, b, c].iter() {
+        out.push(GfxLineVertex { position: [point.0, point.1] });
+    }
+}
+
+// triangles round center from center+from, turning angle anticlockwise
+fn fan(out: &mut Vec< GfxLineVertex >, center: (f32, f32), from: (f32, f32), angle: f32) {
+    let steps = (angle.abs() / ROUND_STEP).ceil().max(1.0) as u32;
+    for i in 0..steps {
+        let a = rotate(from, angle * i as f32 / steps as f32);
+        let b = rotate(from, angle * (i + 1) as f32 / steps as f32);
+        triangle(out, center, add_points(center, a), add_points(center, b));
+    }
+}
+
+// vertex ids that run on from one to the next, and whether the last
+// joins back to the first
+fn polylines(indices: &GfxIndices) -> Vec< (Vec< u32 >, bool) > {
+    match indices.primitive_type {
+        PrimitiveType::LineStrip => return vec![(indices.data.clone(), false)],
+        PrimitiveType::LineLoop  => return vec![(indices.data.clone(), true)],
+        PrimitiveType::LinesList => return chain(&indices.data),
+        _                        => return Vec::new()
+    }
+}
+
+// a LinesList joined up wherever segments share a vertex, whichever
+// way round and order they come in, so an outline added a segment at
+// a time still gets joins at its corners
+fn chain(data: &[u32]) -> Vec< (Vec< u32 >, bool) > {
+    let segments: Vec<_> = data.chunks_exact(2).map(|segment| (segment[0], segment[1])).collect();
+    let mut touching: HashMap<u32, Vec< usize >> = HashMap::new();
+    for (i, (a, b)) in segments.iter().enumerate() {
+        touching.entry(*a).or_default().push(i);
+        touching.entry(*b).or_default().push(i);
+    }
+
+    let mut used  = vec![false; segments.len()];
+    let mut lines = Vec::new();
+    for first in 0..segments.len() {
+        if used[first] {
+            continue;
+        }
+        used[first] = true;
+        let mut line = vec![segments[first].0, segments[first].1];
+        // on from the end as far as it goes, then back from the start
+        for backwards in [false, true].iter() {
+            loop {
+                let end  = if *backwards { line[0] } else { line[line.len() - 1] };
+                let next = touching[&end].iter().cloned().find(|i| !used[*i]);
+                let next = match next {
+                    Some(next) => next,
+                    None       => break
+                };
+                used[next] = true;
+                let (a, b) = segments[next];
+                let other  = if a == end { b } else { a };
+                if *backwards {
+                    line.insert(0, other);
+                } else {
+                    line.push(other);
+                }
+            }
+        }
+        // not there and back along one segment
+        let closed = line.len() > 3 && line[0] == line[line.len() - 1];
+        if closed {
+            line.pop();
+        }
+        lines.push((line, closed));
+    }
+    return lines;
+}
+
+// the outside of the corner at point, coming from previous and going
+// on to next
+fn join(out: &mut Vec< GfxLineVertex >,
+        previous: (f32, f32),
+        point: (f32, f32),
+        next: (f32, f32),
+        half_width: f32,
+        style: GfxLineJoin) {
+    let d0   = direction(previous, point);
+    let d1   = direction(point, next);
+    let turn = cross(d0, d1);
+    if turn.abs() < 1e-6 && dot(d0, d1) > 0.0 {
+        return;
+    }
+    // turning left the outside is on the right
+    let side = if turn > 0.0 { -half_width } else { half_width };
+    let n0   = scale_point(normal(d0), side);
+    let n1   = scale_point(normal(d1), side);
+    match style {
+        GfxLineJoin::Bevel => triangle(out, point, add_points(point, n0), add_points(point, n1)),
+        GfxLineJoin::Round => fan(out, point, n0, f32::atan2(cross(n0, n1), dot(n0, n1))),
+        GfxLineJoin::Miter => {
+            let middle = add_points(n0, n1);
+            let length = dot(middle, middle).sqrt();
+            // how far out the miter reaches, in half widths
+            let reach  = if length > 0.0 { half_width * length / dot(middle, n0) } else { f32::MAX };
+            if reach > MITER_LIMIT {
+                return join(out, previous, point, next, half_width, GfxLineJoin::Bevel);
+            }
+            let miter = add_points(point, scale_point(middle, half_width * reach / length));
+            triangle(out, point, add_points(point, n0), miter);
+            triangle(out, point, miter, add_points(point, n1));
+        }
+    }
+}
+
+fn polyline(out: &mut Vec< GfxLineVertex >,
+            points: &mut [(f32, f32)],
+            closed: bool,
+            half_width: f32,
+            style: &GfxLineStyle) {
+    let n = points.len();
+    if n == 0 {
+        return;
+    }
+    // a dot, which only shows if the caps stick out
+    if n == 1 {
+        let point = points[0];
+        match style.cap {
+            GfxLineCap::Butt   => {},
+            GfxLineCap::Round  => fan(out, point, (half_width, 0.0), std::f32::consts::PI * 2.0),
+            GfxLineCap::Square => {
+                let (x, y, h) = (point.0, point.1, half_width);
+                triangle(out, (x - h, y - h), (x + h, y - h), (x + h, y + h));
+                triangle(out, (x - h, y - h), (x + h, y + h), (x - h, y + h));
+            }
+        }
+        return;
+    }
+
+    if !closed && style.cap == GfxLineCap::Square {
+        points[0]     = add_points(points[0], scale_point(direction(points[1], points[0]), half_width));
+        points[n - 1] = add_points(points[n - 1], scale_point(direction(points[n - 2], points[n - 1]), half_width));
+    }
+    let segments = if closed { n } else { n - 1 };
+    for i in 0..segments {
+        let a = points[i];
+        let b = points[(i + 1) % n];
+        let across = scale_point(normal(direction(a, b)), half_width);
+        let (a0, a1) = (add_points(a, across), add_points(a, scale_point(across, -1.0)));
+        let (b0, b1) = (add_points(b, across), add_points(b, scale_point(across, -1.0)));
+        triangle(out, a0, a1, b1);
+        triangle(out, a0, b1, b0);
+    }
+
+    let corners = if closed { 0..n } else { 1..(n - 1) };
+    for i in corners {
+        join(out, points[(i + n - 1) % n], points[i], points[(i + 1) % n], half_width, style.join);
+    }
+    if !closed && style.cap == GfxLineCap::Round {
+        // round the back of each end, from one side to the other
+        for (end, towards) in [(points[0], points[1]), (points[n - 1], points[n - 2])].iter() {
+            let across = scale_point(normal(direction(*end, *towards)), half_width);
+            fan(out, *end, across, std::f32::consts::PI);
+        }
+    }
+}
+
+// what a line draw of indices into vertices covers at half_width
+// either side, as a triangle list in the same space.  Vertices that
+// repeat one after another are dropped so every segment has a
+// direction.
+pub fn thick_lines(vertices: &[GfxLineVertex],
+                   indices: &GfxIndices,
+                   half_width: f32,
+                   style: &GfxLineStyle) -> Vec< GfxLineVertex > {
+    let mut out = Vec::new();
+    for (ids, closed) in polylines(indices) {
+        let mut points: Vec< (f32, f32) > = Vec::with_capacity(ids.len());
+        for id in ids {
+            if let Some(vertex) = vertices.get(id as usize) {
+                let point = (vertex.position[0], vertex.position[1]);
+                if points.last() != Some(&point) {
+                    points.push(point);
+                }
+            }
+        }
+        if closed && points.len() > 1 && points[0] == points[points.len() - 1] {
+            points.pop();
+        }
+        // a loop of two is the same segment there and back
+        let closed = closed && points.len() > 2;
+        polyline(&mut out, &mut points, closed, half_width, style);
+    }
+    return out;
+}
+
+// An expansion kept from one frame to the next.  Gfx drops it when its
+// index buffer or any line vertex under it changes, and after any frame
+// that didn't draw it, so lines in pixels are only worked out again when
+// the zoom moves.
+pub struct ThickLines {
+    pub indices:    usize,
+    pub style:      GfxLineStyle,
+    pub half_width: f32,
+    pub triangles:  Vec< GfxLineVertex >,
+    pub used:       bool
+}
+
+// where in cache thick_lines() of indices is, working it out only if
+// nothing there matches
+pub fn cached(cache: &mut Vec< ThickLines >,
+              vertices: &[GfxLineVertex],
+              id: usize,
+              indices: &GfxIndices,
+              half_width: f32,
+              style: &GfxLineStyle) -> usize {
+    let found = cache.iter().position(|entry| {
+        entry.indices == id && entry.style == *style && entry.half_width == half_width
+    });
+    let i = match found {
+        Some(i) => i,
+        None    => {
+            cache.push(ThickLines { indices:    id,
+                                    style:      *style,
+                                    half_width: half_width,
+                                    triangles:  thick_lines(vertices, indices, half_width, style),
+                                    used:       false });
+            cache.len() - 1
+        }
+    };
+    cache[i].used = true;
+    return i;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::state::GfxLineUnits;
+
+    fn vertices(points: &[(f32, f32)]) -> Vec< GfxLineVertex > {
+        return points.iter().map(|point| GfxLineVertex { position: [point.0, point.1] }).collect();
+    }
+
+    fn style(join: GfxLineJoin, cap: GfxLineCap) -> GfxLineStyle {
+        let mut style = GfxLineStyle::new(2.0, GfxLineUnits::World);
+        style.join = join;
+        style.cap  = cap;
+        return style;
+    }
+
+    fn expand(points: &[(f32, f32)], primitive_type: PrimitiveType, style: &GfxLineStyle) -> Vec< (f32, f32) > {
+        let indices   = GfxIndices::new((0..points.len() as u32).collect(), primitive_type);
+        let triangles = thick_lines(&vertices(points), &indices, 1.0, style);
+        assert_eq!(triangles.len() % 3, 0);
+        return triangles.iter().map(|vertex| (vertex.position[0], vertex.position[1])).collect();
+    }
+
+    fn furthest(points: &[(f32, f32)], from: (f32, f32)) -> f32 {
+        return points.iter().map(|p| ((p.0-from.0).powi(2) + (p.1-from.1).powi(2)).sqrt()).fold(0.0, f32::max);
+    }
+
+    fn near(a: (f32, f32), b: (f32, f32)) -> bool {
+        return (a.0-b.0).abs() < 1e-4 && (a.1-b.1).abs() < 1e-4;
+    }
+
+    #[test]
+    fn chain_joins_segments_either_way_round() {
+        assert_eq!(chain(&[0, 1, 2, 1, 2, 3]), vec![(vec![0, 1, 2, 3], false)]);
+        assert_eq!(chain(&[1, 2, 0, 1]), vec![(vec![0, 1, 2], false)]);
+        assert_eq!(chain(&[0, 1, 5, 6]), vec![(vec![0, 1], false), (vec![5, 6], false)]);
+    }
+
+    #[test]
+    fn chain_closes_loops_but_not_there_and_back() {
+        assert_eq!(chain(&[0, 1, 1, 3, 2, 0, 3, 2]), vec![(vec![0, 1, 3, 2], true)]);
+        assert_eq!(chain(&[0, 1, 1, 0]), vec![(vec![0, 1, 0], false)]);
+    }
+
+    #[test]
+    fn miter_reaches_the_corner() {
+        let corner = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)];
+        let mitered = expand(&corner, PrimitiveType::LineStrip, &style(GfxLineJoin::Miter, GfxLineCap::Butt));
+        assert!(mitered.iter().any(|p| near(*p, (11.0, -1.0))));
+        let bevelled = expand(&corner, PrimitiveType::LineStrip, &style(GfxLineJoin::Bevel, GfxLineCap::Butt));
+        assert!(!bevelled.iter().any(|p| near(*p, (11.0, -1.0))));
+        assert!(furthest(&bevelled, (10.0, 0.0)) <= 10.0_f32.hypot(1.0) + 1e-4);
+    }
+
+    #[test]
+    fn sharp_miters_are_bevelled() {
+        // turning back on itself, the miter would be miles out
+        let hairpin = [(0.0, 0.0), (10.0, 0.0), (0.0, 0.5)];
+        let points  = expand(&hairpin, PrimitiveType::LineStrip, &style(GfxLineJoin::Miter, GfxLineCap::Butt));
+        let reach   = points.iter().map(|p| p.0).fold(f32::MIN, f32::max);
+        assert!(reach < 10.0 + 1.0 + 1e-4, "reached {0}", reach);
+    }
+
+    #[test]
+    fn caps() {
+        let segment = [(0.0, 0.0), (10.0, 0.0)];
+        let extent  = |cap| {
+            let points = expand(&segment, PrimitiveType::LineStrip, &style(GfxLineJoin::Miter, cap));
+            return (points.iter().map(|p| p.0).fold(f32::MAX, f32::min),
+                    points.iter().map(|p| p.0).fold(f32::MIN, f32::max));
+        };
+        assert_eq!(extent(GfxLineCap::Butt), (0.0, 10.0));
+        assert_eq!(extent(GfxLineCap::Square), (-1.0, 11.0));
+        let (left, right) = extent(GfxLineCap::Round);
+        assert!((left + 1.0).abs() < 1e-4 && (right - 11.0).abs() < 1e-4);
+        // round caps stay within half the width of the ends
+        let points = expand(&segment, PrimitiveType::LineStrip, &style(GfxLineJoin::Miter, GfxLineCap::Round));
+        for p in points.iter().filter(|p| p.0 < 0.0) {
+            assert!(p.0.hypot(p.1) <= 1.0 + 1e-4);
+        }
+    }
+
+    #[test]
+    fn dots_only_show_with_caps() {
+        assert!(expand(&[(1.0, 1.0)], PrimitiveType::LineStrip, &style(GfxLineJoin::Miter, GfxLineCap::Butt)).is_empty());
+        assert_eq!(expand(&[(1.0, 1.0)], PrimitiveType::LineStrip, &style(GfxLineJoin::Miter, GfxLineCap::Square)).len(), 6);
+        assert!(!expand(&[(1.0, 1.0)], PrimitiveType::LineStrip, &style(GfxLineJoin::Miter, GfxLineCap::Round)).is_empty());
+    }
+
+    #[test]
+    fn cached_until_the_width_changes() {
+        let vertices = vertices(&[(0.0, 0.0), (10.0, 0.0)]);
+        let indices  = GfxIndices::new(vec![0, 1], PrimitiveType::LinesList);
+        let style    = style(GfxLineJoin::Miter, GfxLineCap::Butt);
+        let mut cache = Vec::new();
+        assert_eq!(cached(&mut cache, &vertices, 3, &indices, 1.0, &style), 0);
+        assert_eq!(cached(&mut cache, &vertices, 3, &indices, 1.0, &style), 0);
+        assert_eq!(cached(&mut cache, &vertices, 3, &indices, 2.0, &style), 1);
+        assert_eq!(cached(&mut cache, &vertices, 4, &indices, 1.0, &style), 2);
+        assert_eq!(cache.len(), 3);
+    }
+}
@@ -0,0 +1,208 @@
+use std::ops::Range;
+
+use super::{check_offscreen_size, GfxDrawType, GfxError, GfxIndices, GfxLineVertex,
+            GfxProgram, GfxTriangleVertex, GfxUniforms, RenderBackend};
+use super::image::Image;
+use super::instances::GfxInstance;
+use super::shaders::GfxProgramError;
+use super::state::GfxDrawState;
+
+// A backend with no gpu behind it.  It keeps a copy of what it was
+// given and a list of the draws issued in the last frame, and notes
+// anything that would have blown up on a real device.
+#[derive(Copy, Clone, Debug)]
+pub struct RecordedDraw {
+    pub draw_type: GfxDrawType,
+    pub program:   usize,
+    pub indices:   usize,
+    // None for an ordinary draw, else the instance buffer and how
+    // many were in it
+    pub instances: Option<(usize, usize)>,
+    // for lines with a line style, how many triangles they came to
+    pub triangles: Option<usize>,
+    pub uniforms:  GfxUniforms,
+    pub state:     GfxDrawState
+}
+
+pub struct RecordingBackend {
+    width:          u32,
+    height:         u32,
+    offscreen:      Option<(u32, u32)>,
+    programs:       Vec< bool >,
+    indices:        Vec< Option<Vec< u32 >> >,
+    instances:      Vec< Option<usize> >,
+    num_lines:      usize,
+    num_triangles:  usize,
+    pub vertices_uploaded: usize,
+    pub frames:     usize,
+    pub draws:      Vec< RecordedDraw >,
+    pub errors:     Vec< String >
+}
+
+impl RecordingBackend {
+    pub fn new(width: u32, height: u32) -> RecordingBackend {
+        RecordingBackend { width:         width,
+                           height:        height,
+                           offscreen:     None,
+                           programs:      Vec::new(),
+                           indices:       Vec::new(),
+                           instances:     Vec::new(),
+                           num_lines:     0,
+                           num_triangles: 0,
+                           vertices_uploaded: 0,
+                           frames:        0,
+                           draws:         Vec::new(),
+                           errors:        Vec::new() }
+    }
+
+    pub fn num_lines(&self) -> usize {
+        return self.num_lines;
+    }
+
+    pub fn num_triangles(&self) -> usize {
+        return self.num_triangles;
+    }
+}
+
+impl RenderBackend for RecordingBackend {
+    fn load_program(&mut self, id: usize, _program: &GfxProgram) -> Result<(), GfxProgramError> {
+        if self.programs.len() <= id {
+            self.programs.resize(id + 1, false);
+        }
+        self.programs[id] = true;
+        return Ok(());
+    }
+
+    fn load_indices(&mut self, id: usize, indices: &GfxIndices) {
+        if self.indices.len() <= id {
+            self.indices.resize_with(id + 1, || None);
+        }
+        self.indices[id] = Some(indices.data.clone());
+    }
+
+    fn free_indices(&mut self, id: usize) {
+        if let Some(slot) = self.indices.get_mut(id) {
+            *slot = None;
+        }
+    }
+
+    fn load_instances(&mut self, id: usize, instances: &[GfxInstance]) {
+        if self.instances.len() <= id {
+            self.instances.resize_with(id + 1, || None);
+        }
+        self.instances[id] = Some(instances.len());
+    }
+
+    fn free_instances(&mut self, id: usize) {
+        if let Some(slot) = self.instances.get_mut(id) {
+            *slot = None;
+        }
+    }
+
+    fn load_line_vertices(&mut self,
+                          vertices: &[GfxLineVertex],
+                          dirty: Range<usize>) {
+        self.num_lines = vertices.len();
+        self.vertices_uploaded += dirty.len();
+    }
+
+    fn load_triangle_vertices(&mut self,
+                              vertices: &[GfxTriangleVertex],
+                              dirty: Range<usize>) {
+        self.num_triangles = vertices.len();
+        self.vertices_uploaded += dirty.len();
+    }
+
+    fn set_offscreen(&mut self, size: Option<(u32, u32)>) -> Result<(), GfxError> {
+        if let Some((width, height)) = size {
+            check_offscreen_size(width, height)?;
+        }
+        self.offscreen = size;
+        return Ok(());
+    }
+
+    fn begin_frame(&mut self) -> (u32, u32) {
+        self.draws.clear();
+        return self.offscreen.unwrap_or((self.width, self.height));
+    }
+
+    fn draw(&mut self,
+            draw_type: GfxDrawType,
+            program: usize,
+            indices: usize,
+            uniforms: &GfxUniforms,
+            state: &GfxDrawState) {
+        let frame = self.frames;
+        if program >= self.programs.len() || !self.programs[program] {
+            self.errors.push(format!("frame {0}: draw with unknown program {1}", frame, program));
+        }
+        let num_vertices = match draw_type {
+            GfxDrawType::Lines     => self.num_lines,
+            GfxDrawType::Triangles => self.num_triangles
+        };
+        match self.indices.get(indices) {
+            Some(Some(data)) => {
+                if let Some(max) = data.iter().max() {
+                    if *max as usize >= num_vertices {
+                        self.errors.push(format!("frame {0}: indices {1} reference vertex {2} of {3}",
+                                                 frame, indices, max, num_vertices));
+                    }
+                }
+            },
+            _ => {
+                self.errors.push(format!("frame {0}: draw with unknown indices {1}", frame, indices));
+            }
+        }
+        self.draws.push(RecordedDraw { draw_type: draw_type,
+                                       program:   program,
+                                       indices:   indices,
+                                       instances: None,
+                                       triangles: None,
+                                       uniforms:  *uniforms,
+                                       state:     *state });
+    }
+
+    fn draw_instanced(&mut self,
+                      draw_type: GfxDrawType,
+                      program: usize,
+                      indices: usize,
+                      instances: usize,
+                      uniforms: &GfxUniforms,
+                      state: &GfxDrawState) {
+        let count = match self.instances.get(instances) {
+            Some(Some(count)) => *count,
+            _ => {
+                self.errors.push(format!("frame {0}: draw with unknown instances {1}", self.frames, instances));
+                0
+            }
+        };
+        self.draw(draw_type, program, indices, uniforms, state);
+        if let Some(draw) = self.draws.last_mut() {
+            draw.instances = Some((instances, count));
+        }
+    }
+
+    fn draw_thick_lines(&mut self,
+                        program: usize,
+                        indices: usize,
+                        triangles: &[GfxLineVertex],
+                        uniforms: &GfxUniforms,
+                        state: &GfxDrawState) {
+        if triangles.len() % 3 != 0 {
+            self.errors.push(format!("frame {0}: thick lines with {1} vertices, not whole triangles",
+                                     self.frames, triangles.len()));
+        }
+        self.draw(GfxDrawType::Lines, program, indices, uniforms, state);
+        if let Some(draw) = self.draws.last_mut() {
+            draw.triangles = Some(triangles.len() / 3);
+        }
+    }
+
+    fn end_frame(&mut self) {
+        self.frames += 1;
+    }
+
+    fn read_frame(&mut self) -> Option<Image> {
+        return None;
+    }
+}
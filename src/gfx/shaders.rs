@@ -0,0 +1,249 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::{Gfx, GfxError, GfxProgram, ProgramHandle};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GfxShaderStage {
+    Vertex,
+    Fragment,
+    Link
+}
+
+// what a backend says when a program won't build.  line is counted
+// from 1 with the #version line, the way the drivers count it.
+#[derive(Clone, Debug)]
+pub struct GfxProgramError {
+    pub program:     ProgramHandle,
+    pub stage:       GfxShaderStage,
+    pub line:        Option<usize>,
+    pub source_line: Option<String>,
+    pub message:     String
+}
+
+// the drivers don't agree on a format, this finds the line in any of
+//   0:12(5): error: ...       mesa
+//   0(12) : error C0000: ...  nvidia
+//   ERROR: 0:12: ...          amd and intel
+fn error_line(log: &str) -> Option<usize> {
+    for text in log.lines() {
+        let chars: Vec<char> = text.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            if !chars[i].is_ascii_digit() || (i > 0 && chars[i-1].is_ascii_alphanumeric()) {
+                i += 1;
+                continue;
+            }
+            let mut j = i;
+            while j < chars.len() && chars[j].is_ascii_digit() {
+                j += 1;
+            }
+            if j + 1 < chars.len() && (chars[j] == ':' || chars[j] == '(') && chars[j+1].is_ascii_digit() {
+                let mut k = j + 1;
+                while k < chars.len() && chars[k].is_ascii_digit() {
+                    k += 1;
+                }
+                return chars[(j+1)..k].iter().collect::<String>().parse().ok();
+            }
+            i = j;
+        }
+    }
+    return None;
+}
+
+impl GfxProgramError {
+    pub fn new(program: ProgramHandle,
+               source: &GfxProgram,
+               stage: GfxShaderStage,
+               log: &str) -> GfxProgramError {
+        let line = match stage {
+            GfxShaderStage::Link => None,
+            _                    => error_line(log)
+        };
+        let text = match stage {
+            GfxShaderStage::Vertex   => Some(&source.vert_shader),
+            GfxShaderStage::Fragment => Some(&source.frag_shader),
+            GfxShaderStage::Link     => None
+        };
+        let source_line = match (line, text) {
+            (Some(line), Some(text)) if line > 0 => text.lines().nth(line - 1).map(|text| text.trim().to_string()),
+            _                                    => None
+        };
+        GfxProgramError { program:     program,
+                          stage:       stage,
+                          line:        line,
+                          source_line: source_line,
+                          message:     log.trim().to_string() }
+    }
+}
+
+impl fmt::Display for GfxProgramError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let stage = match self.stage {
+            GfxShaderStage::Vertex   => "vertex shader",
+            GfxShaderStage::Fragment => "fragment shader",
+            GfxShaderStage::Link     => "link"
+        };
+        write!(f, "program {0} {1}", self.program.0, stage)?;
+        if let Some(line) = self.line {
+            write!(f, " line {0}", line)?;
+        }
+        write!(f, ": {0}", self.message)?;
+        if let Some(ref source_line) = self.source_line {
+            write!(f, "\n    {0}", source_line)?;
+        }
+        return Ok(());
+    }
+}
+
+#[derive(Debug)]
+pub enum ShaderFileError {
+    Io {
+        path:  PathBuf,
+        error: std::io::Error
+    },
+    Gfx(GfxError)
+}
+
+impl fmt::Display for ShaderFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShaderFileError::Io { path, error } => write!(f, "{0}: {1}", path.display(), error),
+            ShaderFileError::Gfx(error)         => write!(f, "{0}", error)
+        }
+    }
+}
+
+struct ShaderFiles {
+    name:      String,
+    program:   ProgramHandle,
+    vert_path: PathBuf,
+    frag_path: PathBuf,
+    modified:  (Option<SystemTime>, Option<SystemTime>)
+}
+
+fn read(path: &Path) -> Result<String, ShaderFileError> {
+    match std::fs::read_to_string(path) {
+        Ok(text)   => return Ok(text),
+        Err(error) => return Err(ShaderFileError::Io { path: path.to_path_buf(), error: error })
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    return std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+}
+
+// Programs kept as <name>.vert and <name>.frag in one directory.  The
+// Gfx program is replaced when either file changes, the handle stays
+// the same.
+pub struct ShaderLibrary {
+    dir:     PathBuf,
+    shaders: Vec< ShaderFiles >
+}
+
+impl ShaderLibrary {
+    pub fn new<P: AsRef<Path>>(dir: P) -> ShaderLibrary {
+        ShaderLibrary { dir:     dir.as_ref().to_path_buf(),
+                        shaders: Vec::new() }
+    }
+
+    pub fn load(&mut self, gfx: &mut Gfx, name: &str) -> Result<ProgramHandle, ShaderFileError> {
+        if let Some(program) = self.get(name) {
+            return Ok(program);
+        }
+        let vert_path = self.dir.join(format!("{0}.vert", name));
+        let frag_path = self.dir.join(format!("{0}.frag", name));
+        let modified  = (modified(&vert_path), modified(&frag_path));
+        let program   = gfx.add_program(&read(&vert_path)?, &read(&frag_path)?);
+        self.shaders.push(ShaderFiles { name:      name.to_string(),
+                                        program:   program,
+                                        vert_path: vert_path,
+                                        frag_path: frag_path,
+                                        modified:  modified });
+        return Ok(program);
+    }
+
+    pub fn get(&self, name: &str) -> Option<ProgramHandle> {
+        return self.shaders.iter()
+                           .find(|shader| shader.name == name)
+                           .map(|shader| shader.program);
+    }
+
+    // gives the names of the programs that were reloaded, whether or
+    // not they compile is up to the backend on the next Gfx::run
+    pub fn reload_changed(&mut self, gfx: &mut Gfx) -> Vec< Result<String, ShaderFileError> > {
+        let mut results = Vec::new();
+        for shader in self.shaders.iter_mut() {
+            let now = (modified(&shader.vert_path), modified(&shader.frag_path));
+            if now == shader.modified {
+                continue;
+            }
+            shader.modified = now;
+            let sources = read(&shader.vert_path).and_then(|vert| Ok((vert, read(&shader.frag_path)?)));
+            results.push(match sources {
+                Err(error)               => Err(error),
+                Ok((vert, frag)) => {
+                    match gfx.replace_program(shader.program, &vert, &frag) {
+                        Ok(())     => Ok(shader.name.clone()),
+                        Err(error) => Err(ShaderFileError::Gfx(error))
+                    }
+                }
+            });
+        }
+        return results;
+    }
+
+    // the error against the file it came from, where it's one of ours
+    pub fn report(&self, error: &GfxProgramError) -> String {
+        let shader = match self.shaders.iter().find(|shader| shader.program == error.program) {
+            Some(shader) => shader,
+            None         => return error.to_string()
+        };
+        let path = match error.stage {
+            GfxShaderStage::Vertex   => shader.vert_path.display().to_string(),
+            GfxShaderStage::Fragment => shader.frag_path.display().to_string(),
+            GfxShaderStage::Link     => format!("{0} (link)", shader.name)
+        };
+        let mut out = match error.line {
+            Some(line) => format!("{0}:{1}: {2}", path, line, error.message),
+            None       => format!("{0}: {1}", path, error.message)
+        };
+        if let Some(ref source_line) = error.source_line {
+            out.push_str(&format!("\n    {0}", source_line));
+        }
+        return out;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_line_mesa() {
+        assert_eq!(error_line("0:12(5): error: syntax error, unexpected '}'"), Some(12));
+    }
+
+    #[test]
+    fn error_line_nvidia() {
+        assert_eq!(error_line("0(7) : error C0000: syntax error, unexpected '}'"), Some(7));
+    }
+
+    #[test]
+    fn error_line_amd_and_intel() {
+        assert_eq!(error_line("ERROR: 0:33: 'colour' : undeclared identifier"), Some(33));
+    }
+
+    #[test]
+    fn error_line_skips_lines_without_one() {
+        assert_eq!(error_line("compiling shader\n0:4(10): error: x undeclared"), Some(4));
+        assert_eq!(error_line("vec4 too many components"), None);
+        assert_eq!(error_line(""), None);
+    }
+
+    #[test]
+    fn error_line_ignores_digits_in_words() {
+        assert_eq!(error_line("error C1008: undefined variable vec2:3"), None);
+    }
+}
@@ -0,0 +1,363 @@
+use glium::index::PrimitiveType;
+
+use std::ops::Range;
+
+use super::image::Image;
+use super::instances::GfxInstance;
+use super::{check_offscreen_size, GfxDrawType, GfxError, GfxIndices, GfxLineVertex,
+            GfxProgram, GfxTriangleVertex, GfxUniforms, RenderBackend};
+use super::shaders::GfxProgramError;
+use super::state::{GfxBlend, GfxDrawState, GfxStencil};
+
+// Rasterizes on the cpu into an Image.  It doesn't run glsl, it does
+// what trivertex140/linevertex140 and their fragment shaders do, so
+// it's only good for those two programs and the instanced triangles.
+pub struct SoftwareBackend {
+    image:      Image,
+    indices:    Vec< Option<GfxIndices> >,
+    instances:  Vec< Option<Vec< GfxInstance >> >,
+    lines:      Vec< GfxLineVertex >,
+    triangles:  Vec< GfxTriangleVertex >,
+    stencil:    Vec< u8 >,
+    state:      GfxDrawState,
+    // the scissor rectangle in image pixels, left top right bottom
+    clip:       (u32, u32, u32, u32),
+    // the usual image, put aside while drawing offscreen
+    window:     Option<Image>,
+    // draws it couldn't do, same as RecordingBackend's
+    pub errors: Vec< String >
+}
+
+// same math as the vertex shaders, ending up in clip space
+pub fn transform_vertex(position: [f32; 2], uniforms: &GfxUniforms) -> (f32, f32) {
+    return uniforms.transform.apply((position[0], position[1]));
+}
+
+fn edge(a: (f32, f32), b: (f32, f32), p: (f32, f32)) -> f32 {
+    return (b.0-a.0)*(p.1-a.1) - (b.1-a.1)*(p.0-a.0);
+}
+
+// what GliumBackend asks of glium for each GfxBlend, for colour and
+// alpha alike
+fn blend(image: &mut Image, x: u32, y: u32, color: [f32; 4], mode: GfxBlend) {
+    let dst = image.get(x, y);
+    let a   = color[3].clamp(0.0, 1.0);
+    let mut out = [0u8; 4];
+    for c in 0..4 {
+        let src = if c == 3 { a } else { color[c].clamp(0.0, 1.0) };
+        let dst = dst[c] as f32 / 255.0;
+        let value = match mode {
+            GfxBlend::Alpha    => src*a + dst*(1.0-a),
+            GfxBlend::Additive => src*a + dst,
+            GfxBlend::Replace  => src
+        };
+        out[c] = (value.min(1.0)*255.0).round() as u8;
+    }
+    image.set(x, y, out);
+}
+
+impl SoftwareBackend {
+    pub fn new(width: u32, height: u32) -> SoftwareBackend {
+        SoftwareBackend { image:      Image::new(width, height),
+                          indices:    Vec::new(),
+                          instances:  Vec::new(),
+                          lines:      Vec::new(),
+                          triangles:  Vec::new(),
+                          stencil:    vec![0; width as usize * height as usize],
+                          state:      GfxDrawState::new(),
+                          clip:       (0, 0, width, height),
+                          window:     None,
+                          errors:     Vec::new() }
+    }
+
+    pub fn image(&self) -> &Image {
+        return &self.image;
+    }
+
+    // clip space to image pixels inside the viewport
+    fn to_pixels(&self, clip: (f32, f32)) -> (f32, f32) {
+        let (left, bottom, width, height) = self.state.viewport.to_pixels(self.image.width, self.image.height);
+        let top = self.image.height - bottom - height;
+        return (left as f32 + (clip.0 + 1.0) * 0.5 * width as f32,
+                top as f32 + (1.0 - clip.1) * 0.5 * height as f32);
+    }
+
+    // one fragment, through the stencil test and blending
+    fn plot(&mut self, x: u32, y: u32, color: [f32; 4]) {
+        let i = (y*self.image.width + x) as usize;
+        match self.state.stencil {
+            GfxStencil::Off             => {},
+            GfxStencil::Write(value)    => self.stencil[i] = value,
+            GfxStencil::Equal(value)    => if self.stencil[i] != value { return; },
+            GfxStencil::NotEqual(value) => if self.stencil[i] == value { return; }
+        }
+        blend(&mut self.image, x, y, color, self.state.blend);
+    }
+
+    fn triangle(&mut self,
+                p: [(f32, f32); 3],
+                colors: [[f32; 4]; 3]) {
+        let area = edge(p[0], p[1], p[2]);
+        if area == 0.0 {
+            return;
+        }
+        let (clip_x0, clip_y0, clip_x1, clip_y1) = self.clip;
+        let min_x = p.iter().map(|v| v.0).fold(f32::MAX, f32::min).floor().max(clip_x0 as f32) as u32;
+        let min_y = p.iter().map(|v| v.1).fold(f32::MAX, f32::min).floor().max(clip_y0 as f32) as u32;
+        let max_x = p.iter().map(|v| v.0).fold(f32::MIN, f32::max).ceil().min(clip_x1 as f32) as u32;
+        let max_y = p.iter().map(|v| v.1).fold(f32::MIN, f32::max).ceil().min(clip_y1 as f32) as u32;
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let center = (x as f32 + 0.5, y as f32 + 0.5);
+                let w0 = edge(p[1], p[2], center) / area;
+                let w1 = edge(p[2], p[0], center) / area;
+                let w2 = edge(p[0], p[1], center) / area;
+                if w0 < 0.0 || w1 < 0.0 || w2 < 0.0 {
+                    continue;
+                }
+                let mut color = [0.0f32; 4];
+                for c in 0..4 {
+                    color[c] = colors[0][c]*w0 + colors[1][c]*w1 + colors[2][c]*w2;
+                }
+                self.plot(x, y, color);
+            }
+        }
+    }
+
+    fn set_state(&mut self, state: &GfxDrawState) {
+        self.state = *state;
+        self.clip  = match state.clip() {
+            None       => (0, 0, self.image.width, self.image.height),
+            Some(rect) => {
+                // the image goes top down, glium's rectangles bottom up
+                let (left, bottom, width, height) = rect.to_pixels(self.image.width, self.image.height);
+                (left, self.image.height - bottom - height, left + width, self.image.height - bottom)
+            }
+        };
+    }
+
+    fn line(&mut self,
+            a: (f32, f32),
+            b: (f32, f32),
+            color: [f32; 4]) {
+        let length = ((b.0-a.0).powi(2) + (b.1-a.1).powi(2)).sqrt();
+        let steps  = length.ceil().max(1.0) as u32;
+        let half   = self.state.line_width * 0.5;
+        let (clip_x0, clip_y0, clip_x1, clip_y1) = self.clip;
+        let mut last = None;
+        for i in 0..(steps+1) {
+            let t = i as f32 / steps as f32;
+            let center = (a.0 + (b.0-a.0)*t, a.1 + (b.1-a.1)*t);
+            let x0 = (center.0 - half).round().max(clip_x0 as f32) as i64;
+            let y0 = (center.1 - half).round().max(clip_y0 as f32) as i64;
+            let x1 = ((center.0 + half).round() as i64).min(clip_x1 as i64);
+            let y1 = ((center.1 + half).round() as i64).min(clip_y1 as i64);
+            // don't blend the same pixels twice when stepping slowly
+            if last == Some((x0, y0)) {
+                continue;
+            }
+            last = Some((x0, y0));
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    self.plot(x as u32, y as u32, color);
+                }
+            }
+        }
+    }
+}
+
+impl RenderBackend for SoftwareBackend {
+    fn load_program(&mut self, _id: usize, _program: &GfxProgram) -> Result<(), GfxProgramError> {
+        return Ok(());
+    }
+
+    fn load_indices(&mut self, id: usize, indices: &GfxIndices) {
+        if self.indices.len() <= id {
+            self.indices.resize_with(id + 1, || None);
+        }
+        self.indices[id] = Some(indices.clone());
+    }
+
+    fn free_indices(&mut self, id: usize) {
+        if let Some(slot) = self.indices.get_mut(id) {
+            *slot = None;
+        }
+    }
+
+    fn load_instances(&mut self, id: usize, instances: &[GfxInstance]) {
+        if self.instances.len() <= id {
+            self.instances.resize_with(id + 1, || None);
+        }
+        self.instances[id] = Some(instances.to_vec());
+    }
+
+    fn free_instances(&mut self, id: usize) {
+        if let Some(slot) = self.instances.get_mut(id) {
+            *slot = None;
+        }
+    }
+
+    fn load_line_vertices(&mut self,
+                          vertices: &[GfxLineVertex],
+                          dirty: Range<usize>) {
+        self.lines.resize(vertices.len(), GfxLineVertex { position: [0.0, 0.0] });
+        self.lines[dirty.clone()].copy_from_slice(&vertices[dirty]);
+    }
+
+    fn load_triangle_vertices(&mut self,
+                              vertices: &[GfxTriangleVertex],
+                              dirty: Range<usize>) {
+        self.triangles.resize(vertices.len(), GfxTriangleVertex { position: [0.0, 0.0], color: [0.0; 4] });
+        self.triangles[dirty.clone()].copy_from_slice(&vertices[dirty]);
+    }
+
+    fn set_offscreen(&mut self, size: Option<(u32, u32)>) -> Result<(), GfxError> {
+        if let Some((width, height)) = size {
+            check_offscreen_size(width, height)?;
+        }
+        let image = match (size, self.window.take()) {
+            (Some((width, height)), window) => {
+                self.window = Some(window.unwrap_or_else(|| self.image.clone()));
+                Image::new(width, height)
+            },
+            (None, Some(window)) => window,
+            (None, None)         => return Ok(())
+        };
+        self.stencil = vec![0; image.width as usize * image.height as usize];
+        self.image   = image;
+        return Ok(());
+    }
+
+    fn begin_frame(&mut self) -> (u32, u32) {
+        self.image.clear([0, 0, 0, 0]);
+        for value in self.stencil.iter_mut() {
+            *value = 0;
+        }
+        return (self.image.width, self.image.height);
+    }
+
+    fn draw(&mut self,
+            draw_type: GfxDrawType,
+            _program: usize,
+            indices: usize,
+            uniforms: &GfxUniforms,
+            state: &GfxDrawState) {
+        self.set_state(state);
+
+        let (data, primitive_type) = match self.indices.get(indices) {
+            Some(Some(indices)) => (indices.data.clone(), indices.primitive_type),
+            _ => return
+        };
+
+        match draw_type {
+            GfxDrawType::Triangles => {
+                let mut draw_one = |a: u32, b: u32, c: u32| {
+                    let mut p      = [(0.0, 0.0); 3];
+                    let mut colors = [[0.0; 4]; 3];
+                    for (i, index) in [a, b, c].iter().enumerate() {
+                        let vertex = match self.triangles.get(*index as usize) {
+                            Some(vertex) => *vertex,
+                            None => return
+                        };
+                        p[i]      = self.to_pixels(transform_vertex(vertex.position, uniforms));
+                        colors[i] = [vertex.color[0] * uniforms.tint[0],
+                                     vertex.color[1] * uniforms.tint[1],
+                                     vertex.color[2] * uniforms.tint[2],
+                                     vertex.color[3] * uniforms.alpha];
+                    }
+                    self.triangle(p, colors);
+                };
+                match primitive_type {
+                    PrimitiveType::TrianglesList => {
+                        for tri in data.chunks_exact(3) {
+                            draw_one(tri[0], tri[1], tri[2]);
+                        }
+                    },
+                    PrimitiveType::TriangleStrip => {
+                        for i in 2..data.len() {
+                            draw_one(data[i-2], data[i-1], data[i]);
+                        }
+                    },
+                    PrimitiveType::TriangleFan => {
+                        for i in 2..data.len() {
+                            draw_one(data[0], data[i-1], data[i]);
+                        }
+                    },
+                    other => self.errors.push(format!("software backend can't draw triangles as {0:?}", other))
+                }
+            },
+            GfxDrawType::Lines => {
+                // linefragment140 draws white, tinted
+                let white = [uniforms.tint[0], uniforms.tint[1], uniforms.tint[2], uniforms.alpha];
+                let mut draw_one = |a: u32, b: u32| {
+                    let (va, vb) = match (self.lines.get(a as usize), self.lines.get(b as usize)) {
+                        (Some(va), Some(vb)) => (*va, *vb),
+                        _ => return
+                    };
+                    let pa = self.to_pixels(transform_vertex(va.position, uniforms));
+                    let pb = self.to_pixels(transform_vertex(vb.position, uniforms));
+                    self.line(pa, pb, white);
+                };
+                match primitive_type {
+                    PrimitiveType::LinesList => {
+                        for line in data.chunks_exact(2) {
+                            draw_one(line[0], line[1]);
+                        }
+                    },
+                    PrimitiveType::LineStrip | PrimitiveType::LineLoop => {
+                        for i in 1..data.len() {
+                            draw_one(data[i-1], data[i]);
+                        }
+                        if primitive_type == PrimitiveType::LineLoop && data.len() > 2 {
+                            draw_one(data[data.len()-1], data[0]);
+                        }
+                    },
+                    other => self.errors.push(format!("software backend can't draw lines as {0:?}", other))
+                }
+            }
+        }
+    }
+
+    // what triangle_instanced.vert does, as an ordinary draw per instance
+    fn draw_instanced(&mut self,
+                      draw_type: GfxDrawType,
+                      program: usize,
+                      indices: usize,
+                      instances: usize,
+                      uniforms: &GfxUniforms,
+                      state: &GfxDrawState) {
+        let instances = match self.instances.get(instances) {
+            Some(Some(instances)) => instances.clone(),
+            _ => return
+        };
+        for instance in instances.iter() {
+            self.draw(draw_type, program, indices, &instance.uniforms(uniforms), state);
+        }
+    }
+
+    // the triangles through linevertex140, white and tinted
+    fn draw_thick_lines(&mut self,
+                        _program: usize,
+                        _indices: usize,
+                        triangles: &[GfxLineVertex],
+                        uniforms: &GfxUniforms,
+                        state: &GfxDrawState) {
+        self.set_state(state);
+        let white = [uniforms.tint[0], uniforms.tint[1], uniforms.tint[2], uniforms.alpha];
+        for triangle in triangles.chunks_exact(3) {
+            let mut p = [(0.0, 0.0); 3];
+            for (i, vertex) in triangle.iter().enumerate() {
+                p[i] = self.to_pixels(transform_vertex(vertex.position, uniforms));
+            }
+            self.triangle(p, [white; 3]);
+        }
+    }
+
+    fn end_frame(&mut self) {
+    }
+
+    fn read_frame(&mut self) -> Option<Image> {
+        return Some(self.image.clone());
+    }
+}
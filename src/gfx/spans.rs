@@ -0,0 +1,89 @@
+use std::ops::Range;
+
+use super::{GfxDrawType, GfxIndices};
+
+// The vertex spans of every index buffer into one backing array,
+// sorted by where they start, for finding the buffers a write to some
+// vertices touches without going through all of them.  reach[i] is
+// the furthest any of spans[..=i] goes, so the search back from the
+// end of a range stops once nothing earlier can get to it.
+pub struct GfxSpans {
+    spans: Vec< (Range<u32>, usize) >,
+    reach: Vec< u32 >
+}
+
+impl GfxSpans {
+    pub fn new(indices: &[Option<GfxIndices>], draw_type: GfxDrawType) -> GfxSpans {
+        let mut spans: Vec<_> = indices.iter().enumerate().filter_map(|(id, indices)| {
+            match indices {
+                Some(indices) if indices.draw_type() == draw_type && !indices.span.is_empty() => {
+                    Some((indices.span.clone(), id))
+                },
+                _ => None
+            }
+        }).collect();
+        spans.sort_by_key(|(span, _)| span.start);
+        let mut reach = Vec::with_capacity(spans.len());
+        let mut furthest = 0;
+        for (span, _) in spans.iter() {
+            furthest = furthest.max(span.end);
+            reach.push(furthest);
+        }
+        return GfxSpans { spans: spans, reach: reach };
+    }
+
+    // ids of the index buffers using any of vertices
+    pub fn overlapping(&self, vertices: Range<usize>) -> impl Iterator<Item = usize> + '_ {
+        let (start, end) = (vertices.start as u32, vertices.end as u32);
+        let before = self.spans.partition_point(|(span, _)| span.start < end);
+        return (0..before).rev()
+                          .take_while(move |i| self.reach[*i] > start)
+                          .filter(move |i| self.spans[*i].0.end > start)
+                          .map(move |i| self.spans[i].1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glium::index::PrimitiveType;
+
+    fn spans(data: &[&[u32]]) -> GfxSpans {
+        let indices: Vec<_> = data.iter().map(|data| {
+            Some(GfxIndices::new(data.to_vec(), PrimitiveType::TrianglesList))
+        }).collect();
+        return GfxSpans::new(&indices, GfxDrawType::Triangles);
+    }
+
+    fn overlapping(spans: &GfxSpans, vertices: Range<usize>) -> Vec<usize> {
+        let mut ids: Vec<_> = spans.overlapping(vertices).collect();
+        ids.sort();
+        return ids;
+    }
+
+    #[test]
+    fn finds_the_buffers_a_range_touches() {
+        let spans = spans(&[&[0, 1, 2], &[3, 4, 5], &[10, 11, 12], &[6, 7, 8]]);
+        assert_eq!(overlapping(&spans, 0..1), vec![0]);
+        assert_eq!(overlapping(&spans, 2..4), vec![0, 1]);
+        assert_eq!(overlapping(&spans, 9..10), vec![]);
+        assert_eq!(overlapping(&spans, 5..11), vec![1, 2, 3]);
+        assert_eq!(overlapping(&spans, 13..20), vec![]);
+    }
+
+    #[test]
+    fn a_long_span_is_found_past_short_ones() {
+        let spans = spans(&[&[0, 100], &[10, 11], &[20, 21]]);
+        assert_eq!(overlapping(&spans, 50..51), vec![0]);
+        assert_eq!(overlapping(&spans, 20..21), vec![0, 2]);
+    }
+
+    #[test]
+    fn only_the_one_draw_type() {
+        let indices = vec![Some(GfxIndices::new(vec![0, 1], PrimitiveType::LinesList)),
+                           None,
+                           Some(GfxIndices::new(vec![0, 1, 2], PrimitiveType::TrianglesList))];
+        let spans = GfxSpans::new(&indices, GfxDrawType::Lines);
+        assert_eq!(overlapping(&spans, 0..3), vec![0]);
+    }
+}
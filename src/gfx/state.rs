@@ -0,0 +1,189 @@
+use super::transform::GfxTransform;
+
+// how a draw's colours combine with what's already drawn
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GfxBlend {
+    // glium::Blend::alpha_blending()
+    Alpha,
+    // source times its alpha added on top, for glows
+    Additive,
+    // no blending, source replaces what's there
+    Replace
+}
+
+// a rectangle as fractions of the frame, from the bottom left like
+// glium::Rect, so it doesn't care what size the window is
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GfxRect {
+    pub left:   f32,
+    pub bottom: f32,
+    pub width:  f32,
+    pub height: f32
+}
+
+impl GfxRect {
+    pub fn new(left: f32, bottom: f32, width: f32, height: f32) -> GfxRect {
+        GfxRect { left: left, bottom: bottom, width: width, height: height }
+    }
+
+    pub fn full() -> GfxRect {
+        GfxRect::new(0.0, 0.0, 1.0, 1.0)
+    }
+
+    // the part inside both, no width or height if they don't overlap
+    pub fn intersect(&self, other: &GfxRect) -> GfxRect {
+        let left   = self.left.max(other.left);
+        let bottom = self.bottom.max(other.bottom);
+        let right  = (self.left + self.width).min(other.left + other.width);
+        let top    = (self.bottom + self.height).min(other.bottom + other.height);
+        return GfxRect::new(left, bottom, (right - left).max(0.0), (top - bottom).max(0.0));
+    }
+
+    // (left, bottom, width, height) in pixels, clipped to the frame.
+    // A negative width or height comes out as nothing.
+    pub fn to_pixels(&self, frame_width: u32, frame_height: u32) -> (u32, u32, u32, u32) {
+        let to_x = |x: f32| (x * frame_width as f32).round().max(0.0).min(frame_width as f32) as u32;
+        let to_y = |y: f32| (y * frame_height as f32).round().max(0.0).min(frame_height as f32) as u32;
+        let left   = to_x(self.left);
+        let bottom = to_y(self.bottom);
+        return (left,
+                bottom,
+                to_x(self.left + self.width).saturating_sub(left),
+                to_y(self.bottom + self.height).saturating_sub(bottom));
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GfxStencil {
+    Off,
+    // draw as usual and set the stencil to the value wherever it drew
+    Write(u8),
+    // only draw where the stencil is, or isn't, the value
+    Equal(u8),
+    NotEqual(u8)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GfxLineUnits {
+    // the units the vertices are in, so it zooms with everything else
+    World,
+    // the same on screen however it's transformed
+    Pixels
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GfxLineJoin {
+    // corners sharper than about 30 degrees are bevelled instead
+    Miter,
+    Bevel,
+    Round
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GfxLineCap {
+    // stops at the end vertex
+    Butt,
+    // half the width past the end vertex
+    Square,
+    Round
+}
+
+// Lines drawn as triangles, width across, rather than leaving
+// line_width to the driver, which may cap it or ignore it
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GfxLineStyle {
+    pub width: f32,
+    pub units: GfxLineUnits,
+    pub join:  GfxLineJoin,
+    pub cap:   GfxLineCap
+}
+
+impl GfxLineStyle {
+    // mitered and butt ended, change join and cap after for others
+    pub fn new(width: f32, units: GfxLineUnits) -> GfxLineStyle {
+        GfxLineStyle { width: width,
+                       units: units,
+                       join:  GfxLineJoin::Miter,
+                       cap:   GfxLineCap::Butt }
+    }
+
+    // half the width in vertex space, for lines drawn with transform
+    // into a viewport pixels across.  A transform that doesn't scale x
+    // and y alike gets the average.
+    pub fn half_width(&self, transform: &GfxTransform, pixels: (u32, u32)) -> f32 {
+        match self.units {
+            GfxLineUnits::World  => return self.width * 0.5,
+            GfxLineUnits::Pixels => {
+                let m = &transform.m;
+                let area = (m[0]*m[3] - m[2]*m[1]).abs() * (pixels.0 as f32 * 0.5) * (pixels.1 as f32 * 0.5);
+                if area == 0.0 {
+                    return 0.0;
+                }
+                return self.width * 0.5 / area.sqrt();
+            }
+        }
+    }
+}
+
+// Everything about a draw that isn't the program, the indices or the
+// uniforms.  Set by commands in the list the same way Program and
+// Indices are, and reset to new() at the start of every run.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GfxDrawState {
+    pub blend:      GfxBlend,
+    pub line_width: f32,
+    // None leaves lines to the driver at line_width
+    pub line_style: Option<GfxLineStyle>,
+    pub scissor:    Option<GfxRect>,
+    pub stencil:    GfxStencil,
+    // draws go back to front by layer, and in the order they come in
+    // the list within one, so stencil writes and reads only line up
+    // in the same layer or going up
+    pub layer:      i32,
+    // which part of the frame clip space maps to, set from the
+    // viewport being drawn rather than by a command
+    pub viewport:   GfxRect
+}
+
+impl GfxDrawState {
+    // what every draw used before there were commands for it
+    pub fn new() -> GfxDrawState {
+        GfxDrawState { blend:      GfxBlend::Alpha,
+                       line_width: 2.0,
+                       line_style: None,
+                       scissor:    None,
+                       stencil:    GfxStencil::Off,
+                       layer:      0,
+                       viewport:   GfxRect::full() }
+    }
+
+    // what to actually scissor to, inside the viewport and the
+    // scissor both, None when that's the whole frame
+    pub fn clip(&self) -> Option<GfxRect> {
+        let clip = match self.scissor {
+            Some(scissor) => scissor.intersect(&self.viewport),
+            None          => self.viewport
+        };
+        if clip == GfxRect::full() {
+            return None;
+        }
+        return Some(clip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_pixels_clips_to_the_frame() {
+        assert_eq!(GfxRect::full().to_pixels(640, 480), (0, 0, 640, 480));
+        assert_eq!(GfxRect::new(0.5, 0.25, 0.25, 0.5).to_pixels(640, 480), (320, 120, 160, 240));
+        assert_eq!(GfxRect::new(-0.5, 0.5, 2.0, 2.0).to_pixels(640, 480), (0, 240, 640, 240));
+    }
+
+    #[test]
+    fn to_pixels_negative_size_is_empty() {
+        assert_eq!(GfxRect::new(0.5, 0.5, -0.25, -0.25).to_pixels(640, 480), (320, 240, 0, 0));
+    }
+}
@@ -0,0 +1,52 @@
+use std::fmt;
+use std::time::Duration;
+
+// What the last Gfx::run cost.  vertices counts each draw's span of
+// the backing array, so it's what the gpu had to look at rather than
+// how many distinct vertices there were, and an instanced draw counts
+// it once per instance.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GfxFrameStats {
+    pub width:              u32,
+    pub height:             u32,
+    pub draw_calls:         usize,
+    pub culled:             usize,
+    pub instances:          usize,
+    pub skipped:            usize,
+    pub vertices:           usize,
+    pub indices:            usize,
+    pub vertices_uploaded:  usize,
+    pub indices_uploaded:   usize,
+    pub instances_uploaded: usize,
+    pub programs_uploaded:  usize,
+    // walking the command list and handing draws to the backend, not
+    // waiting for the frame to be presented
+    pub render_time:        Duration
+}
+
+impl GfxFrameStats {
+    pub fn new() -> GfxFrameStats {
+        GfxFrameStats { width:             0,
+                        height:            0,
+                        draw_calls:        0,
+                        culled:            0,
+                        instances:         0,
+                        skipped:           0,
+                        vertices:          0,
+                        indices:           0,
+                        vertices_uploaded: 0,
+                        indices_uploaded:  0,
+                        instances_uploaded: 0,
+                        programs_uploaded: 0,
+                        render_time:       Duration::from_secs(0) }
+    }
+}
+
+impl fmt::Display for GfxFrameStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{0} draws ({1} culled, {2} skipped, {3} instances), {4} vertices, {5} indices, uploaded {6} vertices {7} index buffers {8} instance buffers {9} programs, {10:.2}ms",
+               self.draw_calls, self.culled, self.skipped, self.instances, self.vertices, self.indices,
+               self.vertices_uploaded, self.indices_uploaded, self.instances_uploaded, self.programs_uploaded,
+               self.render_time.as_secs_f64() * 1000.0)
+    }
+}
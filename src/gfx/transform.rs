@@ -0,0 +1,148 @@
+// 2d affine transform, the top two rows of
+//
+//   | a c e |
+//   | b d f |
+//   | 0 0 1 |
+//
+// stored as [a, b, c, d, e, f]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GfxTransform {
+    pub m: [f32; 6]
+}
+
+impl GfxTransform {
+    pub fn identity() -> GfxTransform {
+        GfxTransform { m: [1.0, 0.0, 0.0, 1.0, 0.0, 0.0] }
+    }
+
+    pub fn translation(x: f32, y: f32) -> GfxTransform {
+        GfxTransform { m: [1.0, 0.0, 0.0, 1.0, x, y] }
+    }
+
+    pub fn rotation(angle: f32) -> GfxTransform {
+        let sina = angle.sin();
+        let cosa = angle.cos();
+        GfxTransform { m: [cosa, sina, -sina, cosa, 0.0, 0.0] }
+    }
+
+    pub fn scale(x: f32, y: f32) -> GfxTransform {
+        GfxTransform { m: [x, 0.0, 0.0, y, 0.0, 0.0] }
+    }
+
+    // x moves by x_shear*y, y moves by y_shear*x
+    pub fn shear(x_shear: f32, y_shear: f32) -> GfxTransform {
+        GfxTransform { m: [1.0, y_shear, x_shear, 1.0, 0.0, 0.0] }
+    }
+
+    // self * other, ie. other is applied first
+    pub fn multiply(&self, other: &GfxTransform) -> GfxTransform {
+        let a = &self.m;
+        let b = &other.m;
+        GfxTransform { m: [a[0]*b[0] + a[2]*b[1],
+                           a[1]*b[0] + a[3]*b[1],
+                           a[0]*b[2] + a[2]*b[3],
+                           a[1]*b[2] + a[3]*b[3],
+                           a[0]*b[4] + a[2]*b[5] + a[4],
+                           a[1]*b[4] + a[3]*b[5] + a[5]] }
+    }
+
+    // None if it squashes everything onto a line or a point
+    pub fn inverse(&self) -> Option<GfxTransform> {
+        let m = &self.m;
+        let det = m[0]*m[3] - m[2]*m[1];
+        if det == 0.0 {
+            return None;
+        }
+        let a =  m[3] / det;
+        let b = -m[1] / det;
+        let c = -m[2] / det;
+        let d =  m[0] / det;
+        return Some(GfxTransform { m: [a, b, c, d,
+                                       -(a*m[4] + c*m[5]),
+                                       -(b*m[4] + d*m[5])] });
+    }
+
+    pub fn apply(&self, point: (f32, f32)) -> (f32, f32) {
+        return (self.m[0]*point.0 + self.m[2]*point.1 + self.m[4],
+                self.m[1]*point.0 + self.m[3]*point.1 + self.m[5]);
+    }
+
+    // column major, the way glsl wants a mat3
+    pub fn to_mat3(&self) -> [[f32; 3]; 3] {
+        return [[self.m[0], self.m[1], 0.0],
+                [self.m[2], self.m[3], 0.0],
+                [self.m[4], self.m[5], 1.0]];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: (f32, f32), b: (f32, f32)) -> bool {
+        return (a.0 - b.0).abs() < 1e-5 && (a.1 - b.1).abs() < 1e-5;
+    }
+
+    fn close_transform(a: &GfxTransform, b: &GfxTransform) -> bool {
+        return a.m.iter().zip(b.m.iter()).all(|(a, b)| (a - b).abs() < 1e-5);
+    }
+
+    #[test]
+    fn identity_changes_nothing() {
+        let t = GfxTransform::translation(3.0, -2.0).multiply(&GfxTransform::rotation(0.7));
+        assert_eq!(GfxTransform::identity().multiply(&t), t);
+        assert_eq!(t.multiply(&GfxTransform::identity()), t);
+        assert_eq!(GfxTransform::identity().apply((1.5, -4.0)), (1.5, -4.0));
+    }
+
+    #[test]
+    fn multiply_applies_the_right_hand_side_first() {
+        let scale_then_move = GfxTransform::translation(10.0, 0.0).multiply(&GfxTransform::scale(2.0, 3.0));
+        assert!(close(scale_then_move.apply((1.0, 1.0)), (12.0, 3.0)));
+        let move_then_scale = GfxTransform::scale(2.0, 3.0).multiply(&GfxTransform::translation(10.0, 0.0));
+        assert!(close(move_then_scale.apply((1.0, 1.0)), (22.0, 3.0)));
+    }
+
+    #[test]
+    fn rotation_turns_the_same_way_as_rotate() {
+        let quarter = GfxTransform::rotation(std::f32::consts::FRAC_PI_2);
+        assert!(close(quarter.apply((1.0, 0.0)), (0.0, 1.0)));
+        assert!(close(quarter.apply((0.0, 1.0)), (-1.0, 0.0)));
+        for angle in [0.3f32, -1.2, 2.5].iter() {
+            let point = (2.0, -0.5);
+            assert!(close(GfxTransform::rotation(*angle).apply(point), crate::gfx::rotate(point, *angle)));
+        }
+    }
+
+    #[test]
+    fn shear_moves_across() {
+        assert!(close(GfxTransform::shear(0.5, 0.0).apply((1.0, 2.0)), (2.0, 2.0)));
+        assert!(close(GfxTransform::shear(0.0, 0.5).apply((2.0, 1.0)), (2.0, 2.0)));
+    }
+
+    #[test]
+    fn inverse_undoes() {
+        let t = GfxTransform::translation(3.0, -2.0)
+                    .multiply(&GfxTransform::rotation(0.7))
+                    .multiply(&GfxTransform::shear(0.2, -0.1))
+                    .multiply(&GfxTransform::scale(2.0, 0.5));
+        let inverse = t.inverse().unwrap();
+        assert!(close_transform(&t.multiply(&inverse), &GfxTransform::identity()));
+        assert!(close_transform(&inverse.multiply(&t), &GfxTransform::identity()));
+        let point = (1.25, -7.5);
+        assert!(close(inverse.apply(t.apply(point)), point));
+    }
+
+    #[test]
+    fn flat_has_no_inverse() {
+        assert_eq!(GfxTransform::scale(1.0, 0.0).inverse(), None);
+        assert_eq!(GfxTransform::scale(0.0, 0.0).inverse(), None);
+    }
+
+    #[test]
+    fn mat3_is_column_major() {
+        let mat = GfxTransform::translation(4.0, 5.0).to_mat3();
+        assert_eq!(mat[2], [4.0, 5.0, 1.0]);
+        assert_eq!(mat[0], [1.0, 0.0, 0.0]);
+    }
+}
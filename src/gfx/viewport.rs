@@ -0,0 +1,99 @@
+use std::ops::Range;
+
+use super::{CommandHandle, Gfx, GfxError};
+use super::state::GfxRect;
+
+// Where a viewport looks from, in place of the Origin, SceneScale and
+// ViewRotate commands in the list, which it then ignores.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GfxView {
+    pub origin:   (f32, f32),
+    pub scale:    f32,
+    pub rotation: f32
+}
+
+impl GfxView {
+    pub fn new(origin: (f32, f32), scale: f32, rotation: f32) -> GfxView {
+        GfxView { origin: origin, scale: scale, rotation: rotation }
+    }
+}
+
+// A named part of the frame that run() draws the command list into,
+// or the commands from first to last.  Viewports are drawn in the
+// order they were added, later ones over earlier ones, each starting
+// from the same state as a whole run.  A Gfx starts out with "main",
+// the whole frame and the whole list.
+#[derive(Clone, Debug)]
+pub struct GfxViewport {
+    pub name:     String,
+    pub rect:     GfxRect,
+    pub view:     Option<GfxView>,
+    pub commands: Option<(CommandHandle, CommandHandle)>
+}
+
+impl GfxViewport {
+    pub fn new(name: &str, rect: GfxRect) -> GfxViewport {
+        GfxViewport { name:     name.to_string(),
+                      rect:     rect,
+                      view:     None,
+                      commands: None }
+    }
+}
+
+impl Gfx {
+    fn viewport_mut(&mut self, name: &str) -> Result<&mut GfxViewport, GfxError> {
+        match self.viewports.iter_mut().find(|viewport| viewport.name == name) {
+            Some(viewport) => return Ok(viewport),
+            None           => return Err(GfxError::NoSuchViewport(name.to_string()))
+        }
+    }
+
+    // adds one on top of the others, or moves it if it's already there
+    pub fn set_viewport(&mut self, name: &str, rect: GfxRect) {
+        match self.viewport_mut(name) {
+            Ok(viewport) => viewport.rect = rect,
+            Err(_)       => self.viewports.push(GfxViewport::new(name, rect))
+        }
+    }
+
+    pub fn remove_viewport(&mut self, name: &str) -> Result<(), GfxError> {
+        self.viewport_mut(name)?;
+        self.viewports.retain(|viewport| viewport.name != name);
+        return Ok(());
+    }
+
+    // None to go back to the view commands in the list
+    pub fn set_viewport_view(&mut self, name: &str, view: Option<GfxView>) -> Result<(), GfxError> {
+        self.viewport_mut(name)?.view = view;
+        return Ok(());
+    }
+
+    // None to go back to the whole list
+    pub fn set_viewport_commands(&mut self,
+                                 name: &str,
+                                 commands: Option<(CommandHandle, CommandHandle)>) -> Result<(), GfxError> {
+        if let Some((first, last)) = commands {
+            self.position(first)?;
+            self.position(last)?;
+        }
+        self.viewport_mut(name)?.commands = commands;
+        return Ok(());
+    }
+
+    pub fn viewports(&self) -> &[GfxViewport] {
+        return &self.viewports;
+    }
+
+    // the positions in the list a viewport draws, None if one of its
+    // commands has been freed since
+    pub(super) fn viewport_range(&self, viewport: &GfxViewport) -> Option<Range<usize>> {
+        match viewport.commands {
+            None                => return Some(0..(self.commands.len() + self.inserting.len())),
+            Some((first, last)) => {
+                let first = self.position(first).ok()?;
+                let last  = self.position(last).ok()?;
+                return Some(first.min(last)..(first.max(last) + 1));
+            }
+        }
+    }
+}
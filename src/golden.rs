@@ -0,0 +1,80 @@
+// Golden image tests: a few close ups of the starting scene drawn by
+// the software backend and held against the pngs in tests/golden.
+// Run with UPDATE_GOLDEN=1 to write them afresh after a change that's
+// meant to show.
+
+use crate::*;
+
+const WIDTH:  u32 = 320;
+const HEIGHT: u32 = 240;
+// pixels allowed to come out differently, for rounding at the edges
+const TOLERANCE: usize = (WIDTH * HEIGHT / 200) as usize;
+// how far a colour can be off before the pixel counts as different,
+// for float rounding in the transforms
+const SLACK: u8 = 4;
+
+fn render(position: (f32, f32), scale: f32) -> gfx::image::Image {
+    let mut assets = assets::build_assets();
+    let mut gfx = gfx::Gfx::new();
+    let mut shaders = gfx::shaders::ShaderLibrary::new(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders"));
+    let programs = Programs { lines:               shaders.load(&mut gfx, "line").unwrap(),
+                              triangles:           shaders.load(&mut gfx, "triangle").unwrap(),
+                              instanced_triangles: shaders.load(&mut gfx, "triangle_instanced").unwrap() };
+    camera::Camera::new(&mut gfx, position, scale);
+    let mut planet = Planet::new((0.0, 0.0),
+                                 1000.0,
+                                 1000.0,
+                                 Planet::geometry(&mut gfx, &mut assets, &programs, 1000.0, &PLANET_BACKGROUND).unwrap());
+    let mut player_ship = PlayerShip::new(PlayerShip::geometry(&mut gfx, &programs).unwrap());
+    player_ship.tick(&mut gfx).unwrap();
+    planet.tick(&mut gfx, gfx::get_angle(player_ship.position, planet.position)).unwrap();
+
+    let mut backend = gfx::software::SoftwareBackend::new(WIDTH, HEIGHT);
+    gfx.run(&mut backend);
+    assert!(backend.errors.is_empty(), "{0:?}", backend.errors);
+    return backend.image().clone();
+}
+
+fn differing_pixels(a: &gfx::image::Image, b: &gfx::image::Image) -> usize {
+    return a.pixels.chunks(4).zip(b.pixels.chunks(4)).filter(|(a, b)| {
+        (0..3).any(|c| (a[c] as i32 - b[c] as i32).unsigned_abs() > SLACK as u32)
+    }).count();
+}
+
+fn check(name: &str, image: &gfx::image::Image) {
+    let path = format!("{0}/tests/golden/{1}.png", env!("CARGO_MANIFEST_DIR"), name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        image.save_png(&path).unwrap();
+        return;
+    }
+    let expected = match gfx::image::Image::load_png(&path) {
+        Ok(expected) => expected,
+        Err(error)   => panic!("{0}: {1}, UPDATE_GOLDEN=1 writes it", path, error)
+    };
+    let difference = match image.difference(&expected) {
+        Some(difference) => difference,
+        None             => panic!("{0} is {1}x{2}, not {3}x{4}", path, expected.width, expected.height, WIDTH, HEIGHT)
+    };
+    let differing = differing_pixels(image, &expected);
+    if differing > TOLERANCE {
+        let actual = std::env::temp_dir().join(format!("{0}.png", name));
+        image.save_png(&actual.to_string_lossy()).unwrap();
+        panic!("{0} pixels differ from {1} by up to {2}, see {3}",
+               differing, path, difference.max_difference, actual.display());
+    }
+}
+
+#[test]
+fn city_block() {
+    check("city_block", &render((8.0, 1002.0), 0.12));
+}
+
+#[test]
+fn windows() {
+    check("windows", &render((9.8, 1001.0), 0.6));
+}
+
+#[test]
+fn ship() {
+    check("ship", &render((0.0, 1000.0), 0.4));
+}
@@ -2,7 +2,17 @@
 extern crate glium;
 
 mod assets;
+mod camera;
+mod geometry;
 mod gfx;
+#[cfg(test)]
+mod golden;
+mod parallax;
+mod particles;
+mod profiler;
+mod radar;
+mod recorder;
+mod zoom;
 
 #[allow(unused_imports)]
 use glium::{glutin, Surface};
@@ -10,6 +20,8 @@ use glium::index::PrimitiveType;
 
 use std::collections::HashMap;
 
+use gfx::RenderBackend;
+
 const ROTATE_LEFT: u32 = 1;
 const ROTATE_RIGHT: u32 = 2;
 const THRUST_ON: u32 = 4;
@@ -18,29 +30,115 @@ const LANDED: u32 = 8;
 const GEAR_CLOSED_ANGLE: f32 = 1.1;
 const FOOT_CLOSED_ANGLE: f32 = -3.14159/2.0 + 0.45;
 const GEAR_STEPS: u32 = 200;
+// the ship's scale times the scene scale, so 0.2 landed at 0.2
+const SHIP_SIZE: f32 = 0.04;
+
+// where the city starts round the planet and how wide it is, in
+// blocks of CITY_WIDTH / CITY_BLOCKS
+const CITY_ANGLE: f32 = 0.0;
+const CITY_WIDTH: f32 = 100.0;
+const CITY_BLOCKS: u32 = 1;
+const LANDING_SITE_COLOR: (f32, f32, f32, f32) = (1.0, 0.8, 0.2, 1.0);
+// about a pixel across at the starting zoom
+const WINDOW_LINE_WIDTH: f32 = 0.05;
+
+// what's drawn over what, back to front, whatever order things were
+// built in
+const SKY_LAYER:     i32 = 0;
+const HILLS_LAYER:   i32 = 10;
+const GROUND_LAYER:  i32 = 20;
+const EFFECTS_LAYER: i32 = 30;
+const SHIP_LAYER:    i32 = 40;
+
+// the planet pulls with GRAVITY over the distance squared
+const GRAVITY: f32 = 2000.0;
+
+// where things come off the ship, in its own units before scaling
+const NOZZLES: [(f32, f32); 2] = [(-1.35, -12.5), (1.35, -12.5)];
+const FEET:    [(f32, f32); 2] = [(-5.0, -12.5), (5.0, -12.5)];
+// thrusting lower than this kicks up dust from the ground
+const DUST_ALTITUDE: f32 = 15.0;
+// touching down faster than this throws sparks even with the gear down
+const HARD_LANDING: f32 = 0.2;
+
+const EXHAUST: particles::ParticleStyle = particles::ParticleStyle {
+    speed:       (0.4, 0.7),
+    spread:      0.15,
+    life:        (20, 40),
+    size:        (1.5, 4.0),
+    gravity:     0.3,
+    drag:        0.02,
+    start_color: (1.0, 0.8, 0.2, 0.8),
+    end_color:   (0.6, 0.1, 0.0, 0.0)
+};
+const DUST: particles::ParticleStyle = particles::ParticleStyle {
+    speed:       (0.2, 0.5),
+    spread:      1.4,
+    life:        (40, 80),
+    size:        (2.0, 6.0),
+    gravity:     0.5,
+    drag:        0.03,
+    start_color: (0.55, 0.5, 0.45, 0.6),
+    end_color:   (0.55, 0.5, 0.45, 0.0)
+};
+const SPARKS: particles::ParticleStyle = particles::ParticleStyle {
+    speed:       (0.5, 1.2),
+    spread:      1.2,
+    life:        (10, 25),
+    size:        (0.6, 0.2),
+    gravity:     1.0,
+    drag:        0.0,
+    start_color: (1.0, 0.9, 0.5, 1.0),
+    end_color:   (1.0, 0.3, 0.0, 0.0)
+};
+
+// what stands behind the ground, see parallax::ParallaxLayer
+const PLANET_BACKGROUND: [parallax::ParallaxLayer; 2] = [
+    parallax::ParallaxLayer {
+        depth:      0.3,
+        height:     tall_mountains,
+        altitude:   0.0,
+        divisions:  1500,
+        top_color:  (0.05, 0.05, 0.1, 1.0),
+        base_color: (0.2, 0.2, 0.5, 1.0),
+        scroll:     0.0
+    },
+    parallax::ParallaxLayer {
+        depth:      0.35,
+        height:     short_mountains,
+        altitude:   0.0,
+        divisions:  1000,
+        top_color:  (0.05, 0.05, 0.1, 1.0),
+        base_color: (0.2, 0.2, 0.5, 1.0),
+        scroll:     0.0
+    }
+];
 
-pub fn render_asset(asset: &mut assets::asset::Asset, 
-                    gfx: &mut gfx::Gfx,
-                    indices: &mut Vec< u32 >,
-                    distance: f32, 
-                    angle: f32) {
-    let origin =  (angle.sin()*distance, angle.cos()*distance);
+// one copy of an asset standing at the origin, for drawing wherever
+// it's placed with instanced_draw
+pub fn asset_geometry(asset: &mut assets::asset::Asset, 
+                      gfx: &mut gfx::Gfx) -> gfx::IndexHandle {
     let poly = asset.get_poly(0);
+    let mut indices = Vec::new();
     
     let start_vert = gfx.triangle_len();
 
     for vertex in &poly.vertices {
-        let mut v2 = *vertex;
-        v2.0 *= 0.1;
-        v2.1 *= 0.1;
-        gfx.add_triangle_vertex( gfx::add_points(origin, gfx::rotate(v2, angle * -1.0)),
-                                 (poly.color.0 * 0.2, poly.color.1 * 0.2,  poly.color.2 * 0.2, poly.color.3) );
+        gfx.add_triangle_vertex( *vertex, poly.color );
     }
 
     for index in &poly.drawlist {
         indices.push((start_vert as u32)+(*index as u32));
     }
-        
+    return gfx.add_indices(&indices, PrimitiveType::TrianglesList);
+}
+
+// an asset standing on the ground distance from the middle of the
+// planet, upright at angle
+pub fn place_asset(instances: &mut Vec< gfx::instances::GfxInstance >,
+                   distance: f32, 
+                   angle: f32) {
+    instances.push(gfx::instances::GfxInstance::new(gfx::place(angle, distance), angle * -1.0, 0.1));
 }
 
 fn width_to_angle(width: f32,
@@ -79,99 +177,161 @@ enum PaneStyle {
     Tinted
 }
 
+// what a geometry() function hands back to its owner
+struct GfxHandles {
+    commands:  HashMap<String, gfx::CommandHandle>,
+    indices:   HashMap<String, gfx::IndexHandle>,
+    vertices:  HashMap<String, gfx::VertexHandle>,
+    instances: HashMap<String, gfx::InstanceHandle>
+}
+
+impl GfxHandles {
+    fn new() -> GfxHandles {
+        GfxHandles { commands:  HashMap::new(),
+                     indices:   HashMap::new(),
+                     vertices:  HashMap::new(),
+                     instances: HashMap::new() }
+    }
+}
+
+struct Programs {
+    lines:               gfx::ProgramHandle,
+    triangles:           gfx::ProgramHandle,
+    instanced_triangles: gfx::ProgramHandle
+}
+
 struct Planet {
     position: (f32, f32),
     velocity: (f32, f32),
     mass: f32,
     size: f32,
-    hills_trans: usize,
-    mountains_trans: usize,
-    hills_geometry: usize,
-    mountains_geometry: usize,
-    sky_geometry: usize,
-    horizon_geometry: usize,
+    background: parallax::Parallax,
+    sky_geometry: gfx::IndexHandle,
+    horizon_geometry: gfx::IndexHandle,
+    gfx_commands: (gfx::CommandHandle, gfx::CommandHandle),
+    gfx_indices: Vec< gfx::IndexHandle >,
+    gfx_vertices: Vec< gfx::VertexHandle >,
+    gfx_instances: Vec< gfx::InstanceHandle >
 }
 
 impl Planet {
     fn new(position: (f32, f32),
            mass: f32,
            size: f32,
-           gfx_geometry: HashMap<String, usize>) -> Planet {
+           (gfx_geometry, background): (GfxHandles, parallax::Parallax)) -> Planet {
         Planet { position:           position,
                  velocity:           (0.0, 0.0),
                  mass:               mass,
                  size:               size,
-                 hills_trans:        gfx_geometry["hills_trans"],
-                 mountains_trans:    gfx_geometry["mountains_trans"],
-                 hills_geometry:     gfx_geometry["hills"],
-                 mountains_geometry: gfx_geometry["mountains"],
-                 sky_geometry:       gfx_geometry["sky"],
-                 horizon_geometry:   gfx_geometry["horizon"]
+                 background:         background,
+                 sky_geometry:       gfx_geometry.indices["sky"],
+                 horizon_geometry:   gfx_geometry.indices["horizon"],
+                 gfx_commands:       (gfx_geometry.commands["first"], gfx_geometry.commands["last"]),
+                 gfx_indices:        gfx_geometry.indices.values().cloned().collect(),
+                 gfx_vertices:       gfx_geometry.vertices.values().cloned().collect(),
+                 gfx_instances:      gfx_geometry.instances.values().cloned().collect()
         }
     }
 
-    fn tick(&mut self, gfx: &mut gfx::Gfx, angle: f32) {
-        gfx.change_translation(self.hills_trans, 
-                               (angle.sin()*(self.size - (self.size*0.35)),
-                               angle.cos()*(self.size - (self.size*0.35))));
-        gfx.change_translation(self.mountains_trans, 
-                               (angle.sin()*(self.size - (self.size*0.3)),
-                               angle.cos()*(self.size - (self.size*0.3))));
+    // gives back everything geometry() made, so another planet can be
+    // built in its place
+    fn free(&self, gfx: &mut gfx::Gfx) -> Result<(), gfx::GfxError> {
+        gfx.free_commands(self.gfx_commands.0, self.gfx_commands.1)?;
+        for indices in &self.gfx_indices {
+            gfx.free_indices(*indices)?;
+        }
+        for vertices in &self.gfx_vertices {
+            gfx.free_vertices(*vertices)?;
+        }
+        for instances in &self.gfx_instances {
+            gfx.free_instances(*instances)?;
+        }
+        return self.background.free_indices(gfx);
+    }
+
+    // the middle of each city, for finding them on the radar
+    fn landing_sites(&self) -> Vec< (f32, f32) > {
+        let angle = CITY_ANGLE + width_to_angle(CITY_WIDTH, self.size) / 2.0;
+        return vec![gfx::add_points(self.position, gfx::place(angle, self.size))];
+    }
+
+    fn tick(&mut self, gfx: &mut gfx::Gfx, angle: f32) -> Result<(), gfx::GfxError> {
+        return self.background.tick(gfx, angle);
     }
 
         fn geometry(gfx: &mut gfx::Gfx, 
-                    display: &glium::Display,
                     assets: &mut assets::asset::Assets,
-                    radius: f32) -> HashMap<String, usize> {
-            let mut handles = HashMap::new(); 
-
-            handles.insert("horizon".to_string(),      
-                           Planet::circle(gfx, &display, 500, radius));
-            handles.insert("sky".to_string(),             
-                           Planet::sky(gfx, &display, radius, 16.0, 1000));
-            handles.insert("mountains".to_string(), 
-                           Planet::mountains(gfx, &display, tall_mountains, radius*0.3, 1500));
-            handles.insert("hills".to_string(),     
-                           Planet::mountains(gfx, &display, short_mountains, radius*0.35, 1000));
+                    programs: &Programs,
+                    radius: f32,
+                    background: &[parallax::ParallaxLayer]) -> Result<(GfxHandles, parallax::Parallax), gfx::GfxError> {
+            let mut handles = GfxHandles::new(); 
+            let line_start     = gfx.line_len();
+            let triangle_start = gfx.triangle_len();
+
+            handles.indices.insert("horizon".to_string(),      
+                                   Planet::circle(gfx, 500, radius));
+            handles.indices.insert("sky".to_string(),             
+                                   Planet::sky(gfx, radius, 16.0, 1000));
+            let mut background = parallax::Parallax::new(gfx, radius, background);
            
-            let foreground = Planet::foreground(gfx, &display,  assets, radius);
-            handles.insert("foreground".to_string(), foreground.0);
-            handles.insert("foreground_lines".to_string(), foreground.1);
+            let (blocks, lampposts) = Planet::foreground(gfx, radius);
+            for (i, (triangles, lines)) in blocks.iter().enumerate() {
+                handles.indices.insert(format!("block{0}", i), *triangles);
+                handles.indices.insert(format!("block{0}_lines", i), *lines);
+            }
+            handles.indices.insert("lamppost".to_string(), 
+                                   asset_geometry(assets.get_asset(&"lamppost".to_string(), &"1".to_string()), gfx));
+            handles.instances.insert("lampposts".to_string(), gfx.add_instances(&lampposts));
+
+            handles.vertices.insert("lines".to_string(), gfx.claim_line_vertices(line_start));
+            handles.vertices.insert("triangles".to_string(), gfx.claim_triangle_vertices(triangle_start));
 
             // draw sky
-            gfx.program(1);
+            handles.commands.insert("first".to_string(), gfx.program(programs.triangles)?);
+            gfx.layer(SKY_LAYER);
             gfx.translate(0.0,0.0);
-            gfx.indices(handles["sky"]);
-            gfx.triangle_draw();
-
-            // tall mountains
-            handles.insert("mountains_trans".to_string(), gfx.translate(0.0,radius - (0.3 * radius)));
-            gfx.indices(handles["mountains"]);
-            gfx.triangle_draw();
-
-            // hills
-            handles.insert("hills_trans".to_string(), gfx.translate(0.0,radius - (0.35 * radius)));
-            gfx.indices(handles["hills"]);
+            gfx.indices(handles.indices["sky"])?;
             gfx.triangle_draw();
 
-            gfx.translate(0.0, 0.0);
-            // foreground (cities, etc)
-            gfx.indices(handles["foreground"]);
-            gfx.triangle_draw();
+            // mountains and hills
+            gfx.layer(HILLS_LAYER);
+            background.draw(gfx)?;
+
+            gfx.layer(GROUND_LAYER);
+            // lampposts and the like, in shadow, one copy of each kind
+            // drawn wherever they stand
+            gfx.program(programs.instanced_triangles)?;
+            gfx.tint(0.2, 0.2, 0.2);
+            gfx.indices(handles.indices["lamppost"])?;
+            gfx.instanced_draw(handles.instances["lampposts"])?;
+            gfx.tint(1.0, 1.0, 1.0);
+            gfx.program(programs.triangles)?;
+
+            // foreground (cities, etc), a draw per block so the ones
+            // off screen are culled
+            for i in 0..blocks.len() {
+                gfx.indices(handles.indices[&format!("block{0}", i)])?;
+                gfx.triangle_draw();
+            }
 
-            gfx.program(0);
-            gfx.indices(handles["foreground_lines"]);
-            gfx.line_draw();
-            gfx.indices(handles["horizon"]);
+            // window outlines zoom with the buildings, the horizon
+            // stays the same on screen
+            gfx.program(programs.lines)?;
+            gfx.line_style(Some(gfx::state::GfxLineStyle::new(WINDOW_LINE_WIDTH, gfx::state::GfxLineUnits::World)));
+            for i in 0..blocks.len() {
+                gfx.indices(handles.indices[&format!("block{0}_lines", i)])?;
+                gfx.line_draw();
+            }
+            gfx.line_style(Some(gfx::state::GfxLineStyle::new(3.0, gfx::state::GfxLineUnits::Pixels)));
+            gfx.indices(handles.indices["horizon"])?;
             gfx.line_draw();
+            handles.commands.insert("last".to_string(), gfx.line_style(None));
             
-            return handles;
+            return Ok((handles, background));
         }
 
 
-        fn door(gfx: &mut gfx::Gfx, 
-                indices: &mut Vec< u32 >,
-                line_indices: &mut Vec< u32 >,
+        fn door(geometry: &mut geometry::GeometryBuilder, 
                 angle: f32,
                 radius: f32,
                 extents: (f32, f32),
@@ -181,31 +341,17 @@ impl Planet {
             let door_color        = (0.8, 0.8, 0.8, 1.0);
             let window_inset      = 0.2;
 
-            let start_vert    = gfx.triangle_len();
-            gfx.add_triangle_vertex( gfx::place(angle, radius), 
-                                     door_color);
-            gfx.add_triangle_vertex( gfx::place(angle, radius+extents.1), 
-                                     door_color);
-            gfx.add_triangle_vertex( gfx::place(angle+width_to_angle(extents.0, radius), radius), 
-                                     door_color);
-            gfx.add_triangle_vertex( gfx::place(angle+width_to_angle(extents.0, radius), radius+extents.1), 
-                                     door_color);
-            indices.push((start_vert as u32)+0);
-            indices.push((start_vert as u32)+1);
-            indices.push((start_vert as u32)+2);
-            indices.push((start_vert as u32)+1);
-            indices.push((start_vert as u32)+2);
-            indices.push((start_vert as u32)+3);
+            geometry.curved_rect((angle, angle+width_to_angle(extents.0, radius)),
+                                 (radius, radius+extents.1),
+                                 [door_color; 4]);
             
-            Planet::window(gfx, indices, line_indices, 
+            Planet::window(geometry, 
                            angle+width_to_angle(window_inset, radius),
                            radius + 0.4, (extents.0, extents.1), WindowStyle::Plain);
             return 0;
         }
 
-        fn windowpane(gfx: &mut gfx::Gfx, 
-                      indices: &mut Vec< u32 >,
-                      line_indices: &mut Vec< u32 >,
+        fn windowpane(geometry: &mut geometry::GeometryBuilder, 
                       angle: f32,
                       radius: f32,
                       extents: (f32, f32),
@@ -214,44 +360,20 @@ impl Planet {
             // window pane
             let darker_color      = (0.01, 0.02, 0.05, 1.0);
             let lighter_color     = (0.12, 0.16, 0.4, 1.0);
-            let start_vert    = gfx.triangle_len();
-            gfx.add_triangle_vertex( gfx::place(angle, radius), 
-                                     darker_color);
-            gfx.add_triangle_vertex( gfx::place(angle, radius+extents.1), 
-                                     (0.0, 0.0, 0.0, 1.0));
-            gfx.add_triangle_vertex( gfx::place(angle+width_to_angle(extents.0, radius), radius), 
-                                     lighter_color);
-            gfx.add_triangle_vertex( gfx::place(angle+width_to_angle(extents.0, radius), radius+extents.1), 
-                                     darker_color);
-            indices.push((start_vert as u32)+0);
-            indices.push((start_vert as u32)+1);
-            indices.push((start_vert as u32)+2);
-            indices.push((start_vert as u32)+1);
-            indices.push((start_vert as u32)+2);
-            indices.push((start_vert as u32)+3);
+            geometry.curved_rect((angle, angle+width_to_angle(extents.0, radius)),
+                                 (radius, radius+extents.1),
+                                 [darker_color, (0.0, 0.0, 0.0, 1.0), lighter_color, darker_color]);
             
-            let start_vert = gfx.line_len();
-            gfx.add_line_vertex( gfx::place(angle, radius));
-            gfx.add_line_vertex( gfx::place(angle, radius+extents.1));
-            gfx.add_line_vertex( gfx::place(angle+width_to_angle(extents.0, radius),radius));
-            gfx.add_line_vertex( gfx::place(angle+width_to_angle(extents.0, radius+extents.1),radius+extents.1));
-
-            line_indices.push((start_vert as u32)+0);
-            line_indices.push((start_vert as u32)+1);
-            line_indices.push((start_vert as u32)+1);
-            line_indices.push((start_vert as u32)+3);
-            line_indices.push((start_vert as u32)+2);
-            line_indices.push((start_vert as u32)+0);
-            line_indices.push((start_vert as u32)+3);
-            line_indices.push((start_vert as u32)+2);
+            geometry.outline(&[(angle, radius),
+                               (angle, radius+extents.1),
+                               (angle+width_to_angle(extents.0, radius+extents.1), radius+extents.1),
+                               (angle+width_to_angle(extents.0, radius), radius)]);
             
             return 0;
         }
 
 
-        fn window(gfx: &mut gfx::Gfx, 
-                  indices: &mut Vec< u32 >,
-                  line_indices: &mut Vec< u32 >,
+        fn window(geometry: &mut geometry::GeometryBuilder, 
                   angle: f32,
                   radius: f32,
                   extents: (f32, f32),
@@ -263,108 +385,57 @@ impl Planet {
             let sill_height   = 0.06;
             let window_inset  = 0.5;
 
-            Planet::windowpane(gfx, indices, line_indices, 
+            Planet::windowpane(geometry, 
                                angle+width_to_angle(window_inset, radius), 
                                radius+sill_height, 
                                (extents.0 - window_inset*2.0, extents.1 - sill_height*2.0),
                                PaneStyle::Plain);
 
+            let across = (angle, angle+width_to_angle(extents.0, radius));
            
             // lower sill
-            let start_vert    = gfx.triangle_len();
-            gfx.add_triangle_vertex( gfx::place(angle, radius), 
-                                     trim_color);
-            gfx.add_triangle_vertex( gfx::place(angle, radius+sill_height), 
-                                     trim_color);
-            gfx.add_triangle_vertex( gfx::place(angle+width_to_angle(extents.0, radius), radius), 
-                                     trim_color);
-            gfx.add_triangle_vertex( gfx::place(angle+width_to_angle(extents.0, radius), radius+sill_height), 
-                                     trim_color);
-            indices.push((start_vert as u32)+0);
-            indices.push((start_vert as u32)+1);
-            indices.push((start_vert as u32)+2);
-            indices.push((start_vert as u32)+1);
-            indices.push((start_vert as u32)+2);
-            indices.push((start_vert as u32)+3);
+            geometry.curved_rect(across, (radius, radius+sill_height), [trim_color; 4]);
             
             // lower sill 2
-            let start_vert    = gfx.triangle_len();
-            gfx.add_triangle_vertex( gfx::place(angle+width_to_angle(window_inset*0.6, radius), radius), 
-                                     shaded_trim_color);
-            gfx.add_triangle_vertex( gfx::place(angle+width_to_angle(window_inset*0.6, radius), radius-(sill_height*1.2)), 
-                                     shaded_trim_color);
-            gfx.add_triangle_vertex( gfx::place(angle+width_to_angle(extents.0-(window_inset*0.6), radius), radius), 
-                                     shaded_trim_color);
-            gfx.add_triangle_vertex( gfx::place(angle+width_to_angle(extents.0-(window_inset*0.6), radius), radius-(sill_height*1.2)), 
-                                     shaded_trim_color);
-            indices.push((start_vert as u32)+0);
-            indices.push((start_vert as u32)+1);
-            indices.push((start_vert as u32)+2);
-            indices.push((start_vert as u32)+1);
-            indices.push((start_vert as u32)+2);
-            indices.push((start_vert as u32)+3);
+            geometry.curved_rect((angle+width_to_angle(window_inset*0.6, radius), 
+                                  angle+width_to_angle(extents.0-(window_inset*0.6), radius)),
+                                 (radius, radius-(sill_height*1.2)),
+                                 [shaded_trim_color; 4]);
 
             // upper sill
-            let start_vert    = gfx.triangle_len();
-            gfx.add_triangle_vertex( gfx::place(angle, radius+extents.1-sill_height), 
-                                     trim_color);
-            gfx.add_triangle_vertex( gfx::place(angle, radius+extents.1), 
-                                     trim_color);
-            gfx.add_triangle_vertex( gfx::place(angle+width_to_angle(extents.0, radius), radius+extents.1-sill_height), 
-                                     trim_color);
-            gfx.add_triangle_vertex( gfx::place(angle+width_to_angle(extents.0, radius), radius+extents.1), 
-                                     trim_color);
-            indices.push((start_vert as u32)+0);
-            indices.push((start_vert as u32)+1);
-            indices.push((start_vert as u32)+2);
-            indices.push((start_vert as u32)+1);
-            indices.push((start_vert as u32)+2);
-            indices.push((start_vert as u32)+3);
+            geometry.curved_rect(across, (radius+extents.1-sill_height, radius+extents.1), [trim_color; 4]);
             
             return 0;
         }
 
-        fn downtown_building(gfx: &mut gfx::Gfx, 
-                             indices: &mut Vec< u32 >,
-                             line_indices: &mut Vec< u32 >,
+        fn downtown_building(geometry: &mut geometry::GeometryBuilder, 
                              start_angle: f32,
                              arc_length: f32, 
                              radius: f32) -> usize {
             let building_height = 2.0;
-            let start_vert = gfx.triangle_len();
 
             let num_steps = ((angle_to_width(arc_length, radius)/20.0).floor() as u32) + 1;
             let step_width = arc_length / (num_steps as f32);
             
-            gfx.add_triangle_vertex( gfx::place(start_angle, radius), 
-                                     (0.3, 0.05, 0.05, 1.0));
-            gfx.add_triangle_vertex( gfx::place(start_angle, radius+building_height), 
-                                     (0.2, 0.045, 0.045, 1.0));
-
-            for i in 1..(num_steps+1) {
-
-                gfx.add_triangle_vertex( gfx::place(start_angle+(step_width*(i as f32)), radius), 
-                                         (0.2, 0.045, 0.045, 1.0));
-                gfx.add_triangle_vertex( gfx::place(start_angle+(step_width*(i as f32)), radius+building_height), 
-                                         (0.3, 0.05, 0.05, 1.0));
-                indices.push((start_vert as u32)+(i*2 - 2));
-                indices.push((start_vert as u32)+(i*2 - 1));
-                indices.push((start_vert as u32)+(i*2));
-                indices.push((start_vert as u32)+(i*2 - 1));
-                indices.push((start_vert as u32)+(i*2));
-                indices.push((start_vert as u32)+(i*2 +1));
-
+            let dark  = (0.2, 0.045, 0.045, 1.0);
+            let light = (0.3, 0.05, 0.05, 1.0);
+            // the first step's near edge is shaded the other way up
+            geometry.curved_rect((start_angle, start_angle+step_width), (radius, radius+building_height),
+                                 [light, dark, dark, light]);
+            if num_steps > 1 {
+                geometry.arc_band(start_angle+step_width, arc_length-step_width, (radius, radius+building_height),
+                                  num_steps-1, (dark, light));
             }
 
-            Planet::door(gfx, indices, line_indices,
+            Planet::door(geometry,
                          start_angle+(step_width/2.0), radius+0.1, (6.0, 1.5), DoorStyle::Plain);
 
             // windows, have to run the loop again...
             for i in 2..(num_steps+1) {
-                Planet::window(gfx, indices, line_indices, 
+                Planet::window(geometry, 
                                start_angle+(step_width*(i as f32)-step_width+(step_width*0.05)), 
                                radius + 0.4, (7.8, 0.8), WindowStyle::Plain);
-                Planet::window(gfx, indices, line_indices, 
+                Planet::window(geometry, 
                                start_angle+(step_width*(i as f32)-(step_width*0.5)+(step_width*0.05)), 
                                radius + 0.4, (7.8, 0.8), WindowStyle::Plain);
             }
@@ -372,22 +443,16 @@ impl Planet {
             return 0;
         }
 
-        fn small_city_buildings(gfx: &mut gfx::Gfx, 
-                                indices: &mut Vec< u32 >,
-                                line_indices: &mut Vec< u32 >,
+        fn small_city_buildings(geometry: &mut geometry::GeometryBuilder, 
                                 start_angle: f32,
                                 arc_length: f32, 
                                 radius: f32) -> usize {
-            Planet::downtown_building(gfx, indices, line_indices, start_angle, arc_length, radius);
+            Planet::downtown_building(geometry, start_angle, arc_length, radius);
             return 0;
         }
 
-        fn sidewalks(gfx: &mut gfx::Gfx, 
-                     indices: &mut Vec< u32 >,
-                     line_indices: &mut Vec< u32 >,
-                     contents: fn(&mut gfx::Gfx, 
-                                       &mut Vec< u32 >,
-                                       &mut Vec< u32 >,
+        fn sidewalks(geometry: &mut geometry::GeometryBuilder, 
+                     contents: fn(&mut geometry::GeometryBuilder, 
                                        f32,
                                        f32, 
                                        f32) -> usize,
@@ -398,133 +463,44 @@ impl Planet {
             let curb_base   = 0.15;
             let sidewalk_width = 12.0;
 
-            let start_vert = gfx.triangle_len();
-
-            let num_steps = 5;
-            let step_width = arc_length / (num_steps as f32);
-
-
-            // first render the contents
-
-
             // the bulk of the sidewalks
-            gfx.add_triangle_vertex( gfx::place(start_angle, radius+curb_base),
-                                     (0.3, 0.3, 0.3, 1.0));
-            gfx.add_triangle_vertex( gfx::place(start_angle, radius+curb_base+curb_height), 
-                                     (0.3, 0.3, 0.3, 1.0));
-
-            for i in 1..(num_steps+1) {
-                gfx.add_triangle_vertex( gfx::place(start_angle+(step_width*(i as f32)), radius+curb_base), 
-                                         (0.3, 0.3, 0.3, 1.0));
-                gfx.add_triangle_vertex( gfx::place(start_angle+(step_width*(i as f32)), radius+curb_base+curb_height), 
-                                         (0.3, 0.3, 0.3, 1.0));
-                indices.push((start_vert as u32)+(i*2 - 2));
-                indices.push((start_vert as u32)+(i*2 - 1));
-                indices.push((start_vert as u32)+(i*2));
-                indices.push((start_vert as u32)+(i*2 - 1));
-                indices.push((start_vert as u32)+(i*2));
-                indices.push((start_vert as u32)+(i*2 + 1));
-            }
+            geometry.arc_band(start_angle, arc_length, (radius+curb_base, radius+curb_base+curb_height), 5, 
+                              ((0.3, 0.3, 0.3, 1.0), (0.3, 0.3, 0.3, 1.0)));
 
             // crosswalk ramps
             let mut crosswalk_ramp = |start: f32, width: f32| {
-                let start_vert = gfx.triangle_len();
+                let at   = |along: f32| start_angle+width_to_angle(start+along, radius);
+                let top  = radius+curb_base+curb_height;
+                let foot = radius+curb_base+(curb_height*0.2);
                 // left 
-                gfx.add_triangle_vertex( gfx::place(start_angle+width_to_angle(start, radius), 
-                                                    radius+curb_base+curb_height), 
-                                         (0.4, 0.4, 0.4, 1.0));
-                gfx.add_triangle_vertex( gfx::place(start_angle+width_to_angle(start+(width*0.285), radius), 
-                                                     radius+curb_base+curb_height), 
-                                         (0.4, 0.4, 0.4, 1.0));
-                gfx.add_triangle_vertex( gfx::place(start_angle+width_to_angle(start+(width*0.214), radius), 
-                                                    radius+curb_base+(curb_height*0.2)), 
-                                         (0.4, 0.4, 0.4, 1.0));
-                
-                indices.push((start_vert as u32)+0);
-                indices.push((start_vert as u32)+1);
-                indices.push((start_vert as u32)+2);
-               
+                geometry.polygon(&[(at(0.0), top), (at(width*0.285), top), (at(width*0.214), foot)],
+                                 (0.4, 0.4, 0.4, 1.0));
                 // middle
-                gfx.add_triangle_vertex(   gfx::place(start_angle+width_to_angle(start+(width*0.285), radius), 
-                                                      radius+curb_base+curb_height), 
-                                         (0.5, 0.5, 0.5, 1.0));
-                gfx.add_triangle_vertex(  gfx::place(start_angle+width_to_angle(start+(width*0.214), radius), 
-                                                     radius+curb_base+(curb_height*0.2)), 
-                                         (0.5, 0.5, 0.5, 1.0));
-                gfx.add_triangle_vertex(  gfx::place(start_angle+width_to_angle(start+(width*0.714), radius), 
-                                                     radius+curb_base+curb_height), 
-                                         (0.5, 0.5, 0.5, 1.0));
-                gfx.add_triangle_vertex(  gfx::place(start_angle+width_to_angle(start+(width*0.785), radius), 
-                                                     radius+curb_base+(curb_height*0.2)), 
-                                         (0.5, 0.5, 0.5, 1.0));
-                indices.push((start_vert as u32)+3);
-                indices.push((start_vert as u32)+4);
-                indices.push((start_vert as u32)+5);
-                indices.push((start_vert as u32)+4);
-                indices.push((start_vert as u32)+5);
-                indices.push((start_vert as u32)+6);
-                
+                geometry.quad([(at(width*0.285), top), (at(width*0.214), foot), 
+                               (at(width*0.714), top), (at(width*0.785), foot)],
+                              [(0.5, 0.5, 0.5, 1.0); 4]);
                 // right 
-                gfx.add_triangle_vertex(  gfx::place(start_angle+width_to_angle(start+(width*0.714), radius), 
-                                                     radius+curb_base+curb_height), 
-                                         (0.65, 0.65, 0.65, 1.0));
-                gfx.add_triangle_vertex(  gfx::place(start_angle+width_to_angle(start+width, radius), 
-                                                     radius+curb_base+curb_height), 
-                                         (0.65, 0.65, 0.65, 1.0));
-                gfx.add_triangle_vertex(  gfx::place(start_angle+width_to_angle(start+(width*0.785), radius), 
-                                                     radius+curb_base+(curb_height*0.2)), 
-                                         (0.65, 0.65, 0.65, 1.0));
-                
-                indices.push((start_vert as u32)+7);
-                indices.push((start_vert as u32)+8);
-                indices.push((start_vert as u32)+9);
+                geometry.polygon(&[(at(width*0.714), top), (at(width), top), (at(width*0.785), foot)],
+                                 (0.65, 0.65, 0.65, 1.0));
                 
                 // crosswalk stripe (left)
-                let start_vert = gfx.triangle_len();
-                gfx.add_triangle_vertex( gfx::place(start_angle+width_to_angle(start+0.3, radius), 
-                                                    radius+curb_base-0.02), 
-                                         (0.8, 0.8, 0.8, 1.0));
-                gfx.add_triangle_vertex( gfx::place(start_angle+width_to_angle(start-0.4, radius), 
-                                                    radius+curb_base-0.14), 
-                                         (0.8, 0.8, 0.8, 1.0));
-                gfx.add_triangle_vertex( gfx::place(start_angle+width_to_angle(start+1.6, radius), 
-                                                    radius+curb_base-0.14), 
-                                         (0.8, 0.8, 0.8, 1.0));
-                gfx.add_triangle_vertex( gfx::place(start_angle+width_to_angle(start+1.9, radius), 
-                                                    radius+curb_base-0.02), 
-                                         (0.8, 0.8, 0.8, 1.0));
-                indices.push((start_vert as u32)+0);
-                indices.push((start_vert as u32)+1);
-                indices.push((start_vert as u32)+2);
-                indices.push((start_vert as u32)+0);
-                indices.push((start_vert as u32)+2);
-                indices.push((start_vert as u32)+3);
+                geometry.polygon(&[(at(0.3), radius+curb_base-0.02), 
+                                   (at(-0.4), radius+curb_base-0.14),
+                                   (at(1.6), radius+curb_base-0.14), 
+                                   (at(1.9), radius+curb_base-0.02)],
+                                 (0.8, 0.8, 0.8, 1.0));
             
                 // crosswalk stripe (right)
-                let start_vert = gfx.triangle_len();
-                gfx.add_triangle_vertex( gfx::place(start_angle+width_to_angle(start+width-0.3, radius), 
-                                                    radius+curb_base-0.02), 
-                                         (0.8, 0.8, 0.8, 1.0));
-                gfx.add_triangle_vertex( gfx::place(start_angle+width_to_angle(start+width+0.4, radius), 
-                                                    radius+curb_base-0.14), 
-                                         (0.8, 0.8, 0.8, 1.0));
-                gfx.add_triangle_vertex( gfx::place(start_angle+width_to_angle(start+width-1.6, radius), 
-                                                    radius+curb_base-0.14), 
-                                         (0.8, 0.8, 0.8, 1.0));
-                gfx.add_triangle_vertex( gfx::place(start_angle+width_to_angle(start+width-2.0, radius), 
-                                                    radius+curb_base-0.02), 
-                                         (0.8, 0.8, 0.8, 1.0));
-                indices.push((start_vert as u32)+0);
-                indices.push((start_vert as u32)+1);
-                indices.push((start_vert as u32)+2);
-                indices.push((start_vert as u32)+0);
-                indices.push((start_vert as u32)+2);
-                indices.push((start_vert as u32)+3);
+                geometry.polygon(&[(at(width-0.3), radius+curb_base-0.02), 
+                                   (at(width+0.4), radius+curb_base-0.14),
+                                   (at(width-1.6), radius+curb_base-0.14), 
+                                   (at(width-2.0), radius+curb_base-0.02)],
+                                 (0.8, 0.8, 0.8, 1.0));
             };
             crosswalk_ramp(4.5,7.0); 
             crosswalk_ramp(angle_to_width(arc_length, radius)-11.5, 7.0);
 
-            contents(gfx, indices, line_indices,
+            contents(geometry,
                      start_angle+width_to_angle(sidewalk_width,radius), 
                      arc_length-width_to_angle(sidewalk_width*2.0, radius), 
                      radius+curb_base+curb_height);
@@ -532,115 +508,68 @@ impl Planet {
             return 0;
         }
 
-        fn block(gfx: &mut gfx::Gfx, 
-                 indices: &mut Vec< u32 >,
-                 line_indices: &mut Vec< u32 >,
-                 assets: &mut assets::asset::Assets,
+        fn block(geometry: &mut geometry::GeometryBuilder, 
+                 lampposts: &mut Vec< gfx::instances::GfxInstance >,
                  start_angle: f32,
                  arc_length: f32, 
                  radius: f32) -> usize {
             // road
             let road_height = 0.15;
-            let road_width  = width_to_angle(20.0, radius);
-            let start_vert  = gfx.triangle_len();
-
-            let num_steps   = 5;
-            let step_width  = arc_length / (num_steps as f32);
-
-
-
-
-            gfx.add_triangle_vertex( gfx::place(start_angle, radius),
-                                     (0.05, 0.05, 0.05, 1.0));
-            gfx.add_triangle_vertex( gfx::place(start_angle, radius+road_height), 
-                                     (0.05, 0.05, 0.05, 1.0));
-
-            // cross road crown
-            gfx.add_triangle_vertex( gfx::place(start_angle + width_to_angle(7.0,radius), radius+road_height+0.05),
-                                     (0.05, 0.05, 0.05, 1.0));
-            gfx.add_triangle_vertex( gfx::place(start_angle + width_to_angle(13.0, radius), radius+road_height+0.05),
-                                     (0.05, 0.05, 0.05, 1.0));
-            gfx.add_triangle_vertex( gfx::place(start_angle + width_to_angle(20.0, radius), radius),
-                                     (0.05, 0.05, 0.05, 1.0));
-            gfx.add_triangle_vertex( gfx::place(start_angle + width_to_angle(20.0, radius), radius+road_height),
-                                     (0.05, 0.05, 0.05, 1.0));
-
-            indices.push((start_vert as u32)+0);
-            indices.push((start_vert as u32)+1);
-            indices.push((start_vert as u32)+2);
-            indices.push((start_vert as u32)+0);
-            indices.push((start_vert as u32)+2);
-            indices.push((start_vert as u32)+3);
-            indices.push((start_vert as u32)+0);
-            indices.push((start_vert as u32)+3);
-            indices.push((start_vert as u32)+4);
-            indices.push((start_vert as u32)+3);
-            indices.push((start_vert as u32)+4);
-            indices.push((start_vert as u32)+5);
-
-            let start_vert  = gfx.triangle_len()-2;
+            let road_color  = (0.05, 0.05, 0.05, 1.0);
 
             // the bulk of the road
-            for i in 1..(num_steps+1) {
-                gfx.add_triangle_vertex( gfx::place(start_angle+(step_width*(i as f32)), radius), 
-                                         (0.05, 0.05, 0.05, 1.0));
-                gfx.add_triangle_vertex( gfx::place(start_angle+(step_width*(i as f32)), radius+road_height), 
-                                         (0.05, 0.05, 0.05, 1.0));
-                indices.push((start_vert as u32)+(i*2 - 2));
-                indices.push((start_vert as u32)+(i*2 - 1));
-                indices.push((start_vert as u32)+(i*2));
-                indices.push((start_vert as u32)+(i*2 - 1));
-                indices.push((start_vert as u32)+(i*2));
-                indices.push((start_vert as u32)+(i*2 + 1));
-            }
-
-
+            geometry.arc_band(start_angle, arc_length, (radius, radius+road_height), 5, 
+                              (road_color, road_color));
 
+            // cross road crown
+            geometry.polygon(&[(start_angle, radius+road_height),
+                               (start_angle + width_to_angle(7.0, radius), radius+road_height+0.05),
+                               (start_angle + width_to_angle(13.0, radius), radius+road_height+0.05),
+                               (start_angle + width_to_angle(20.0, radius), radius+road_height)],
+                             road_color);
 
             // TODO: handle different kinds of blocks
-            Planet::city_block(gfx, indices, line_indices, assets, 
+            Planet::city_block(geometry, lampposts, 
                                start_angle + width_to_angle(20.0, radius), 
                                arc_length - width_to_angle(20.0, radius), radius);
 
             return 0;
         }
 
-        fn city_block(gfx: &mut gfx::Gfx, 
-                      indices: &mut Vec< u32 >,
-                      line_indices: &mut Vec< u32 >,
-                      assets: &mut assets::asset::Assets,
+        fn city_block(geometry: &mut geometry::GeometryBuilder, 
+                      lampposts: &mut Vec< gfx::instances::GfxInstance >,
                       start_angle: f32,
                       arc_length: f32, 
                       radius: f32) -> usize {
-            let lamppost = assets.get_asset(&"lamppost".to_string(),&"1".to_string());
-
-            render_asset(lamppost, gfx, indices, radius+0.25, 
-                         start_angle+width_to_angle(2.5,radius));
-            render_asset(lamppost, gfx, indices, radius+0.25, 
-                         start_angle+arc_length-width_to_angle(2.5,radius));
-            Planet::sidewalks(gfx, indices, line_indices, 
+            place_asset(lampposts, radius+0.25, 
+                        start_angle+width_to_angle(2.5,radius));
+            place_asset(lampposts, radius+0.25, 
+                        start_angle+arc_length-width_to_angle(2.5,radius));
+            Planet::sidewalks(geometry, 
                               Planet::small_city_buildings, 
                               start_angle, arc_length, radius);
             return 0;
         }
 
+        // the triangles and lines of each block, and the lampposts as
+        // where each one stands
         fn foreground(gfx: &mut gfx::Gfx,
-                      display: &glium::Display,
-                      assets: &mut assets::asset::Assets,
-                      radius: f32) -> (usize, usize) {
-            let mut indices = Vec::< u32 >::new();
-            let mut line_indices = Vec::< u32 >::new();
-            Planet::block(gfx, &mut indices, &mut line_indices, assets, 0.0, width_to_angle(100.0, radius), radius);
-            return ( gfx.add_indices(display, &indices, 
-                                     PrimitiveType::TrianglesList),
-                     gfx.add_indices(display, &line_indices, 
-                                          PrimitiveType::LinesList) );
+                      radius: f32) -> (Vec< (gfx::IndexHandle, gfx::IndexHandle) >, Vec< gfx::instances::GfxInstance >) {
+            let mut blocks = Vec::new();
+            let mut lampposts = Vec::new();
+            let block_angle = width_to_angle(CITY_WIDTH / CITY_BLOCKS as f32, radius);
+            for i in 0..CITY_BLOCKS {
+                let mut geometry = geometry::GeometryBuilder::new(gfx);
+                Planet::block(&mut geometry, &mut lampposts, 
+                              CITY_ANGLE + block_angle * i as f32, block_angle, radius);
+                blocks.push(geometry.finish());
+            }
+            return ( blocks, lampposts );
         }
 
         fn circle(gfx: &mut gfx::Gfx, 
-                  display: &glium::Display, 
                   num_verts: u32, 
-                  radius: f32) -> usize {
+                  radius: f32) -> gfx::IndexHandle {
             let angle_step = (3.14159*2.0)/(num_verts as f32);
             let mut indices = Vec::new();
             let start_vert = gfx.line_len();
@@ -649,14 +578,13 @@ impl Planet {
                 gfx.add_line_vertex( ( angle.sin()*radius, angle.cos()*radius ) );
                 indices.push((start_vert as u32)+(i as u32));
             }
-            return gfx.add_indices(display, &indices, PrimitiveType::LineLoop);
+            return gfx.add_indices(&indices, PrimitiveType::LineLoop);
         }
 
         fn sky(gfx: &mut gfx::Gfx,
-               display: &glium::Display, 
                inner_radius: f32,
                height: f32,
-               num_divisions: u32) -> usize {
+               num_divisions: u32) -> gfx::IndexHandle {
             let angle_step = (3.14159*2.0)/(num_divisions as f32);
             let mut indices = Vec::new();
             let start_vert = gfx.triangle_len();
@@ -689,36 +617,10 @@ impl Planet {
             }
             indices.push(start_vert as u32);
             indices.push((start_vert as u32)+1);
-            return gfx.add_indices(display, &indices, PrimitiveType::TriangleStrip);
+            return gfx.add_indices(&indices, PrimitiveType::TriangleStrip);
         }
 
 
-        fn mountains(gfx: &mut gfx::Gfx, 
-                     display: &glium::Display, 
-                     height_fn: fn(f32) -> f32,
-                     inner_radius: f32, 
-                     num_divisions: u32 ) -> usize {
-            let angle_step = (3.14159*2.0)/(num_divisions as f32);
-            let mut indices = Vec::new();
-            let start_vert = gfx.triangle_len();
-            for i in 0..(num_divisions) {
-
-                let angle  = (i as f32)*angle_step;
-                let height = height_fn(angle);
-                gfx.add_triangle_vertex( ( angle.sin()*(inner_radius+height),
-                                           angle.cos()*(inner_radius+height) ),
-                                         (0.05, 0.05, 0.1, 1.0));
-
-                gfx.add_triangle_vertex( ( angle.sin()*inner_radius,
-                                           angle.cos()*inner_radius ),
-                                         (0.2, 0.2, 0.5, 1.0));
-                indices.push((start_vert as u32)+(i as u32)*2);
-                indices.push((start_vert as u32)+(i as u32)*2+1);
-            }
-            indices.push(start_vert as u32);
-            indices.push((start_vert as u32)+1);
-            return gfx.add_indices(display, &indices, PrimitiveType::TriangleStrip);
-        }
 
     }
      
@@ -740,28 +642,26 @@ impl Planet {
         scale: f32,
         flags: u32,
         gear_state: LandingGearState,
-        ship_geometry: usize,
-    exhaust_draw: usize,
-    left_gear_geometry: usize,
-    right_gear_geometry: usize,
-
-    gfx_scale: usize,
-    gfx_angle: usize,
-    gfx_translation: usize,
+        ship_geometry: gfx::IndexHandle,
+    exhaust_draw: gfx::CommandHandle,
+    exhaust_vertices: gfx::VertexHandle,
+    exhaust_frame: u32,
+    left_gear_geometry: gfx::IndexHandle,
+    right_gear_geometry: gfx::IndexHandle,
+
+    gfx_scale: gfx::CommandHandle,
+    gfx_angle: gfx::CommandHandle,
+    gfx_translation: gfx::CommandHandle,
     
-    gfx_left_gear_translation: usize,
-    gfx_right_gear_translation: usize,
-    gfx_left_gear_rotation: usize,
-    gfx_right_gear_rotation: usize,
+    gfx_left_gear_rotation: gfx::CommandHandle,
+    gfx_right_gear_rotation: gfx::CommandHandle,
     
-    gfx_left_foot_translation: usize,
-    gfx_right_foot_translation: usize,
-    gfx_left_foot_rotation: usize,
-    gfx_right_foot_rotation: usize
+    gfx_left_foot_rotation: gfx::CommandHandle,
+    gfx_right_foot_rotation: gfx::CommandHandle
 }
 
 impl PlayerShip {
-    fn new(gfx_handles: HashMap<String, usize>) -> PlayerShip {
+    fn new(gfx_handles: GfxHandles) -> PlayerShip {
 
 
         PlayerShip { position:      (0.0, 1000.0),
@@ -770,25 +670,23 @@ impl PlayerShip {
                      scale: 0.05,
                      flags: LANDED,
                      gear_state: LandingGearState::Down,
-                     ship_geometry: gfx_handles["fuselage"],
-                     exhaust_draw: gfx_handles["exhaust_draw"],
-                     left_gear_geometry: gfx_handles["left_gear"],
-                     right_gear_geometry: gfx_handles["right_gear"],
+                     ship_geometry: gfx_handles.indices["fuselage"],
+                     exhaust_draw: gfx_handles.commands["exhaust_draw"],
+                     exhaust_vertices: gfx_handles.vertices["exhaust"],
+                     exhaust_frame: 0,
+                     left_gear_geometry: gfx_handles.indices["left_gear"],
+                     right_gear_geometry: gfx_handles.indices["right_gear"],
                      
-                     gfx_scale: gfx_handles["scale"],
-                     gfx_left_gear_translation: gfx_handles["left_gear_translation"],
-                     gfx_right_gear_translation: gfx_handles["right_gear_translation"],
-                     gfx_left_gear_rotation: gfx_handles["left_gear_rotation"],
-                     gfx_right_gear_rotation: gfx_handles["right_gear_rotation"],
+                     gfx_scale: gfx_handles.commands["scale"],
+                     gfx_left_gear_rotation: gfx_handles.commands["left_gear_rotation"],
+                     gfx_right_gear_rotation: gfx_handles.commands["right_gear_rotation"],
                      
-                     gfx_left_foot_translation: gfx_handles["left_foot_translation"],
-                     gfx_right_foot_translation: gfx_handles["right_foot_translation"],
-                     gfx_left_foot_rotation: gfx_handles["left_foot_rotation"],
-                     gfx_right_foot_rotation: gfx_handles["right_foot_rotation"],
+                     gfx_left_foot_rotation: gfx_handles.commands["left_foot_rotation"],
+                     gfx_right_foot_rotation: gfx_handles.commands["right_foot_rotation"],
                      
-                     gfx_angle: gfx_handles["ship_rotation"],
-                     gfx_translation: gfx_handles["ship_translation"],
-                     //gfx_origin: gfx_handles["ship_origin"]
+                     gfx_angle: gfx_handles.commands["ship_rotation"],
+                     gfx_translation: gfx_handles.commands["ship_translation"],
+                     //gfx_origin: gfx_handles.commands["ship_origin"]
         }
     }
 
@@ -797,11 +695,8 @@ impl PlayerShip {
         let distance = gfx::get_distance(self.position, planet.position);
         let angle    = gfx::get_angle(self.position, planet.position);
 
-        self.scale = 0.2 + (distance-planet.size+0.001)/500.0;
-        //self.scale = 0.2;
-        //self.scale = 0.08;
         if distance > (planet.size-0.01) && self.flags & LANDED == 0 {
-            let pull = (1.0/(distance.powf(2.0))) * 2000.0;
+            let pull = (1.0/(distance.powf(2.0))) * GRAVITY;
             self.velocity.0 -= angle.sin() * pull;
             self.velocity.1 -= angle.cos() * pull;
         } else {
@@ -858,6 +753,58 @@ impl PlayerShip {
         }
     }
 
+    fn gear_down(&self) -> bool {
+        match self.gear_state {
+            LandingGearState::Down => return true,
+            _                      => return false
+        }
+    }
+
+    // a point on the ship, in its own units, to where it is in the world
+    fn to_world(&self, point: (f32, f32)) -> (f32, f32) {
+        return gfx::add_points(self.position, gfx::rotate(gfx::scale_point(point, self.scale), self.angle));
+    }
+
+    // exhaust out of the nozzles, and dust off the ground underneath
+    // when thrusting close to it
+    fn emit_particles(&self, 
+                      planet: &Planet,
+                      exhaust: &mut particles::ParticleSystem,
+                      dust: &mut particles::ParticleSystem) {
+        if !self.thrusting() {
+            return;
+        }
+        let backwards = gfx::rotate((0.0, -1.0), self.angle);
+        for nozzle in NOZZLES.iter() {
+            exhaust.emit(&EXHAUST, self.to_world(*nozzle), self.velocity, backwards, 2, self.scale);
+        }
+
+        let altitude = gfx::get_distance(self.position, planet.position) - planet.size;
+        if altitude < DUST_ALTITUDE {
+            let angle  = gfx::get_angle(self.position, planet.position);
+            let ground = gfx::add_points(planet.position, gfx::place(angle, planet.size + 0.05));
+            let count  = if altitude < DUST_ALTITUDE * 0.5 { 2 } else { 1 };
+            dust.emit(&DUST, ground, (0.0, 0.0), gfx::place(angle, 1.0), count, self.scale);
+        }
+    }
+
+    // a cloud of dust from each foot on landing, more the faster it
+    // came down, and sparks if it hit hard or without the gear
+    fn touch_down(&self,
+                  planet: &Planet,
+                  speed: f32,
+                  exhaust: &mut particles::ParticleSystem,
+                  dust: &mut particles::ParticleSystem) {
+        let up = gfx::place(gfx::get_angle(self.position, planet.position), 1.0);
+        for foot in FEET.iter() {
+            let foot = self.to_world(*foot);
+            dust.emit(&DUST, foot, (0.0, 0.0), up, 4 + (speed * 200.0).min(60.0) as usize, self.scale);
+            if speed > HARD_LANDING || !self.gear_down() {
+                exhaust.emit(&SPARKS, foot, (0.0, 0.0), up, 5 + (speed * 100.0).min(30.0) as usize, self.scale);
+            }
+        }
+    }
+
     fn cycle_gear(&mut self) {
         match self.gear_state {
             LandingGearState::Opening (state) => {
@@ -875,7 +822,7 @@ impl PlayerShip {
         }
     }
 
-    fn tick(&mut self, gfx: &mut gfx::Gfx) {
+    fn tick(&mut self, gfx: &mut gfx::Gfx) -> Result<(), gfx::GfxError> {
         let mut gear_angle = 0.0f32;
         let mut foot_angle = 0.0f32;
         
@@ -890,9 +837,16 @@ impl PlayerShip {
         if self.flags & THRUST_ON != 0 {
             self.velocity.0 -= self.angle.sin()*0.00205;
             self.velocity.1 += self.angle.cos()*0.00205;
-            gfx.unskip(self.exhaust_draw);
+            gfx.unskip(self.exhaust_draw)?;
+
+            // flicker the flame tips, left one is vertex 2, right one 7
+            self.exhaust_frame = self.exhaust_frame.wrapping_add(1);
+            let flicker = ((self.exhaust_frame as f32)*1.7).sin()*0.8 + ((self.exhaust_frame as f32)*0.63).sin()*0.5;
+            let exhaust = gfx.vertex_start(self.exhaust_vertices)?;
+            gfx.set_triangle_vertex(exhaust+2, (-2.4, -18.4-flicker), (0.0, 0.0, 0.0, 0.0))?;
+            gfx.set_triangle_vertex(exhaust+7, (2.4, -18.4+flicker*0.7), (0.0, 0.0, 0.0, 0.0))?;
         } else {
-            gfx.skip(self.exhaust_draw);
+            gfx.skip(self.exhaust_draw)?;
         }
 
 
@@ -928,40 +882,24 @@ impl PlayerShip {
             }
         }
         
-        gfx.change_object_scale(self.gfx_scale, self.scale);
-        gfx.change_translation(self.gfx_translation,self.position);
-
-        // gear legs
-        gfx.change_translation(self.gfx_left_gear_translation, 
-                               gfx::add_points(self.position, 
-                                               gfx::scale_point(gfx::rotate ((-3.0, -7.0), self.angle), self.scale)));
-
-        gfx.change_translation(self.gfx_right_gear_translation, 
-                               gfx::add_points(self.position, 
-                                               gfx::scale_point(gfx::rotate ((3.0, -7.0), self.angle), self.scale)));
-
-        // gear feet
-        gfx.change_translation(self.gfx_left_foot_translation, 
-                               gfx::add_points(self.position, 
-                                               gfx::scale_point(gfx::add_points(gfx::rotate((-3.0, -7.0), self.angle),
-                                                                gfx::rotate((-2.0, -5.5), self.angle-gear_angle)), self.scale )));
-        gfx.change_translation(self.gfx_right_foot_translation, 
-                               gfx::add_points(self.position, 
-                                               gfx::scale_point(gfx::add_points(gfx::rotate((3.0, -7.0), self.angle),
-                                                                gfx::rotate((2.0, -5.5), self.angle+gear_angle)), self.scale )));
-
-        gfx.change_rotation(self.gfx_left_gear_rotation, self.angle-gear_angle);
-        gfx.change_rotation(self.gfx_right_gear_rotation, self.angle+gear_angle);
+        gfx.change_object_scale(self.gfx_scale, self.scale)?;
+        gfx.change_translation(self.gfx_translation,self.position)?;
+
+        // gear legs swing out from the fuselage, the feet counter-rotate
+        // on the end of the legs
+        gfx.change_rotation(self.gfx_left_gear_rotation, -gear_angle)?;
+        gfx.change_rotation(self.gfx_right_gear_rotation, gear_angle)?;
         
-        gfx.change_rotation(self.gfx_left_foot_rotation, self.angle-foot_angle);
-        gfx.change_rotation(self.gfx_right_foot_rotation, self.angle+foot_angle);
+        gfx.change_rotation(self.gfx_left_foot_rotation, gear_angle-foot_angle)?;
+        gfx.change_rotation(self.gfx_right_foot_rotation, foot_angle-gear_angle)?;
         
-        gfx.change_rotation(self.gfx_angle, self.angle);
+        gfx.change_rotation(self.gfx_angle, self.angle)?;
+        return Ok(());
     }
     
-    fn geometry(gfx: &mut gfx::Gfx, display: &glium::Display) -> HashMap<String, usize> {
+    fn geometry(gfx: &mut gfx::Gfx, programs: &Programs) -> Result<GfxHandles, gfx::GfxError> {
         let start_vert = gfx.triangle_len();
-        let mut handles = HashMap::new(); 
+        let mut handles = GfxHandles::new(); 
 
         // fuselage, top to bottom... tip to tail
         gfx.add_triangle_vertex( (  0.0, 19.0 ), ( 0.6, 0.5, 0.5, 1.0 ) ); // 0  nosecone
@@ -1214,8 +1152,8 @@ impl PlayerShip {
         indices.push((start_vert as u32)+54);
 
 
-        handles.insert("fuselage".to_string(), 
-                       gfx.add_indices(display, &indices, PrimitiveType::TrianglesList));
+        handles.indices.insert("fuselage".to_string(), 
+                       gfx.add_indices(&indices, PrimitiveType::TrianglesList));
         
         // exhaust
         let mut indices = Vec::new();
@@ -1232,6 +1170,7 @@ impl PlayerShip {
         gfx.add_triangle_vertex( ( 2.4, -18.4 ), ( 0.0, 0.0, 0.0, 0.0 ) ); // 57 
         gfx.add_triangle_vertex( ( 0.4, -13.5 ), ( 1.0, 1.0, 0.1, 1.0 ) ); // 57 
         gfx.add_triangle_vertex( ( 0.7, -12.0 ), ( 1.0, 0.3, 0.0, 1.0 ) ); // 57 
+        handles.vertices.insert("exhaust".to_string(), gfx.claim_triangle_vertices(start_vert));
 
         // left exhaust 
         indices.push((start_vert as u32)+0);
@@ -1260,8 +1199,8 @@ impl PlayerShip {
         indices.push((start_vert as u32)+8);
         
         
-        handles.insert("exhaust".to_string(), 
-                       gfx.add_indices(display, &indices, PrimitiveType::TrianglesList));
+        handles.indices.insert("exhaust".to_string(), 
+                       gfx.add_indices(&indices, PrimitiveType::TrianglesList));
         
         // left landing gear leg
         let mut indices = Vec::new();
@@ -1286,8 +1225,8 @@ impl PlayerShip {
         indices.push((start_vert as u32)+4);
         
         
-        handles.insert("left_gear".to_string(),
-                       gfx.add_indices(display, &indices, PrimitiveType::TrianglesList));
+        handles.indices.insert("left_gear".to_string(),
+                       gfx.add_indices(&indices, PrimitiveType::TrianglesList));
         
         // right landing gear leg
         let mut indices = Vec::new();
@@ -1312,8 +1251,8 @@ impl PlayerShip {
         indices.push((start_vert as u32)+4);
         
         
-        handles.insert("right_gear".to_string(),
-                       gfx.add_indices(display, &indices, PrimitiveType::TrianglesList));
+        handles.indices.insert("right_gear".to_string(),
+                       gfx.add_indices(&indices, PrimitiveType::TrianglesList));
         
         
         // left gear foot
@@ -1334,8 +1273,8 @@ impl PlayerShip {
         indices.push((start_vert as u32)+3);
         
         
-        handles.insert("left_foot".to_string(),
-                       gfx.add_indices(display, &indices, PrimitiveType::TrianglesList));
+        handles.indices.insert("left_foot".to_string(),
+                       gfx.add_indices(&indices, PrimitiveType::TrianglesList));
         
         // right gear foot
         let mut indices = Vec::new();
@@ -1355,122 +1294,135 @@ impl PlayerShip {
         indices.push((start_vert as u32)+3);
         
         
-        handles.insert("right_foot".to_string(),
-                       gfx.add_indices(display, &indices, PrimitiveType::TrianglesList));
+        handles.indices.insert("right_foot".to_string(),
+                       gfx.add_indices(&indices, PrimitiveType::TrianglesList));
 
         
 
-        handles.insert("program".to_string(), gfx.program(1));
-        handles.insert("scale".to_string(), gfx.scene_scale(0.05));
-
-        // left landing gear
-        handles.insert("left_gear_indices".to_string(), gfx.indices(handles["left_gear"]));
-        handles.insert("left_gear_rotation".to_string(), gfx.rotate(0.0));
-        handles.insert("left_gear_translation".to_string(), gfx.translate(-3.0,-7.0));
-        handles.insert("left_gear_draw".to_string(), gfx.triangle_draw());
+        handles.commands.insert("program".to_string(), gfx.program(programs.triangles)?);
+        gfx.layer(SHIP_LAYER);
+
+        // ship rotation/translate/scale, everything down to the last
+        // pop_transform is relative to the ship
+        gfx.push_transform();
+        handles.commands.insert("scale".to_string(), gfx.object_scale(0.05));
+        handles.commands.insert("ship_rotation".to_string(), gfx.rotate(0.0));
+        handles.commands.insert("ship_translation".to_string(), gfx.translate(0.0,0.0));
+
+        // left landing gear, hinged on the fuselage
+        gfx.push_transform();
+        handles.commands.insert("left_gear_translation".to_string(), gfx.translate(-3.0,-7.0));
+        handles.commands.insert("left_gear_rotation".to_string(), gfx.rotate(0.0));
+        handles.commands.insert("left_gear_indices".to_string(), gfx.indices(handles.indices["left_gear"])?);
+        handles.commands.insert("left_gear_draw".to_string(), gfx.triangle_draw());
+
+        // left landing foot, hinged on the end of the leg
+        gfx.push_transform();
+        handles.commands.insert("left_foot_translation".to_string(), gfx.translate(-2.0,-5.5));
+        handles.commands.insert("left_foot_rotation".to_string(), gfx.rotate(0.0));
+        handles.commands.insert("left_foot_indices".to_string(), gfx.indices(handles.indices["left_foot"])?);
+        handles.commands.insert("left_foot_draw".to_string(), gfx.triangle_draw());
+        gfx.pop_transform();
+        gfx.pop_transform();
         
         // right landing gear
-        handles.insert("right_gear_indices".to_string(), gfx.indices(handles["right_gear"]));
-        handles.insert("right_gear_rotation".to_string(), gfx.rotate(0.0));
-        handles.insert("right_gear_translation".to_string(), gfx.translate(3.0,-7.0));
-        handles.insert("right_gear_draw".to_string(), gfx.triangle_draw());
+        gfx.push_transform();
+        handles.commands.insert("right_gear_translation".to_string(), gfx.translate(3.0,-7.0));
+        handles.commands.insert("right_gear_rotation".to_string(), gfx.rotate(0.0));
+        handles.commands.insert("right_gear_indices".to_string(), gfx.indices(handles.indices["right_gear"])?);
+        handles.commands.insert("right_gear_draw".to_string(), gfx.triangle_draw());
     
-        // left landing foot
-        handles.insert("left_foot_indices".to_string(), gfx.indices(handles["left_foot"]));
-        handles.insert("left_foot_rotation".to_string(), gfx.rotate(0.0));
-        handles.insert("left_foot_translation".to_string(), gfx.translate(-5.0,-12.5));
-        handles.insert("left_foot_draw".to_string(), gfx.triangle_draw());
-        
         // right landing foot
-        handles.insert("right_foot_indices".to_string(), gfx.indices(handles["right_foot"]));
-        handles.insert("right_foot_rotation".to_string(), gfx.rotate(0.0));
-        handles.insert("right_foot_translation".to_string(), gfx.translate(5.0,-12.5));
-        handles.insert("right_foot_draw".to_string(), gfx.triangle_draw());
-        
-        // ship rotation/translate
-        handles.insert("ship_rotation".to_string(), gfx.rotate(0.0));
-        handles.insert("ship_translation".to_string(), gfx.translate(0.0,0.0));
-
-        handles.insert("ship_indices".to_string(), gfx.indices(handles["fuselage"]));
-        handles.insert("ship_draw".to_string(), gfx.triangle_draw());
+        gfx.push_transform();
+        handles.commands.insert("right_foot_translation".to_string(), gfx.translate(2.0,-5.5));
+        handles.commands.insert("right_foot_rotation".to_string(), gfx.rotate(0.0));
+        handles.commands.insert("right_foot_indices".to_string(), gfx.indices(handles.indices["right_foot"])?);
+        handles.commands.insert("right_foot_draw".to_string(), gfx.triangle_draw());
+        gfx.pop_transform();
+        gfx.pop_transform();
+
+        handles.commands.insert("ship_indices".to_string(), gfx.indices(handles.indices["fuselage"])?);
+        handles.commands.insert("ship_draw".to_string(), gfx.triangle_draw());
         
-        handles.insert("exhaust_indices".to_string(), gfx.indices(handles["exhaust"]));
-        handles.insert("exhaust_draw".to_string(), gfx.triangle_draw());
+        // the flame glows over whatever is behind it
+        gfx.blend(gfx::state::GfxBlend::Additive);
+        handles.commands.insert("exhaust_indices".to_string(), gfx.indices(handles.indices["exhaust"])?);
+        handles.commands.insert("exhaust_draw".to_string(), gfx.triangle_draw());
+        gfx.blend(gfx::state::GfxBlend::Alpha);
+        gfx.pop_transform();
         
          
-        //gfx.skip(handles["ship_draw"]);
-        //gfx.skip(handles["left_gear_draw"]);
-        //gfx.skip(handles["right_gear_draw"]);
-        //gfx.skip(handles["left_foot_draw"]);
-        //gfx.skip(handles["right_foot_draw"]);
+        //gfx.skip(handles.commands["ship_draw"]);
+        //gfx.skip(handles.commands["left_gear_draw"]);
+        //gfx.skip(handles.commands["right_gear_draw"]);
+        //gfx.skip(handles.commands["left_foot_draw"]);
+        //gfx.skip(handles.commands["right_foot_draw"]);
+
+        return Ok(handles);
+    }
+
 
-        return handles;
+}
+fn arg_value(args: &[String], name: &str) -> Option<String> {
+    let position = args.iter().position(|arg| arg == name)?;
+    return args.get(position + 1).cloned();
+}
+
+// 3840x2160, no bigger than can be drawn offscreen
+fn parse_size(text: &str) -> Option<(u32, u32)> {
+    let mut parts = text.split('x');
+    let width  = parts.next()?.parse().ok()?;
+    let height = parts.next()?.parse().ok()?;
+    if parts.next().is_some() || gfx::check_offscreen_size(width, height).is_err() {
+        return None;
     }
+    return Some((width, height));
+}
+
+// days since 1970 to (year, month, day) in the proleptic gregorian
+// calendar
+fn civil_date(days: i64) -> (i64, i64, i64) {
+    let days  = days + 719468;
+    let era   = days.div_euclid(146097);
+    let day_of_era  = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era/1460 + day_of_era/36524 - day_of_era/146096) / 365;
+    let day_of_year = day_of_era - (365*year_of_era + year_of_era/4 - year_of_era/100);
+    let month_from_march = (5*day_of_year + 2) / 153;
+    let day   = day_of_year - (153*month_from_march + 2)/5 + 1;
+    let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 };
+    let year  = year_of_era + era*400 + if month <= 2 { 1 } else { 0 };
+    return (year, month, day);
+}
 
+// 20201231-235959-999 in utc, for file names that sort by when they
+// were made
+fn timestamp() -> String {
+    let now = std::time::SystemTime::now()
+                  .duration_since(std::time::UNIX_EPOCH)
+                  .unwrap_or_default();
+    let seconds = now.as_secs() as i64;
+    let time    = seconds.rem_euclid(86400);
+    let (year, month, day) = civil_date(seconds.div_euclid(86400));
+
+    return format!("{0:04}{1:02}{2:02}-{3:02}{4:02}{5:02}-{6:03}",
+                   year, month, day,
+                   time / 3600, (time / 60) % 60, time % 60, now.subsec_millis());
+}
 
+// shaders/ next to the executable, for a copy that's been installed
+// somewhere, otherwise shaders/ under the working directory, which is
+// where cargo run leaves it
+fn default_shader_dir() -> String {
+    if let Some(dir) = std::env::current_exe().ok().and_then(|exe| exe.parent().map(|dir| dir.join("shaders"))) {
+        if dir.is_dir() {
+            return dir.to_string_lossy().into_owned();
+        }
+    }
+    return "shaders".to_string();
 }
+
 fn main() {
     let mut assets = assets::build_assets();
-    let event_loop = glutin::event_loop::EventLoop::new();
-    let wb = glutin::window::WindowBuilder::new();
-    let cb = glutin::ContextBuilder::new();
-    let mut display = glium::Display::new(wb, cb, &event_loop).unwrap();
-    let linevertex140: &'static str = " #version 140
-                                        in vec2 position;
-                                        uniform vec2 translation;
-                                        uniform vec2 origin;
-                                        uniform float scene_scale;
-                                        uniform float object_scale;
-                                        uniform float angle;
-                                        uniform float aspect_ratio;
-                                        out vec3 vColor;
-                                       
-                                        float posx = position[0] * object_scale;
-                                        float posy = position[1] * object_scale;
-                                        float sina = sin(angle);
-                                        float cosa = cos(angle);
-
-                                        void main() {
-                                            gl_Position = vec4(((posx*cosa-posy*sina)+(translation[0]-origin[0]))*scene_scale*aspect_ratio,
-                                                               ((posx*sina+posy*cosa)+(translation[1]-origin[1]))*scene_scale, 0.0, 1.0);
-                                            vColor = vec3(1.0,1.0,1.0);
-                                        }";
-
-    let linefragment140: &'static str = " #version 140
-                                          in vec3 vColor;
-                                          out vec4 f_color;
-                                          void main() {
-                                              f_color = vec4(vColor, 1.0);
-                                          }";
-    
-    let trivertex140: &'static str = " #version 140
-                                       in vec2 position;
-                                       in vec4 color;
-                                       uniform vec2 translation;
-                                       uniform vec2 origin;
-                                       uniform float scene_scale;
-                                       uniform float object_scale;
-                                       uniform float angle;
-                                       uniform float aspect_ratio;
-                                       out vec4 vColor;
-
-                                       float posx = position[0] * object_scale;
-                                       float posy = position[1] * object_scale;
-                                       float sina = sin(angle);
-                                       float cosa = cos(angle);
-                                       void main() {
-                                           gl_Position = vec4(((posx*cosa-posy*sina)+(translation[0]-origin[0]))*scene_scale*aspect_ratio,
-                                                              ((posx*sina+posy*cosa)+(translation[1]-origin[1]))*scene_scale, 0.0, 1.0);
-                                           vColor = color;
-                                       }";
-
-    let trifragment140: &'static str = " #version 140
-                                         in vec4 vColor;
-                                         out vec4 f_color;
-                                         void main() {
-                                             f_color = vec4(vColor);
-                                         }";
-   
     let mut gfx = gfx::Gfx::new();
 
 
@@ -1479,24 +1431,244 @@ fn main() {
     //gfx.sky(&display, 1000.0, 8.0, 200);
     //gfx.circle(&display, 400, 1000.0);
 
-    gfx.add_program(&display, linevertex140, linefragment140);
-    gfx.add_program(&display, trivertex140, trifragment140);
-    gfx.scene_scale(0.05);
-    gfx.origin(0.0,1000.0);
+    let args: Vec<String> = std::env::args().collect();
+
+    // screenshots and snapshots drawn offscreen at this size rather
+    // than whatever the window or the software backend is
+    let screenshot_size = arg_value(&args, "--screenshot-size").map(|text| {
+        match parse_size(&text) {
+            Some(size) => size,
+            None => {
+                println!("--screenshot-size is width x height, like 3840x2160, up to {0}x{0}", gfx::MAX_OFFSCREEN_SIZE);
+                std::process::exit(1);
+            }
+        }
+    });
+
+    // shaders/<name>.vert and .frag, picked up again when they change
+    let shader_dir = arg_value(&args, "--shaders")
+                         .unwrap_or_else(default_shader_dir);
+    let mut shaders = gfx::shaders::ShaderLibrary::new(&shader_dir);
+    let mut load_program = |gfx: &mut gfx::Gfx, name: &str| {
+        match shaders.load(gfx, name) {
+            Ok(program) => program,
+            Err(error)  => {
+                println!("{0}", error);
+                std::process::exit(1);
+            }
+        }
+    };
+    let programs = Programs { lines:               load_program(&mut gfx, "line"),
+                              triangles:           load_program(&mut gfx, "triangle"),
+                              instanced_triangles: load_program(&mut gfx, "triangle_instanced") };
+    // the main viewport draws from here to scene_end, things with
+    // viewports of their own go after
+    let scene_start = gfx.noop();
+    let mut camera = camera::Camera::new(&mut gfx, (0.0, 1000.0), 0.05);
 
     let mut planet = Planet::new((0.0, 0.0),
                                  1000.0,
                                  1000.0,
-                                 Planet::geometry(&mut gfx, &display, &mut assets, 1000.0));
+                                 Planet::geometry(&mut gfx, &mut assets, &programs, 1000.0, &PLANET_BACKGROUND).unwrap());
+
+    let mut player_ship = PlayerShip::new(PlayerShip::geometry(&mut gfx, &programs).unwrap());
+
+    // over the planet and under the ship, dust in front of the flame
+    let mut exhaust = particles::ParticleSystem::new(&mut gfx, programs.triangles, 512, 
+                                                     gfx::state::GfxBlend::Additive, EFFECTS_LAYER).unwrap();
+    let mut dust    = particles::ParticleSystem::new(&mut gfx, programs.triangles, 256, 
+                                                     gfx::state::GfxBlend::Alpha, EFFECTS_LAYER).unwrap();
+    let scene_end = gfx.noop();
+    gfx.set_viewport_commands("main", Some((scene_start, scene_end))).unwrap();
+
+    // throw the planet away and build it again in the same place in
+    // the command list, which should look exactly the same
+    if args.iter().any(|arg| arg == "--rebuild-planet") {
+        gfx.insert_before(planet.gfx_commands.0).unwrap();
+        let geometry = Planet::geometry(&mut gfx, &mut assets, &programs, 1000.0, &PLANET_BACKGROUND).unwrap();
+        gfx.append();
+        planet.free(&mut gfx).unwrap();
+        gfx.compact();
+        planet = Planet::new((0.0, 0.0), 1000.0, 1000.0, geometry);
+    }
+
+    let landing_sites: Vec<_> = planet.landing_sites().iter().map(|site| (*site, LANDING_SITE_COLOR)).collect();
+    let mut radar = radar::Radar::new(&mut gfx,
+                                      programs.lines,
+                                      programs.triangles,
+                                      planet.horizon_geometry,
+                                      planet.position,
+                                      planet.size * 1.5,
+                                      gfx::state::GfxRect::new(0.74, 0.02, 0.24, 0.32),
+                                      &landing_sites).unwrap();
+
+    if let Some(path) = arg_value(&args, "--dump-scene") {
+        std::fs::write(&path, gfx.dump()).unwrap();
+    }
+    if args.iter().any(|arg| arg == "--dump") {
+        gfx.print_commands();
+    }
+
+    // a scene loaded from a file is drawn exactly as it was saved, the
+    // ship and planet don't get to move anything
+    let loaded = match arg_value(&args, "--load-scene") {
+        None => false,
+        Some(path) => {
+            let text = std::fs::read_to_string(&path).unwrap();
+            match gfx::Gfx::load(&text) {
+                Ok(scene) => gfx = scene,
+                Err(error) => {
+                    println!("{0}: {1}", path, error);
+                    std::process::exit(1);
+                }
+            }
+            true
+        }
+    };
+
+    // build the scene and push one frame through a backend with no 
+    // window behind it, then quit
+    if args.iter().any(|arg| arg == "--check") {
+        let mut backend = gfx::recording::RecordingBackend::new(1024, 768);
+        if !loaded {
+            player_ship.tick(&mut gfx).unwrap();
+            planet.tick(&mut gfx, gfx::get_angle(player_ship.position, planet.position)).unwrap();
+            radar.tick(&mut gfx, player_ship.position, player_ship.angle, player_ship.velocity).unwrap();
+        }
+        gfx.run(&mut backend);
+
+        println!("commands: {0} programs: {1} index buffers: {2}", 
+                 gfx.num_commands(), gfx.num_programs(), gfx.num_indices());
+        println!("line vertices: {0} triangle vertices: {1} draws: {2}", 
+                 backend.num_lines(), backend.num_triangles(), backend.draws.len());
+        println!("vertices uploaded: {0} culled: {1}", backend.vertices_uploaded, gfx.num_culled());
+        println!("frame: {0}", gfx.stats());
+        let ship_on_screen = camera.world_to_screen(player_ship.position, 1024, 768);
+        println!("camera: {0:?} scale {1} angle {2} ship on screen {3:?} back in world {4:?}",
+                 camera.position(), camera.scale(), camera.angle(), ship_on_screen, 
+                 camera.screen_to_world(ship_on_screen, 1024, 768));
+        for (i, draw) in backend.draws.iter().enumerate() {
+            let instances = match (draw.instances, draw.triangles) {
+                (Some((id, count)), _) => format!(" instances {0} x{1}", id, count),
+                (None, Some(count))    => format!(" as {0} triangles", count),
+                (None, None)           => String::new()
+            };
+            println!("draw {0}: {1:?} program {2} indices {3}{4} transform {5:?} {6:?}", 
+                     i, draw.draw_type, draw.program, draw.indices, instances,
+                     draw.uniforms.transform.m, draw.state);
+        }
+        for error in &backend.errors {
+            println!("error: {0}", error);
+        }
+        if !backend.errors.is_empty() {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // render one frame on the cpu, optionally checking it against a 
+    // golden image
+    if let Some(path) = arg_value(&args, "--snapshot") {
+        let mut backend = gfx::software::SoftwareBackend::new(1024, 768);
+        if !loaded {
+            player_ship.tick(&mut gfx).unwrap();
+            planet.tick(&mut gfx, gfx::get_angle(player_ship.position, planet.position)).unwrap();
+            radar.tick(&mut gfx, player_ship.position, player_ship.angle, player_ship.velocity).unwrap();
+        }
+        let image = match screenshot_size {
+            Some((width, height)) => match gfx.render_offscreen(&mut backend, width, height) {
+                Ok(image)  => image.unwrap(),
+                Err(error) => {
+                    println!("{0}", error);
+                    std::process::exit(1);
+                }
+            },
+            None => {
+                gfx.run(&mut backend);
+                backend.image().clone()
+            }
+        };
+        for error in &backend.errors {
+            println!("error: {0}", error);
+        }
+        if !backend.errors.is_empty() {
+            std::process::exit(1);
+        }
+        image.save_png(&path).unwrap();
+        println!("wrote {0}", path);
+
+        if let Some(golden) = arg_value(&args, "--golden") {
+            let expected = gfx::image::Image::load_png(&golden).unwrap();
+            // compare what was written, not what was rendered, so the
+            // alpha channel doesn't count
+            let written = gfx::image::Image::load_png(&path).unwrap();
+            match written.difference(&expected) {
+                None => {
+                    println!("{0} is {1}x{2}, snapshot is {3}x{4}", golden, 
+                             expected.width, expected.height, written.width, written.height);
+                    std::process::exit(1);
+                },
+                Some(difference) => {
+                    if difference.differing_pixels > 0 {
+                        println!("{0} pixels differ from {1}, by up to {2}", 
+                                 difference.differing_pixels, golden, difference.max_difference);
+                        std::process::exit(1);
+                    }
+                    println!("matches {0}", golden);
+                }
+            }
+        }
+        return;
+    }
 
-    let mut player_ship = PlayerShip::new(PlayerShip::geometry(&mut gfx, &display));
-    gfx.run(&display);
+    let event_loop = glutin::event_loop::EventLoop::new();
+    let wb = glutin::window::WindowBuilder::new();
+    let cb = glutin::ContextBuilder::new().with_stencil_buffer(8);
+    let display = glium::Display::new(wb, cb, &event_loop).unwrap();
+    let mut backend = gfx::glium_backend::GliumBackend::new(&display);
+    camera.set_smoothing(0.1);
+    camera.set_zoom_limits(0.001, 1.0);
+    let mut zoom = zoom::AltitudeZoom::new(10.0, 0.2, 1000.0, 0.002);
+    zoom.set_curve(match arg_value(&args, "--zoom-curve") {
+        None => zoom::ZoomCurve::EaseOut,
+        Some(name) => match zoom::ZoomCurve::from_name(&name) {
+            Some(curve) => curve,
+            None => {
+                println!("--zoom-curve is one of linear, ease-in, ease-out or smooth-step");
+                std::process::exit(1);
+            }
+        }
+    });
+    zoom.set_hysteresis(5.0);
+    let mut profiler = profiler::FrameProfiler::new(std::time::Duration::from_secs(10));
+    profiler.add_overlay(&mut gfx, programs.lines, programs.triangles).unwrap();
+    gfx.run(&mut backend);
+    let mut last_shader_check = std::time::Instant::now();
+    let mut screenshot_wanted = false;
+    let mut recorder = recorder::FrameRecorder::new(screenshot_size);
+    if let Some(dir) = arg_value(&args, "--record") {
+        if let Err(error) = recorder.start(&dir) {
+            println!("{0}: {1}", dir, error);
+            std::process::exit(1);
+        }
+    }
 
     // the main loop
     event_loop.run(move |event, _, control_flow| {
         let next_frame_time = std::time::Instant::now() +
             std::time::Duration::from_nanos(16_666_667);
         *control_flow = glutin::event_loop::ControlFlow::WaitUntil(next_frame_time);
+
+        // while recording, every frame is exactly one tick however long
+        // it takes to draw and save, and the next one starts as soon as
+        // it's done rather than waiting for real time to catch up
+        let frame_done = match event {
+            glutin::event::Event::MainEventsCleared => true,
+            _                                       => false
+        };
+        if recorder.recording() {
+            *control_flow = glutin::event_loop::ControlFlow::Poll;
+        }
             
         match event {
             glutin::event::Event::WindowEvent { event, .. } => match event {
@@ -1509,6 +1681,30 @@ fn main() {
                     if input.scancode == 34 && input.state == glutin::event::ElementState::Released {
                         player_ship.cycle_gear();
                     }
+                    // F3
+                    if input.scancode == 61 && input.state == glutin::event::ElementState::Released {
+                        profiler.toggle_overlay(&mut gfx).unwrap();
+                    }
+                    // M
+                    if input.scancode == 50 && input.state == glutin::event::ElementState::Released {
+                        radar.toggle(&mut gfx).unwrap();
+                    }
+                    // F12
+                    if input.scancode == 88 && input.state == glutin::event::ElementState::Released {
+                        screenshot_wanted = true;
+                    }
+                    // R
+                    if input.scancode == 19 && input.state == glutin::event::ElementState::Released {
+                        if recorder.recording() {
+                            println!("recorded {0} frames", recorder.stop());
+                        } else {
+                            let dir = format!("recording-{0}", timestamp());
+                            match recorder.start(&dir) {
+                                Ok(())     => println!("recording to {0}", dir),
+                                Err(error) => println!("{0}: {1}", dir, error)
+                            }
+                        }
+                    }
 
                     if input.state == glutin::event::ElementState::Released {
                         if input.scancode == 105 && player_ship.turning_left() {
@@ -1541,18 +1737,103 @@ fn main() {
             _ => ()
         };
         
-        let angle = gfx::get_angle(planet.position, player_ship.position);
+        if recorder.recording() && !frame_done {
+            return;
+        }
+
+        let tick_started = std::time::Instant::now();
+        // look at the point halfway between the ground and the ship,
+        // with the planet underneath
+        let angle = gfx::get_angle(player_ship.position, planet.position);
         let distance = gfx::get_distance(planet.position, player_ship.position);
         let midpoint = planet.size + ((distance - planet.size)/2.0);
-
-        gfx.change_origin(1, -1.0 * angle.sin()*midpoint, -1.0 * angle.cos()*midpoint);
-        //gfx.change_scene_scale(0, 0.00005 + (1.0/(distance-planet.size + 10.0))  );
-        gfx.change_scene_scale(0, 0.2);
-
-        player_ship.tick(&mut gfx);
-        planet.tick(&mut gfx, gfx::get_angle(player_ship.position, planet.position));
+        camera.follow(gfx::add_points(planet.position, gfx::place(angle, midpoint)));
+        camera.face_away_from(planet.position);
+        camera.zoom_to(zoom.scale(distance - planet.size));
+        camera.tick(&mut gfx).unwrap();
+        // the ship stays the same size on screen however far out the
+        // camera is
+        player_ship.scale = SHIP_SIZE / camera.scale();
+
+        player_ship.tick(&mut gfx).unwrap();
+        planet.tick(&mut gfx, gfx::get_angle(player_ship.position, planet.position)).unwrap();
+        radar.tick(&mut gfx, player_ship.position, player_ship.angle, player_ship.velocity).unwrap();
+
+        // a bump on touching down, harder the faster it came in
+        let landed = player_ship.flags & LANDED != 0;
+        let speed  = gfx::get_distance(player_ship.velocity, (0.0, 0.0));
         player_ship.gravity(&planet);
-        gfx.run(&mut display);
+        if !landed && player_ship.flags & LANDED != 0 {
+            camera.shake(speed * 0.5);
+            player_ship.touch_down(&planet, speed, &mut exhaust, &mut dust);
+        }
+        player_ship.emit_particles(&planet, &mut exhaust, &mut dust);
+        exhaust.tick(&mut gfx, planet.position, planet.size, GRAVITY).unwrap();
+        dust.tick(&mut gfx, planet.position, planet.size, GRAVITY).unwrap();
+        let tick_time = tick_started.elapsed();
+
+        if last_shader_check.elapsed() > std::time::Duration::from_millis(500) {
+            for result in shaders.reload_changed(&mut gfx) {
+                match result {
+                    Ok(name)   => println!("reloading shader {0}", name),
+                    Err(error) => println!("{0}", error)
+                }
+            }
+            last_shader_check = std::time::Instant::now();
+        }
+        gfx.run(&mut backend);
+        for error in gfx.take_program_errors() {
+            println!("{0}", shaders.report(&error));
+        }
+        profiler.record(&mut gfx, tick_time).unwrap();
+        if let Some(line) = profiler.log_line() {
+            println!("{0}", line);
+        }
+        if recorder.recording() {
+            if let Err(error) = recorder.record(&mut gfx, &mut backend) {
+                println!("recording stopped after {0} frames: {1}", recorder.stop(), error);
+            }
+        }
+        if screenshot_wanted {
+            screenshot_wanted = false;
+            let image = match screenshot_size {
+                Some((width, height)) => gfx.render_offscreen(&mut backend, width, height),
+                None                  => Ok(backend.read_frame())
+            };
+            let path = format!("screenshot-{0}.png", timestamp());
+            match image.map(|image| image.map(|image| image.save_png(&path))) {
+                Ok(Some(Ok(())))     => println!("wrote {0}", path),
+                Ok(Some(Err(error))) => println!("{0}: {1}", path, error),
+                Ok(None)             => println!("couldn't read back the frame for a screenshot"),
+                Err(error)           => println!("no screenshot: {0}", error)
+            }
+        }
     });
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(-1), (1969, 12, 31));
+        assert_eq!(civil_date(59), (1970, 3, 1));
+        // 2000 is a leap year, 2100 isn't
+        assert_eq!(civil_date(11016), (2000, 2, 29));
+        assert_eq!(civil_date(11017), (2000, 3, 1));
+        assert_eq!(civil_date(47540), (2100, 2, 28));
+        assert_eq!(civil_date(47541), (2100, 3, 1));
+        assert_eq!(civil_date(18627), (2020, 12, 31));
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("3840x2160"), Some((3840, 2160)));
+        assert_eq!(parse_size("0x10"), None);
+        assert_eq!(parse_size("10x10x10"), None);
+        assert_eq!(parse_size("100000x100000"), None);
+        assert_eq!(parse_size("4294967295x1"), None);
+    }
+}
@@ -0,0 +1,108 @@
+use glium::index::PrimitiveType;
+
+use crate::gfx;
+
+// One ring of scenery behind the ground.  It's built round the middle
+// of the planet at depth times the planet's radius and moved so the
+// part of it under whoever's looking sits on the surface, so going
+// round the planet it slides past depth times as fast as the ground
+// does: small depths are far away, 1.0 would be the ground itself.
+#[derive(Copy, Clone)]
+pub struct ParallaxLayer {
+    pub depth:      f32,
+    // how tall it stands at an angle round the ring, starting altitude
+    // above the ring, so clouds can float and have gaps where it's 0
+    pub height:     fn(f32) -> f32,
+    pub altitude:   f32,
+    pub divisions:  u32,
+    // faded from one to the other going down
+    pub top_color:  (f32, f32, f32, f32),
+    pub base_color: (f32, f32, f32, f32),
+    // radians a tick it drifts round by itself
+    pub scroll:     f32
+}
+
+struct ParallaxRing {
+    layer:   ParallaxLayer,
+    indices: gfx::IndexHandle,
+    drift:   f32
+}
+
+// A planet's background, drawn furthest layer first whatever order
+// they're given in.  Built in two goes like the rest of a planet: the
+// geometry, then once the vertices are claimed the commands to draw it.
+pub struct Parallax {
+    radius:     f32,
+    rings:      Vec< ParallaxRing >,
+    // translation and rotation for each ring, once draw() has added them
+    transforms: Vec< (gfx::CommandHandle, gfx::CommandHandle) >
+}
+
+fn ring(gfx: &mut gfx::Gfx, layer: &ParallaxLayer, inner_radius: f32) -> gfx::IndexHandle {
+    let angle_step = (std::f32::consts::PI * 2.0)/(layer.divisions as f32);
+    let mut indices = Vec::new();
+    let start_vert = gfx.triangle_len();
+    for i in 0..(layer.divisions) {
+        let angle  = (i as f32)*angle_step;
+        let bottom = inner_radius + layer.altitude;
+        let height = (layer.height)(angle);
+        gfx.add_triangle_vertex( gfx::place(angle, bottom + height), layer.top_color );
+        gfx.add_triangle_vertex( gfx::place(angle, bottom), layer.base_color );
+        indices.push((start_vert as u32)+i*2);
+        indices.push((start_vert as u32)+i*2+1);
+    }
+    indices.push(start_vert as u32);
+    indices.push((start_vert as u32)+1);
+    return gfx.add_indices(&indices, PrimitiveType::TriangleStrip);
+}
+
+impl Parallax {
+    pub fn new(gfx: &mut gfx::Gfx,
+               radius: f32,
+               layers: &[ParallaxLayer]) -> Parallax {
+        let mut layers = layers.to_vec();
+        layers.sort_by(|a, b| a.depth.partial_cmp(&b.depth).unwrap_or(std::cmp::Ordering::Equal));
+        let rings = layers.iter().map(|layer| ParallaxRing { layer:   *layer,
+                                                             indices: ring(gfx, layer, radius*layer.depth),
+                                                             drift:   0.0 }).collect();
+        return Parallax { radius:     radius,
+                          rings:      rings,
+                          transforms: Vec::new() };
+    }
+
+    // with whatever program and draw state are current, leaving the
+    // transform as it found it
+    pub fn draw(&mut self, gfx: &mut gfx::Gfx) -> Result<(), gfx::GfxError> {
+        self.transforms.clear();
+        for ring in self.rings.iter() {
+            gfx.push_transform();
+            let translation = gfx.translate(0.0, self.radius - (ring.layer.depth * self.radius));
+            let rotation    = gfx.rotate(ring.drift);
+            gfx.indices(ring.indices)?;
+            gfx.triangle_draw();
+            gfx.pop_transform();
+            self.transforms.push((translation, rotation));
+        }
+        return Ok(());
+    }
+
+    // angle is where it's being looked at from, the way get_angle()
+    // measures it from the middle of the planet
+    pub fn tick(&mut self, gfx: &mut gfx::Gfx, angle: f32) -> Result<(), gfx::GfxError> {
+        for (ring, (translation, rotation)) in self.rings.iter_mut().zip(self.transforms.iter()) {
+            ring.drift += ring.layer.scroll;
+            gfx.change_translation(*translation, gfx::place(angle, self.radius - (self.radius*ring.layer.depth)))?;
+            gfx.change_rotation(*rotation, ring.drift)?;
+        }
+        return Ok(());
+    }
+
+    // the commands go with whatever they were added among and the
+    // vertices with whoever claimed them
+    pub fn free_indices(&self, gfx: &mut gfx::Gfx) -> Result<(), gfx::GfxError> {
+        for ring in self.rings.iter() {
+            gfx.free_indices(ring.indices)?;
+        }
+        return Ok(());
+    }
+}
@@ -0,0 +1,173 @@
+use glium::index::PrimitiveType;
+
+use crate::gfx;
+use crate::gfx::state::GfxBlend;
+
+// How one kind of particle starts out and changes over its life.
+// Speeds and sizes are multiplied by the scale given to emit(), so the
+// same style looks the same next to a ship drawn at any size.
+#[derive(Copy, Clone, Debug)]
+pub struct ParticleStyle {
+    // world units per tick, somewhere between the two
+    pub speed:       (f32, f32),
+    // radians either side of the direction it's emitted in
+    pub spread:      f32,
+    // ticks, somewhere between the two
+    pub life:        (u32, u32),
+    // across, at birth and at death
+    pub size:        (f32, f32),
+    // how much of the planet's pull it feels
+    pub gravity:     f32,
+    // fraction of its velocity lost every tick
+    pub drag:        f32,
+    pub start_color: (f32, f32, f32, f32),
+    pub end_color:   (f32, f32, f32, f32)
+}
+
+struct Particle {
+    position: (f32, f32),
+    velocity: (f32, f32),
+    age:      u32,
+    life:     u32,
+    scale:    f32,
+    style:    ParticleStyle
+}
+
+fn between(range: (f32, f32)) -> f32 {
+    return range.0 + (range.1 - range.0) * rand::random::<f32>();
+}
+
+fn mix(from: (f32, f32, f32, f32), to: (f32, f32, f32, f32), t: f32) -> (f32, f32, f32, f32) {
+    return (from.0 + (to.0 - from.0) * t,
+            from.1 + (to.1 - from.1) * t,
+            from.2 + (to.2 - from.2) * t,
+            from.3 + (to.3 - from.3) * t);
+}
+
+// Up to capacity particles in world space, all drawn as quads in one
+// draw with one blend, in whichever layer they're given.  The vertices
+// and indices are allocated up front and only the vertices change;
+// quads nobody is using are squashed to a point on a live one, and the
+// draw is skipped when there are none.
+pub struct ParticleSystem {
+    vertices:  gfx::VertexHandle,
+    draw:      gfx::CommandHandle,
+    particles: Vec< Particle >,
+    capacity:  usize,
+    // quads that had a particle in them last tick, or might have
+    drawn:     usize,
+    // what goes into the vertices, kept to save allocating every tick
+    quads:     Vec< gfx::GfxTriangleVertex >
+}
+
+impl ParticleSystem {
+    pub fn new(gfx: &mut gfx::Gfx,
+               triangle_program: gfx::ProgramHandle,
+               capacity: usize,
+               blend: GfxBlend,
+               layer: i32) -> Result<ParticleSystem, gfx::GfxError> {
+        let vertices = gfx.alloc_triangle_vertices(capacity * 4);
+        let start = gfx.vertex_start(vertices)? as u32;
+        let mut indices = Vec::with_capacity(capacity * 6);
+        for i in 0..(capacity as u32) {
+            let quad = start + i*4;
+            indices.extend_from_slice(&[quad, quad+1, quad+2, quad, quad+2, quad+3]);
+        }
+        let indices = gfx.add_indices(&indices, PrimitiveType::TrianglesList);
+
+        gfx.layer(layer);
+        gfx.program(triangle_program)?;
+        gfx.blend(blend);
+        gfx.indices(indices)?;
+        let draw = gfx.triangle_draw();
+        gfx.blend(GfxBlend::Alpha);
+        gfx.skip(draw)?;
+
+        return Ok(ParticleSystem { vertices:  vertices,
+                                   draw:      draw,
+                                   particles: Vec::with_capacity(capacity),
+                                   capacity:  capacity,
+                                   drawn:     capacity,
+                                   quads:     Vec::with_capacity(capacity * 4) });
+    }
+
+    // count new particles at position heading off along direction,
+    // plus velocity, which is whatever they came off.  Any that don't
+    // fit are dropped.
+    pub fn emit(&mut self,
+                style: &ParticleStyle,
+                position: (f32, f32),
+                velocity: (f32, f32),
+                direction: (f32, f32),
+                count: usize,
+                scale: f32) {
+        let length = gfx::get_distance(direction, (0.0, 0.0));
+        if length == 0.0 {
+            return;
+        }
+        let direction = gfx::scale_point(direction, 1.0 / length);
+        for _ in 0..count.min(self.capacity - self.particles.len()) {
+            let heading = gfx::rotate(direction, (rand::random::<f32>()*2.0 - 1.0) * style.spread);
+            let speed   = between(style.speed) * scale;
+            let life    = style.life.0 + ((style.life.1 - style.life.0) as f32 * rand::random::<f32>()) as u32;
+            self.particles.push(Particle { position: position,
+                                           velocity: gfx::add_points(velocity, gfx::scale_point(heading, speed)),
+                                           age:      0,
+                                           life:     life.max(1),
+                                           scale:    scale,
+                                           style:    *style });
+        }
+    }
+
+    // moves everything on a tick, pulled towards a planet at
+    // planet_position the way the ship is, and puts it in the vertices.
+    // Particles die of old age or on hitting the ground.
+    pub fn tick(&mut self,
+                gfx: &mut gfx::Gfx,
+                planet_position: (f32, f32),
+                planet_size: f32,
+                pull: f32) -> Result<(), gfx::GfxError> {
+        for particle in self.particles.iter_mut() {
+            let distance = gfx::get_distance(particle.position, planet_position);
+            let angle    = gfx::get_angle(particle.position, planet_position);
+            let gravity  = pull / distance.powf(2.0) * particle.style.gravity;
+            particle.velocity.0 -= angle.sin() * gravity;
+            particle.velocity.1 -= angle.cos() * gravity;
+            particle.velocity    = gfx::scale_point(particle.velocity, 1.0 - particle.style.drag);
+            particle.position    = gfx::add_points(particle.position, particle.velocity);
+            particle.age        += 1;
+        }
+        self.particles.retain(|particle| {
+            particle.age < particle.life &&
+            gfx::get_distance(particle.position, planet_position) > planet_size
+        });
+
+        if self.particles.is_empty() {
+            return gfx.skip(self.draw);
+        }
+        gfx.unskip(self.draw)?;
+
+        self.quads.clear();
+        for particle in self.particles.iter() {
+            let t     = particle.age as f32 / particle.life as f32;
+            let half  = (particle.style.size.0 + (particle.style.size.1 - particle.style.size.0) * t) * particle.scale * 0.5;
+            let (r, g, b, a) = mix(particle.style.start_color, particle.style.end_color, t);
+            let (x, y) = particle.position;
+            for corner in [(x - half, y - half), (x + half, y - half),
+                           (x + half, y + half), (x - half, y + half)].iter() {
+                self.quads.push(gfx::GfxTriangleVertex { position: [corner.0, corner.1], color: [r, g, b, a] });
+            }
+        }
+        // out of the way without making the bounds any bigger
+        let (x, y) = self.particles[0].position;
+        for _ in self.particles.len()..self.drawn {
+            for _ in 0..4 {
+                self.quads.push(gfx::GfxTriangleVertex { position: [x, y], color: [0.0; 4] });
+            }
+        }
+        // all at once, so it's one upload and one look at the bounds
+        gfx.set_triangle_vertices(gfx.vertex_start(self.vertices)?, &self.quads)?;
+        self.drawn = self.particles.len();
+        return Ok(());
+    }
+}
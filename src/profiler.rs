@@ -0,0 +1,177 @@
+use std::time::{Duration, Instant};
+
+use crate::gfx;
+use crate::gfx::state::GfxRect;
+use crate::gfx::stats::GfxFrameStats;
+use crate::gfx::viewport::GfxView;
+
+// a frame at 60fps, the overlay bars are measured against it
+const FRAME_BUDGET_MS: f32 = 1000.0 / 60.0;
+
+// where the bars go, in clip space from the top left corner.  A bar
+// BUDGET_LENGTH long is a whole frame.
+const LEFT:          f32 = -0.95;
+const TOP:           f32 = 0.95;
+const BAR_HEIGHT:    f32 = 0.03;
+const BAR_GAP:       f32 = 0.01;
+const BUDGET_LENGTH: f32 = 0.5;
+
+const TICK_COLOR:   (f32, f32, f32, f32) = (0.2, 0.9, 0.3, 0.8);
+const RENDER_COLOR: (f32, f32, f32, f32) = (1.0, 0.6, 0.1, 0.8);
+
+// Two bars in the corner of the screen, time spent ticking and time
+// spent in Gfx::run, with a line across them where a frame runs out.
+// Drawn in a viewport of its own over the whole frame, looking at
+// plain clip space.
+struct Overlay {
+    aspect:  gfx::CommandHandle,
+    draws:   [gfx::CommandHandle; 2],
+    bars:    gfx::VertexHandle,
+    visible: bool
+}
+
+// Adds up what each frame cost so it can be logged as averages every
+// so often, and keeps the overlay up to date.
+pub struct FrameProfiler {
+    period:       Duration,
+    since:        Instant,
+    frames:       u32,
+    tick_total:   Duration,
+    render_total: Duration,
+    draw_total:   usize,
+    upload_total: usize,
+    tick:         Duration,
+    last:         GfxFrameStats,
+    overlay:      Option<Overlay>
+}
+
+fn ms(duration: Duration) -> f32 {
+    return duration.as_secs_f32() * 1000.0;
+}
+
+impl FrameProfiler {
+    pub fn new(period: Duration) -> FrameProfiler {
+        FrameProfiler { period:       period,
+                        since:        Instant::now(),
+                        frames:       0,
+                        tick_total:   Duration::from_secs(0),
+                        render_total: Duration::from_secs(0),
+                        draw_total:   0,
+                        upload_total: 0,
+                        tick:         Duration::from_secs(0),
+                        last:         GfxFrameStats::new(),
+                        overlay:      None }
+    }
+
+    // hidden to start with
+    pub fn add_overlay(&mut self,
+                       gfx: &mut gfx::Gfx,
+                       line_program: gfx::ProgramHandle,
+                       triangle_program: gfx::ProgramHandle) -> Result<(), gfx::GfxError> {
+        let bars   = gfx.alloc_triangle_vertices(8);
+        let budget = gfx.alloc_line_vertices(2);
+        let start  = gfx.vertex_start(bars)? as u32;
+        let bar_indices = gfx.add_indices(&[start,   start+1, start+2, start,   start+2, start+3,
+                                            start+4, start+5, start+6, start+4, start+6, start+7],
+                                          glium::index::PrimitiveType::TrianglesList);
+        let line_start = gfx.vertex_start(budget)?;
+        let x = LEFT + BUDGET_LENGTH;
+        gfx.set_line_vertex(line_start, (x, TOP + BAR_GAP))?;
+        gfx.set_line_vertex(line_start + 1, (x, TOP - BAR_HEIGHT*2.0 - BAR_GAP*2.0))?;
+        let line_indices = gfx.add_indices(&[line_start as u32, line_start as u32 + 1],
+                                           glium::index::PrimitiveType::LinesList);
+
+        let aspect = gfx.scale(1.0, 1.0);
+        gfx.line_width(1.0);
+        gfx.program(triangle_program)?;
+        gfx.indices(bar_indices)?;
+        let bar_draw = gfx.triangle_draw();
+        gfx.program(line_program)?;
+        gfx.indices(line_indices)?;
+        let line_draw = gfx.line_draw();
+        gfx.skip(bar_draw)?;
+        gfx.skip(line_draw)?;
+        gfx.set_viewport("profiler", GfxRect::full());
+        gfx.set_viewport_view("profiler", Some(GfxView::new((0.0, 0.0), 1.0, 0.0)))?;
+        gfx.set_viewport_commands("profiler", Some((aspect, line_draw)))?;
+
+        self.overlay = Some(Overlay { aspect:  aspect,
+                                      draws:   [bar_draw, line_draw],
+                                      bars:    bars,
+                                      visible: false });
+        return Ok(());
+    }
+
+    pub fn toggle_overlay(&mut self, gfx: &mut gfx::Gfx) -> Result<(), gfx::GfxError> {
+        if let Some(ref mut overlay) = self.overlay {
+            overlay.visible = !overlay.visible;
+            for draw in overlay.draws.iter() {
+                if overlay.visible {
+                    gfx.unskip(*draw)?;
+                } else {
+                    gfx.skip(*draw)?;
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    // call after Gfx::run with however long the tick before it took
+    pub fn record(&mut self, gfx: &mut gfx::Gfx, tick: Duration) -> Result<(), gfx::GfxError> {
+        let stats = gfx.stats();
+        self.frames       += 1;
+        self.tick_total   += tick;
+        self.render_total += stats.render_time;
+        self.draw_total   += stats.draw_calls;
+        self.upload_total += stats.vertices_uploaded;
+        self.tick          = tick;
+        self.last          = stats;
+        return self.update_overlay(gfx);
+    }
+
+    fn update_overlay(&mut self, gfx: &mut gfx::Gfx) -> Result<(), gfx::GfxError> {
+        let overlay = match self.overlay {
+            Some(ref overlay) if overlay.visible => overlay,
+            _                                    => return Ok(())
+        };
+        if self.last.width > 0 {
+            gfx.change_scale(overlay.aspect, self.last.width as f32 / self.last.height as f32, 1.0)?;
+        }
+        let start = gfx.vertex_start(overlay.bars)?;
+        let times = [(ms(self.tick), TICK_COLOR), (ms(self.last.render_time), RENDER_COLOR)];
+        for (i, (time, color)) in times.iter().enumerate() {
+            // no further than the same margin on the right
+            let length = (time / FRAME_BUDGET_MS * BUDGET_LENGTH).min(-LEFT * 2.0);
+            let top    = TOP - (BAR_HEIGHT + BAR_GAP) * i as f32;
+            let corners = [(LEFT, top), (LEFT + length, top),
+                           (LEFT + length, top - BAR_HEIGHT), (LEFT, top - BAR_HEIGHT)];
+            for (j, corner) in corners.iter().enumerate() {
+                gfx.set_triangle_vertex(start + i*4 + j, *corner, *color)?;
+            }
+        }
+        return Ok(());
+    }
+
+    // averages since the last one, once every period
+    pub fn log_line(&mut self) -> Option<String> {
+        if self.since.elapsed() < self.period || self.frames == 0 {
+            return None;
+        }
+        let frames  = self.frames as f32;
+        let seconds = self.since.elapsed().as_secs_f32();
+        let line = format!("{0:.1} fps, tick {1:.2}ms render {2:.2}ms, {3:.0} draws {4:.0} vertices uploaded per frame; last frame {5}",
+                           frames / seconds,
+                           ms(self.tick_total) / frames,
+                           ms(self.render_total) / frames,
+                           self.draw_total as f32 / frames,
+                           self.upload_total as f32 / frames,
+                           self.last);
+        self.since        = Instant::now();
+        self.frames       = 0;
+        self.tick_total   = Duration::from_secs(0);
+        self.render_total = Duration::from_secs(0);
+        self.draw_total   = 0;
+        self.upload_total = 0;
+        return Some(line);
+    }
+}
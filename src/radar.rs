@@ -0,0 +1,144 @@
+use glium::index::PrimitiveType;
+
+use crate::gfx;
+use crate::gfx::state::GfxRect;
+use crate::gfx::viewport::GfxView;
+
+// how far ahead the velocity line reaches, in ticks at the current
+// velocity
+const VELOCITY_TICKS: f32 = 120.0;
+// blips are this fraction of the range across, whatever the scale
+const BLIP_SIZE: f32 = 0.05;
+
+const PLANET_TINT:    (f32, f32, f32) = (0.3, 0.6, 1.0);
+const SHIP_COLOR:     (f32, f32, f32, f32) = (1.0, 1.0, 1.0, 1.0);
+const BACKGROUND:     (f32, f32, f32, f32) = (0.0, 0.0, 0.05, 0.7);
+
+// A corner of the screen looking down on the whole planet, north up
+// whichever way the main camera has turned, with the ship as an arrow
+// and its velocity as a line.  Markers are fixed blips for places
+// worth finding, which is only the landing sites so far: there are no
+// passengers or other bodies in the game yet, and when there are
+// they'll want blips that move.  It has its own viewport and draws
+// nothing but its own commands.
+pub struct Radar {
+    ship:     gfx::VertexHandle,
+    velocity: gfx::VertexHandle,
+    commands: (gfx::CommandHandle, gfx::CommandHandle),
+    center:   (f32, f32),
+    range:    f32,
+    rect:     GfxRect,
+    visible:  bool
+}
+
+fn quad(gfx: &mut gfx::Gfx,
+        indices: &mut Vec< u32 >,
+        start: usize,
+        corners: [(f32, f32); 4],
+        color: (f32, f32, f32, f32)) -> Result<(), gfx::GfxError> {
+    for (i, corner) in corners.iter().enumerate() {
+        gfx.set_triangle_vertex(start + i, *corner, color)?;
+    }
+    let start = start as u32;
+    indices.extend_from_slice(&[start, start+1, start+2, start, start+2, start+3]);
+    return Ok(());
+}
+
+impl Radar {
+    // planet_outline is a line loop round the planet in world space,
+    // like Planet::circle, and range how far out from center the
+    // radar sees
+    pub fn new(gfx: &mut gfx::Gfx,
+               line_program: gfx::ProgramHandle,
+               triangle_program: gfx::ProgramHandle,
+               planet_outline: gfx::IndexHandle,
+               center: (f32, f32),
+               range: f32,
+               rect: GfxRect,
+               markers: &[((f32, f32), (f32, f32, f32, f32))]) -> Result<Radar, gfx::GfxError> {
+        let blip = range * BLIP_SIZE * 0.5;
+
+        // background, then a diamond per marker
+        let fixed = gfx.alloc_triangle_vertices(4 + markers.len() * 4);
+        let start = gfx.vertex_start(fixed)?;
+        let mut fixed_indices = Vec::new();
+        // much bigger than the range so it fills a viewport of any shape
+        let edge = range * 4.0;
+        quad(gfx, &mut fixed_indices, start,
+             [(center.0 - edge, center.1 - edge), (center.0 + edge, center.1 - edge),
+              (center.0 + edge, center.1 + edge), (center.0 - edge, center.1 + edge)],
+             BACKGROUND)?;
+        for (i, (position, color)) in markers.iter().enumerate() {
+            quad(gfx, &mut fixed_indices, start + 4 + i*4,
+                 [(position.0, position.1 - blip), (position.0 + blip, position.1),
+                  (position.0, position.1 + blip), (position.0 - blip, position.1)],
+                 *color)?;
+        }
+        let fixed_indices = gfx.add_indices(&fixed_indices, PrimitiveType::TrianglesList);
+
+        let ship     = gfx.alloc_triangle_vertices(3);
+        let start    = gfx.vertex_start(ship)? as u32;
+        let ship_indices = gfx.add_indices(&[start, start+1, start+2], PrimitiveType::TrianglesList);
+        let velocity = gfx.alloc_line_vertices(2);
+        let start    = gfx.vertex_start(velocity)? as u32;
+        let velocity_indices = gfx.add_indices(&[start, start+1], PrimitiveType::LinesList);
+
+        let first = gfx.program(triangle_program)?;
+        gfx.indices(fixed_indices)?;
+        gfx.triangle_draw();
+        gfx.program(line_program)?;
+        gfx.line_width(1.5);
+        gfx.tint(PLANET_TINT.0, PLANET_TINT.1, PLANET_TINT.2);
+        gfx.indices(planet_outline)?;
+        gfx.line_draw();
+        gfx.tint(1.0, 1.0, 1.0);
+        gfx.indices(velocity_indices)?;
+        gfx.line_draw();
+        gfx.program(triangle_program)?;
+        gfx.indices(ship_indices)?;
+        let last = gfx.triangle_draw();
+
+        let radar = Radar { ship:     ship,
+                            velocity: velocity,
+                            commands: (first, last),
+                            center:   center,
+                            range:    range,
+                            rect:     rect,
+                            visible:  true };
+        radar.add_viewport(gfx)?;
+        return Ok(radar);
+    }
+
+    fn add_viewport(&self, gfx: &mut gfx::Gfx) -> Result<(), gfx::GfxError> {
+        gfx.set_viewport("radar", self.rect);
+        gfx.set_viewport_view("radar", Some(GfxView::new(self.center, 1.0 / self.range, 0.0)))?;
+        return gfx.set_viewport_commands("radar", Some(self.commands));
+    }
+
+    pub fn tick(&mut self,
+                gfx: &mut gfx::Gfx,
+                position: (f32, f32),
+                angle: f32,
+                velocity: (f32, f32)) -> Result<(), gfx::GfxError> {
+        let blip = self.range * BLIP_SIZE;
+        let start = gfx.vertex_start(self.ship)?;
+        let points = [(0.0, blip), (-blip*0.5, -blip*0.5), (blip*0.5, -blip*0.5)];
+        for (i, point) in points.iter().enumerate() {
+            gfx.set_triangle_vertex(start + i, gfx::add_points(position, gfx::rotate(*point, angle)), SHIP_COLOR)?;
+        }
+
+        let start = gfx.vertex_start(self.velocity)?;
+        gfx.set_line_vertex(start, position)?;
+        gfx.set_line_vertex(start + 1, (position.0 + velocity.0*VELOCITY_TICKS,
+                                        position.1 + velocity.1*VELOCITY_TICKS))?;
+        return Ok(());
+    }
+
+    pub fn toggle(&mut self, gfx: &mut gfx::Gfx) -> Result<(), gfx::GfxError> {
+        self.visible = !self.visible;
+        if self.visible {
+            return self.add_viewport(gfx);
+        }
+        return gfx.remove_viewport("radar");
+    }
+}
@@ -0,0 +1,62 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::gfx;
+
+// Saves every frame into a directory as frame-000000.png,
+// frame-000001.png and so on, for turning into a video afterwards.
+// The frame numbers are simulation ticks, so a video played back at
+// the tick rate runs at the speed the game would have.
+pub struct FrameRecorder {
+    dir:   Option<PathBuf>,
+    frame: u32,
+    // drawn offscreen at this size rather than read back from the
+    // window
+    size:  Option<(u32, u32)>
+}
+
+impl FrameRecorder {
+    pub fn new(size: Option<(u32, u32)>) -> FrameRecorder {
+        FrameRecorder { dir:   None,
+                        frame: 0,
+                        size:  size }
+    }
+
+    pub fn recording(&self) -> bool {
+        return self.dir.is_some();
+    }
+
+    pub fn start<P: AsRef<Path>>(&mut self, dir: P) -> io::Result<()> {
+        std::fs::create_dir_all(dir.as_ref())?;
+        self.dir   = Some(dir.as_ref().to_path_buf());
+        self.frame = 0;
+        return Ok(());
+    }
+
+    // gives how many frames were saved
+    pub fn stop(&mut self) -> u32 {
+        self.dir = None;
+        return self.frame;
+    }
+
+    // call after Gfx::run, gives the file the frame went to
+    pub fn record(&mut self,
+                  gfx: &mut gfx::Gfx,
+                  backend: &mut dyn gfx::RenderBackend) -> io::Result<PathBuf> {
+        let path = match self.dir {
+            Some(ref dir) => dir.join(format!("frame-{0:06}.png", self.frame)),
+            None          => return Err(io::Error::other("not recording"))
+        };
+        let image = match self.size {
+            Some((width, height)) => gfx.render_offscreen(backend, width, height)
+                                        .map_err(|error| io::Error::other(error.to_string()))?,
+            None                  => backend.read_frame()
+        };
+        match image {
+            Some(image) => image.save_png(&path.to_string_lossy())?,
+            None        => return Err(io::Error::other("couldn't read back the frame"))
+        }
+        self.frame += 1;
+        return Ok(path);
+    }
+}
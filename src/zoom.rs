@@ -0,0 +1,139 @@
+// How the zoom moves between the near and far altitudes, given how far
+// along it is from 0.0 to 1.0
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ZoomCurve {
+    Linear,
+    // stays close in for a while, then pulls out quickly
+    EaseIn,
+    // pulls out quickly leaving the ground, then settles
+    EaseOut,
+    SmoothStep
+}
+
+impl ZoomCurve {
+    pub fn from_name(name: &str) -> Option<ZoomCurve> {
+        match name {
+            "linear"      => return Some(ZoomCurve::Linear),
+            "ease-in"     => return Some(ZoomCurve::EaseIn),
+            "ease-out"    => return Some(ZoomCurve::EaseOut),
+            "smooth-step" => return Some(ZoomCurve::SmoothStep),
+            _             => return None
+        }
+    }
+
+    fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            ZoomCurve::Linear     => return t,
+            ZoomCurve::EaseIn     => return t * t,
+            ZoomCurve::EaseOut    => return 1.0 - (1.0 - t) * (1.0 - t),
+            ZoomCurve::SmoothStep => return t * t * (3.0 - 2.0 * t)
+        }
+    }
+}
+
+// Picks a scene scale from the ship's height above the ground.  Below
+// near_altitude it's all the way in at near_scale for landing, above
+// far_altitude all the way out at far_scale.  In between it goes by
+// the curve, in how much of the world fits on screen rather than in
+// scale, so a linear curve widens the view in step with the climb.
+//
+// The altitude it works from only moves once the ship has moved more
+// than the hysteresis away from it, so bobbing about at one height
+// doesn't pump the zoom in and out.
+pub struct AltitudeZoom {
+    near_altitude: f32,
+    far_altitude:  f32,
+    near_scale:    f32,
+    far_scale:     f32,
+    curve:         ZoomCurve,
+    hysteresis:    f32,
+    altitude:      f32
+}
+
+impl AltitudeZoom {
+    pub fn new(near_altitude: f32, near_scale: f32,
+               far_altitude: f32, far_scale: f32) -> AltitudeZoom {
+        AltitudeZoom { near_altitude: near_altitude,
+                       far_altitude:  far_altitude.max(near_altitude + 0.001),
+                       near_scale:    near_scale,
+                       far_scale:     far_scale,
+                       curve:         ZoomCurve::Linear,
+                       hysteresis:    0.0,
+                       altitude:      0.0 }
+    }
+
+    pub fn set_curve(&mut self, curve: ZoomCurve) {
+        self.curve = curve;
+    }
+
+    // keep it under near_altitude or landing won't zoom all the way in
+    pub fn set_hysteresis(&mut self, hysteresis: f32) {
+        self.hysteresis = hysteresis.max(0.0);
+    }
+
+    pub fn scale(&mut self, altitude: f32) -> f32 {
+        self.altitude = self.altitude.max(altitude - self.hysteresis)
+                                     .min(altitude + self.hysteresis);
+        let t = (self.altitude - self.near_altitude) / (self.far_altitude - self.near_altitude);
+        let t = self.curve.apply(t);
+        let view = 1.0 / self.near_scale + (1.0 / self.far_scale - 1.0 / self.near_scale) * t;
+        return 1.0 / view;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        return (a - b).abs() < 1e-5;
+    }
+
+    #[test]
+    fn curves_run_from_nought_to_one() {
+        for curve in [ZoomCurve::Linear, ZoomCurve::EaseIn, ZoomCurve::EaseOut, ZoomCurve::SmoothStep].iter() {
+            assert!(close(curve.apply(0.0), 0.0));
+            assert!(close(curve.apply(1.0), 1.0));
+            assert!(close(curve.apply(-1.0), 0.0));
+            assert!(close(curve.apply(2.0), 1.0));
+        }
+        assert!(close(ZoomCurve::Linear.apply(0.25), 0.25));
+        assert!(ZoomCurve::EaseIn.apply(0.25) < 0.25);
+        assert!(ZoomCurve::EaseOut.apply(0.25) > 0.25);
+        assert!(close(ZoomCurve::SmoothStep.apply(0.5), 0.5));
+    }
+
+    #[test]
+    fn curve_names() {
+        assert_eq!(ZoomCurve::from_name("ease-out"), Some(ZoomCurve::EaseOut));
+        assert_eq!(ZoomCurve::from_name("smooth-step"), Some(ZoomCurve::SmoothStep));
+        assert_eq!(ZoomCurve::from_name("bouncy"), None);
+    }
+
+    #[test]
+    fn scale_between_the_altitudes() {
+        let mut zoom = AltitudeZoom::new(10.0, 1.0, 110.0, 0.25);
+        assert!(close(zoom.scale(0.0), 1.0));
+        assert!(close(zoom.scale(500.0), 0.25));
+        // halfway up shows halfway between 1 and 4 units of view
+        assert!(close(zoom.scale(60.0), 1.0 / 2.5));
+    }
+
+    #[test]
+    fn hysteresis_holds_the_zoom() {
+        let mut zoom = AltitudeZoom::new(0.0, 1.0, 100.0, 0.5);
+        zoom.set_hysteresis(5.0);
+        // starting from the ground, it's held at 45
+        let start = zoom.scale(50.0);
+        assert!(close(start, AltitudeZoom::new(0.0, 1.0, 100.0, 0.5).scale(45.0)));
+        // bobbing about within 5 of that changes nothing
+        assert!(close(zoom.scale(49.0), start));
+        assert!(close(zoom.scale(41.0), start));
+        // past it, it follows from the edge of the band
+        let climbed = zoom.scale(60.0);
+        assert!(climbed < start);
+        assert!(close(climbed, AltitudeZoom::new(0.0, 1.0, 100.0, 0.5).scale(55.0)));
+        assert!(close(zoom.scale(58.0), climbed));
+    }
+}